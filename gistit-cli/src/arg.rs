@@ -5,8 +5,6 @@ use clap::{crate_authors, crate_description, crate_version, Arg, ArgGroup, Comma
 #[allow(clippy::too_many_lines)]
 #[must_use]
 pub fn app() -> Command<'static> {
-    let random_name = Box::leak(Box::new(names::Generator::default().next().unwrap()));
-
     Command::new("gistit-cli")
         .version(crate_version!())
         .about(crate_description!())
@@ -17,15 +15,115 @@ pub fn app() -> Command<'static> {
         )
         .arg(
             Arg::new("FILE")
-                .help("File to send/upload.")
+                .help("Files or directories to send/upload.")
+                .long_help(
+                    "Files or directories to send/upload.
+Directories are walked recursively and relative paths are kept, so the tree can be rebuilt when fetching.",
+                )
                 .allow_invalid_utf8(true)
                 .takes_value(true)
-                .value_hint(ValueHint::FilePath)
+                .multiple_values(true)
+                .value_hint(ValueHint::AnyPath)
         )
         .arg(
             Arg::new("github")
                 .long("github")
-                .help("Post this gistit to GitHub Gists. Will be prompted to authorize with GitHub OAuth")
+                .help("Also post this gistit to GitHub Gists. Will be prompted to authorize with GitHub OAuth")
+        )
+        .arg(
+            Arg::new("encrypt")
+                .long("encrypt")
+                .short('e')
+                .help("Encrypt this gistit with a random key, shared alongside the hash")
+                .long_help(
+                    "Encrypt this gistit with a random key, shared alongside the hash.
+The resulting hash takes the form '[HASH]#[KEY]'. Neither the server nor peers in the network ever \
+see the key, so anyone without the full hash gets only ciphertext.",
+                )
+                .conflicts_with("github")
+        )
+        .arg(
+            Arg::new("password")
+                .long("password")
+                .short('p')
+                .help("Encrypt this gistit with a passphrase, prompted for on fetch")
+                .long_help(
+                    "Encrypt this gistit with a passphrase, prompted for on fetch.
+The key is derived with argon2id and only the salt is sent along. Reads the passphrase \
+from 'GISTIT_PASSWORD' if set, otherwise prompts for it.",
+                )
+                .conflicts_with_all(&["github", "encrypt"])
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .help("Encrypt this gistit so only the given peers can open it")
+                .long_help(
+                    "Encrypt this gistit so only the given peers can open it.
+Takes a comma separated list of peer ids, see `gistit node --status`. Recipients open it with \
+their local node key when fetching.",
+                )
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .value_name("peer-id")
+                .conflicts_with_all(&["github", "encrypt", "password"])
+        )
+        .arg(
+            Arg::new("expires")
+                .long("expires")
+                .help("Expire this gistit after a lifetime, e.g. '1h', '1d' or '7d'")
+                .long_help(
+                    "Expire this gistit after a lifetime, e.g. '1h', '1d' or '7d'.
+Accepts minutes, hours or days up to 30d. The server drops it and the local node stops \
+hosting it once expired, fetching refuses to show expired gistits.",
+                )
+                .takes_value(true)
+                .value_name("lifetime")
+        )
+        .arg(
+            Arg::new("once")
+                .long("once")
+                .help("Serve this gistit to a single peer, then stop hosting it. Requires a running node")
+                .conflicts_with("github")
+        )
+        .arg(
+            Arg::new("via")
+                .long("via")
+                .help("Where to send this gistit, all at once. Defaults to every available backend")
+                .long_help(
+                    "Where to send this gistit, all at once. Defaults to every available backend:
+'p2p' if your gistit node is running, 'server', and 'github' once you authorized it with '--github'.
+A 'gistit-backend-<name>' executable on your PATH adds a '<name>' backend.",
+                )
+                .takes_value(true)
+                .use_value_delimiter(true)
+                .value_name("backends")
+                .conflicts_with("github")
+        )
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .help("The server profile to send to, see 'gistit config'. Defaults to the 'server' setting")
+                .takes_value(true)
+                .value_name("name"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .help("Give up waiting on your gistit node after this long, e.g. '30s' or '2m'. Defaults to 2m")
+                .takes_value(true)
+                .value_name("duration")
+        )
+        .arg(
+            Arg::new("sign")
+                .long("sign")
+                .short('s')
+                .help("Sign this gistit so receivers can verify who sent it")
+                .long_help(
+                    "Sign this gistit so receivers can verify who sent it.
+Uses your gistit node key if one was set up, otherwise a signing key generated in the config directory.",
+                )
         )
         .arg(
             Arg::new("description")
@@ -38,11 +136,23 @@ pub fn app() -> Command<'static> {
             Arg::new("author")
                 .long("author")
                 .short('a')
-                .help("With author information. Defaults to a random generated name")
+                .help("With author information. Defaults to you, see 'gistit whoami'")
                 .takes_value(true)
-                .default_value(random_name)
                 .value_hint(ValueHint::Username),
         )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .short('l')
+                .help("The language of the files, by name or extension. Detected by default")
+                .long_help(
+                    "The language of the files, by name or extension, like 'rust' or 'rs'. Detected by default,
+from well-known file names like 'Dockerfile', the extension, the shebang line and vim or emacs modelines.
+Stdin is guessed at from its contents.",
+                )
+                .takes_value(true)
+                .value_name("lang"),
+        )
         .arg(
             Arg::new("clipboard")
                 .long("clipboard")
@@ -69,6 +179,10 @@ This is our best efforts at persisting the hash into the system clipboard after
                 .arg(
                     Arg::new("HASH")
                         .help("Fetch a gistit via it's hash")
+                        .long_help(
+                            "Fetch a gistit via it's hash.
+Encrypted gistits are fetched with the hash and key: '[HASH]#[KEY]'.",
+                        )
                         .takes_value(true)
                         .required(true),
                 )
@@ -78,8 +192,8 @@ This is our best efforts at persisting the hash into the system clipboard after
                         .help("Save the gistit to local fs after successfully fetching")
                         .long_help(
                             "Save the gistit to local fs after successfully fetching.
-Target directory defaults to 'XDG user directory' on Linux, 'Known Folder' system on Windows,
-and 'Standard Directories' on MacOS.",
+Files go to the 'saved' directory of the data directory, which defaults to 'XDG user directory'
+on Linux, 'Known Folder' system on Windows, and 'Standard Directories' on MacOS.",
                         ),
                 )
                 .arg(
@@ -92,6 +206,128 @@ and 'Standard Directories' on MacOS.",
 Run `gistit --colorschemes` to list available ones.",
                         ),
                 )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .help("Where to look for the gistit, in order. Defaults to 'cache,p2p,server,github'")
+                        .long_help(
+                            "Where to look for the gistit, in order. Defaults to 'cache,p2p,server,github'.
+'cache' is your local history, 'p2p' your running gistit node and 'github' the gists you posted with '--github'.
+'gistit-backend-<name>' plugins on your PATH are tried last, as '<name>'.
+The first source with contents matching the hash wins.",
+                        )
+                        .takes_value(true)
+                        .use_value_delimiter(true)
+                        .value_name("sources"),
+                )
+                .arg(
+                    Arg::new("server")
+                        .long("server")
+                        .help("The server profile to fetch from, see 'gistit config'. Defaults to the 'server' setting")
+                        .takes_value(true)
+                        .value_name("name"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Give up waiting on your gistit node after this long, e.g. '30s' or '2m'. Defaults to 2m")
+                        .takes_value(true)
+                        .value_name("duration"),
+                )
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Verify the signature of a saved gistit")
+                .arg(
+                    Arg::new("FILE")
+                        .help("A '.gistit' payload, saved by 'gistit fetch --save'")
+                        .allow_invalid_utf8(true)
+                        .takes_value(true)
+                        .required(true)
+                        .value_hint(ValueHint::FilePath),
+                )
+        )
+        .subcommand(
+            Command::new("log")
+                .alias("l")
+                .about("List sent and fetched gistits, newest first")
+                .long_about(
+                    "List sent and fetched gistits, newest first.
+Every successful send and fetch is recorded in the local data directory. Dates are shown in UTC.",
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("date")
+                        .help("Only entries since a date 'YYYY-MM-DD' or a lifetime ago like '12h' or '7d'"),
+                )
+                .arg(
+                    Arg::new("lang")
+                        .long("lang")
+                        .takes_value(true)
+                        .help("Only entries with files in this language"),
+                )
+                .arg(
+                    Arg::new("direction")
+                        .long("direction")
+                        .takes_value(true)
+                        .possible_values(["sent", "fetched"])
+                        .help("Only sent or fetched entries"),
+                )
+                .arg(
+                    Arg::new("show")
+                        .long("show")
+                        .takes_value(true)
+                        .value_name("n")
+                        .help("Preview the n-th entry again, without fetching it")
+                        .long_help(
+                            "Preview the n-th entry again, without fetching it.
+Entries are numbered as listed by `gistit log`, the newest being 1.",
+                        ),
+                )
+                .arg(
+                    Arg::new("colorscheme")
+                        .long("colorscheme")
+                        .takes_value(true)
+                        .help("The colorscheme to apply syntax highlighting"),
+                )
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Read and write settings")
+                .long_about(
+                    "Read and write settings, kept in 'config.toml' in the config directory.
+Command line flags win over settings. Every setting can also be set with an env var, like
+'GISTIT_DAEMON_PORT' for 'daemon.port', which wins over the file.",
+                )
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Print a setting")
+                        .arg(Arg::new("KEY").required(true)),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set a setting in the file. Lists are comma separated")
+                        .arg(Arg::new("KEY").required(true))
+                        .arg(Arg::new("VALUE").required(true)),
+                )
+                .subcommand(Command::new("list").about("Print every setting with a value and where it came from")),
+        )
+        .subcommand(
+            Command::new("whoami")
+                .about("Print or change the author of the gistits you send")
+                .long_about(
+                    "Print or change the author of the gistits you send, kept in the 'author' setting.
+It's picked on first run from 'git config user.name', or generated if git has none.",
+                )
+                .arg(
+                    Arg::new("NAME")
+                        .help("Send as this author from now on")
+                        .value_hint(ValueHint::Username),
+                ),
         )
         .subcommand(
             Command::new("node")
@@ -122,6 +358,37 @@ to peer file sharing.")
                         .help("Display the status of your gistit network node process")
                         // .conflicts_with_all(&["start", "stop"]),
                 )
+                .arg(
+                    Arg::new("hosting")
+                        .long("hosting")
+                        .group("daemon_cmd")
+                        .help("List the gistits hosted by your gistit node"),
+                )
+                .arg(
+                    Arg::new("unhost")
+                        .long("unhost")
+                        .group("daemon_cmd")
+                        .takes_value(true)
+                        .value_name("hash")
+                        .help("Stop hosting a gistit"),
+                )
+                .arg(
+                    Arg::new("republish")
+                        .long("republish")
+                        .group("daemon_cmd")
+                        .help("Announce every hosted gistit to the network again")
+                        .long_help(
+                            "Announce every hosted gistit to the network again.
+Provider records fall off the DHT after a while, republishing refreshes them right away.",
+                        ),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Give up waiting on your gistit node after this long, e.g. '30s' or '2m'. Defaults to 10s")
+                        .takes_value(true)
+                        .value_name("duration"),
+                )
                 .arg(
                     Arg::new("attach")
                         .long("attach")
@@ -143,7 +410,6 @@ to peer file sharing.")
                         .help("Local host address to listen for connection")
                         .takes_value(true)
                         .value_name("ipv4")
-                        .default_value("0.0.0.0")
                         .hide(true)
                         .conflicts_with_all(&["stop", "status"]),
                     )
@@ -153,7 +419,6 @@ to peer file sharing.")
                         .help("Local port to listen for connection")
                         .takes_value(true)
                         .value_name("port")
-                        .default_value("0")
                         .hide(true)
                         .conflicts_with_all(&["stop", "status"]),
                     )
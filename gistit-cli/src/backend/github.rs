@@ -0,0 +1,180 @@
+//! Posts gistits as public GitHub gists, once authorized with `--github`
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use gistit_proto::Gistit;
+
+use crate::backend::{Backend, Context, Published};
+use crate::github::{self, CreateResponse, Token, GITHUB_GISTS_API_URL};
+use crate::history;
+use crate::lang;
+use crate::{Error, Result};
+
+pub const NAME: &str = "github";
+
+pub struct Gists {
+    data_path: PathBuf,
+    token: Option<Token>,
+}
+
+#[derive(Deserialize)]
+struct GistResponse {
+    files: HashMap<String, GistFile>,
+}
+
+#[derive(Deserialize)]
+struct GistFile {
+    content: String,
+}
+
+impl Gists {
+    #[must_use]
+    pub fn new(context: &Context) -> Self {
+        Self {
+            data_path: context.data_path.clone(),
+            token: context.github_token.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for Gists {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    /// Only post gists once authorized
+    fn available(&self) -> bool {
+        self.token.is_some()
+    }
+
+    fn supports_encryption(&self) -> bool {
+        false
+    }
+
+    /// Gists only hold text
+    fn supports_binary(&self) -> bool {
+        false
+    }
+
+    async fn publish(&self, gistit: &Gistit) -> Result<Published> {
+        let token = self
+            .token
+            .as_ref()
+            .ok_or(Error::Server("not authorized with github"))?;
+
+        // Gists hold the text itself
+        let mut gistit = gistit.clone();
+        gistit.decompress()?;
+
+        let description = gistit.description.as_deref().unwrap_or("");
+        let files: serde_json::Map<String, serde_json::Value> = gistit
+            .inner
+            .iter()
+            .map(|inner| {
+                (
+                    github::gist_file_name(&inner.name),
+                    serde_json::json!({ "content": inner.data }),
+                )
+            })
+            .collect();
+
+        let response = reqwest::Client::new()
+            .post(GITHUB_GISTS_API_URL)
+            .header("user-agent", "gistit")
+            .header("authorization", format!("token {}", token.access_token))
+            .header("accept", "application/vnd.github.v3+json")
+            .json(&serde_json::json!({
+                "description": description,
+                "public": true,
+                "files": files
+            }))
+            .send()
+            .await?;
+
+        let gist: CreateResponse = match response.status() {
+            StatusCode::CREATED => response.json().await?,
+            StatusCode::FORBIDDEN | StatusCode::UNPROCESSABLE_ENTITY => {
+                return Err(Error::Server(
+                    "your github token is expired, nothing was posted",
+                ))
+            }
+            _ => {
+                return Err(Error::Server(
+                    "got a invalid response from github, nothing was posted",
+                ))
+            }
+        };
+
+        Ok(Published {
+            hash: None,
+            url: Some(gist.html_url),
+            origin: history::Backend::Github(gist.url),
+        })
+    }
+
+    /// Rebuilds the gistit from the gist it was posted as. Gists only keep the files, so the
+    /// rest comes from the history entry of the send
+    async fn retrieve(&self, hash: &str) -> Result<Option<(Gistit, history::Backend)>> {
+        let posted =
+            history::read(&self.data_path)?
+                .into_iter()
+                .rev()
+                .find_map(|entry| match entry.backend.clone() {
+                    history::Backend::Github(url) if entry.hash == hash => Some((entry, url)),
+                    _ => None,
+                });
+
+        let (entry, url) = match posted {
+            Some(posted) => posted,
+            None => return Ok(None),
+        };
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("user-agent", "gistit")
+            .header("accept", "application/vnd.github.v3+json")
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Ok(None),
+            _ => return Err(Error::Server("unexpected response from github")),
+        }
+
+        let mut gist: GistResponse = response.json().await?;
+        let inner = entry
+            .files
+            .iter()
+            .map(|name| {
+                let data = gist
+                    .files
+                    .remove(&github::gist_file_name(name))
+                    .ok_or(Error::Server("gist is missing files"))?
+                    .content;
+
+                #[allow(clippy::cast_possible_truncation)]
+                Ok(Gistit::new_inner(
+                    name.clone(),
+                    lang::detect(name, &data).to_owned(),
+                    data.len() as u32,
+                    data,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let gistit = Gistit::new(
+            hash.to_owned(),
+            entry.author,
+            entry.description,
+            entry.timestamp.to_string(),
+            inner,
+        );
+        Ok(Some((gistit, history::Backend::Github(url))))
+    }
+}
@@ -0,0 +1,232 @@
+//! The backend module
+//!
+//! Everywhere a gistit can be sent to and fetched from implements [`Backend`] and is listed in
+//! [`registry`], along with the `gistit-backend-<name>` plugins found on `PATH`. `gistit send`
+//! publishes to the backends picked with `--via` and `gistit fetch` retrieves from the ones
+//! picked with `--from`, both only ever go through the trait.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use gistit_project::var;
+use gistit_proto::Gistit;
+
+use crate::github::Token;
+use crate::history;
+use crate::server::Profile;
+use crate::Result;
+
+pub mod github;
+pub mod p2p;
+pub mod plugin;
+pub mod server;
+
+/// Names of the builtin backends
+pub const NAMES: [&str; 3] = [p2p::NAME, server::NAME, github::NAME];
+
+/// Names of every backend, as given to `--via` and `--from`
+#[must_use]
+pub fn names() -> Vec<&'static str> {
+    NAMES.into_iter().chain(plugin::names()).collect()
+}
+
+/// Every backend, in the order they're tried when fetching
+#[must_use]
+pub fn registry(context: &Context) -> Vec<Box<dyn Backend>> {
+    let mut backends: Vec<Box<dyn Backend>> = vec![
+        Box::new(p2p::Node::new(context)),
+        Box::new(server::Server::new(context)),
+        Box::new(github::Gists::new(context)),
+    ];
+    for plugin in plugin::all(context) {
+        backends.push(Box::new(plugin));
+    }
+    backends
+}
+
+/// What backends may need to know about the running command
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub runtime_path: PathBuf,
+    pub data_path: PathBuf,
+    /// How long to wait on the node and plugins
+    pub timeout: Duration,
+    /// Serve the gistit to a single peer only
+    pub once: bool,
+    pub github_token: Option<Token>,
+    /// The server profile picked with `--server`
+    pub server: Profile,
+    /// Largest gistit the node sends and fetches, see `daemon.max_size`
+    pub node_max_size: u64,
+}
+
+/// Where a gistit ended up once published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Published {
+    /// `None` if the backend doesn't address gistits by their hash
+    pub hash: Option<String>,
+    pub url: Option<String>,
+    /// What the history records to find the gistit again
+    pub origin: history::Backend,
+}
+
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Identifies the backend in `--via`, `--from` and the output
+    fn name(&self) -> &'static str;
+
+    /// Asks the backend what it supports, before any of the methods below are called. Builtin
+    /// backends already know
+    async fn probe(&self) {}
+
+    /// Whether `gistit send` publishes here when `--via` isn't given
+    fn available(&self) -> bool;
+
+    fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    /// Whether it can carry files that aren't UTF-8 text
+    fn supports_binary(&self) -> bool {
+        true
+    }
+
+    /// Whether it can stop serving a gistit after the first fetch, see `--once`
+    fn supports_once(&self) -> bool {
+        false
+    }
+
+    /// Largest gistit it takes, in bytes of file contents
+    fn max_size(&self) -> u64 {
+        var::GISTIT_MAX_SIZE as u64
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the gistit couldn't be published
+    async fn publish(&self, gistit: &Gistit) -> Result<Published>;
+
+    /// Looks for a gistit by its hash, `None` if this backend doesn't have it
+    ///
+    /// # Errors
+    ///
+    /// Fails if the backend can't be reached
+    async fn retrieve(&self, hash: &str) -> Result<Option<(Gistit, history::Backend)>>;
+}
+
+/// The backends with these names, in the order of `names`
+#[must_use]
+pub fn select<'a>(backends: &'a [Box<dyn Backend>], names: &[&str]) -> Vec<&'a dyn Backend> {
+    names
+        .iter()
+        .filter_map(|name| backends.iter().find(|backend| backend.name() == *name))
+        .map(AsRef::as_ref)
+        .collect()
+}
+
+/// Probes every backend at once, see [`Backend::probe`]
+pub async fn probe_all(backends: &[&dyn Backend]) {
+    futures::future::join_all(backends.iter().map(|backend| backend.probe())).await;
+}
+
+/// Publishes to every backend at once, one result per backend in the same order
+pub async fn publish_all(backends: &[&dyn Backend], gistit: &Gistit) -> Vec<Result<Published>> {
+    futures::future::join_all(backends.iter().map(|backend| backend.publish(gistit))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use gistit_project::settings::Settings;
+
+    use super::*;
+    use crate::Error;
+
+    struct Mock {
+        name: &'static str,
+        fails: bool,
+    }
+
+    #[async_trait]
+    impl Backend for Mock {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn available(&self) -> bool {
+            true
+        }
+
+        async fn publish(&self, gistit: &Gistit) -> Result<Published> {
+            if self.fails {
+                return Err(Error::Server("mock failure"));
+            }
+            Ok(Published {
+                hash: Some(gistit.hash.clone()),
+                url: None,
+                origin: history::Backend::Server,
+            })
+        }
+
+        async fn retrieve(&self, _hash: &str) -> Result<Option<(Gistit, history::Backend)>> {
+            Ok(None)
+        }
+    }
+
+    fn mocks() -> Vec<Box<dyn Backend>> {
+        vec![
+            Box::new(Mock {
+                name: "a",
+                fails: false,
+            }),
+            Box::new(Mock {
+                name: "b",
+                fails: true,
+            }),
+        ]
+    }
+
+    #[test]
+    fn backend_select_keeps_given_order() {
+        let backends = mocks();
+        let selected = select(&backends, &["b", "c", "a"]);
+        assert_eq!(
+            selected.iter().map(|b| b.name()).collect::<Vec<_>>(),
+            vec!["b", "a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn backend_publish_all_keeps_going_on_failure() {
+        let backends = mocks();
+        let gistit = Gistit {
+            hash: "foo".to_owned(),
+            ..Gistit::default()
+        };
+
+        let results = publish_all(&select(&backends, &["b", "a"]), &gistit).await;
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap().hash.as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn backend_registry_matches_names() {
+        let context = Context {
+            runtime_path: PathBuf::new(),
+            data_path: PathBuf::new(),
+            timeout: Duration::from_secs(1),
+            once: false,
+            github_token: None,
+            server: Profile::from_settings(
+                &Settings::load(Path::new("settings-that-dont-exist")).unwrap(),
+                None,
+            )
+            .unwrap(),
+            node_max_size: var::GISTIT_NODE_MAX_SIZE as u64,
+        };
+        let names: Vec<_> = registry(&context).iter().map(|b| b.name()).collect();
+        assert_eq!(names, super::names());
+    }
+}
@@ -0,0 +1,119 @@
+//! Hosts gistits on the local `gistit-daemon`, reached over ipc
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use gistit_ipc::{Bridge, Client};
+use gistit_project::var;
+use gistit_proto::{ipc, Gistit, Instruction};
+
+use crate::backend::{Backend, Context, Published};
+use crate::history;
+use crate::node;
+use crate::{Error, Result};
+
+pub const NAME: &str = "p2p";
+
+pub struct Node {
+    runtime_path: PathBuf,
+    timeout: Duration,
+    once: bool,
+    max_size: u64,
+}
+
+impl Node {
+    #[must_use]
+    pub fn new(context: &Context) -> Self {
+        Self {
+            runtime_path: context.runtime_path.clone(),
+            timeout: context.timeout,
+            once: context.once,
+            max_size: context.node_max_size,
+        }
+    }
+
+    /// A client allowed frames as big as the gistits the node takes
+    fn bridge(&self) -> Result<Bridge<Client>> {
+        Ok(
+            gistit_ipc::client(&self.runtime_path)?.max_frame_size(var::max_payload_size(
+                usize::try_from(self.max_size).unwrap_or(usize::MAX),
+            )),
+        )
+    }
+}
+
+#[async_trait]
+impl Backend for Node {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn available(&self) -> bool {
+        gistit_ipc::client(&self.runtime_path).map_or(false, |mut bridge| bridge.alive())
+    }
+
+    fn supports_once(&self) -> bool {
+        true
+    }
+
+    /// As set for the node, see `daemon.max_size`
+    fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Asks the local node to host the gistit
+    async fn publish(&self, gistit: &Gistit) -> Result<Published> {
+        let mut bridge = self.bridge()?;
+        if !bridge.alive() {
+            return Err(Error::Node("gistit node is not running"));
+        }
+
+        let response = node::request(
+            &mut bridge,
+            Instruction::request_provide(gistit.clone(), self.once),
+            "hosting",
+            self.timeout,
+        )
+        .await?;
+
+        if let ipc::instruction::Kind::ProvideResponse(ipc::instruction::ProvideResponse {
+            hash: Some(hash),
+        }) = response.expect_response()?
+        {
+            Ok(Published {
+                hash: Some(hash),
+                url: None,
+                origin: history::Backend::P2p,
+            })
+        } else {
+            Err(Error::Node(
+                "failed to provide gistit, check gistit-daemon logs",
+            ))
+        }
+    }
+
+    /// Asks the local node to find the gistit in the DHT. Skipped if no node is running
+    async fn retrieve(&self, hash: &str) -> Result<Option<(Gistit, history::Backend)>> {
+        let mut bridge = self.bridge()?;
+        if !bridge.alive() {
+            return Ok(None);
+        }
+
+        let response = node::request(
+            &mut bridge,
+            Instruction::request_fetch(hash.to_owned()),
+            "looking for the gistit in the DHT",
+            self.timeout,
+        )
+        .await?;
+
+        if let ipc::instruction::Kind::FetchResponse(ipc::instruction::FetchResponse { gistit }) =
+            response.expect_response()?
+        {
+            Ok(gistit.map(|gistit| (gistit, history::Backend::P2p)))
+        } else {
+            Ok(None)
+        }
+    }
+}
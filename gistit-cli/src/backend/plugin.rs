@@ -0,0 +1,216 @@
+//! Backends provided by `gistit-backend-<name>` executables found on `PATH`
+//!
+//! Every request spawns the plugin, writes a length delimited [`Request`] to its stdin and reads
+//! a single length delimited [`Response`] back from its stdout. Capabilities are asked for once,
+//! when the plugin is probed.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::AsyncWriteExt;
+
+use gistit_project::var;
+use gistit_proto::plugin::{response, Request, Response};
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::backend::{Backend, Context, Published, NAMES};
+use crate::history;
+use crate::{Error, Result};
+
+/// Plugin executables are named this followed by the backend name
+const PLUGIN_PREFIX: &str = "gistit-backend-";
+
+/// How long a plugin has to tell its capabilities, every plugin is probed before sending
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    static ref DISCOVERED: Vec<(String, PathBuf)> = discover();
+}
+
+/// Names of the plugins found on `PATH`
+pub fn names() -> impl Iterator<Item = &'static str> {
+    DISCOVERED.iter().map(|(name, _)| name.as_str())
+}
+
+/// Every plugin found on `PATH`
+pub fn all(context: &Context) -> impl Iterator<Item = Plugin> + '_ {
+    DISCOVERED.iter().map(|(name, path)| Plugin {
+        name,
+        path,
+        timeout: context.timeout,
+        once: context.once,
+        capabilities: Mutex::new(response::Capabilities::default()),
+    })
+}
+
+/// Looks for plugins in `PATH` order. Builtin backends can't be overridden
+fn discover() -> Vec<(String, PathBuf)> {
+    let mut found: Vec<(String, PathBuf)> = Vec::new();
+    let paths = env::var_os("PATH").unwrap_or_default();
+
+    for dir in env::split_paths(&paths) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let name = match file_name
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(PLUGIN_PREFIX))
+                .map(|name| name.strip_suffix(env::consts::EXE_SUFFIX).unwrap_or(name))
+            {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+
+            if NAMES.contains(&name) || found.iter().any(|(other, _)| other == name) {
+                continue;
+            }
+            // The first executable one in `PATH` order wins
+            if let Ok(path) = which::which(&file_name) {
+                found.push((name.to_owned(), path));
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+pub struct Plugin {
+    name: &'static str,
+    path: &'static Path,
+    timeout: Duration,
+    once: bool,
+    /// Nothing until probed
+    capabilities: Mutex<response::Capabilities>,
+}
+
+impl Plugin {
+    fn capabilities(&self) -> response::Capabilities {
+        self.capabilities.lock().expect("poisoned lock").clone()
+    }
+
+    /// Gives up after `timeout`, killing the plugin
+    async fn call(&self, request: Request, timeout: Duration) -> Result<Response> {
+        let call = async {
+            let mut process = tokio::process::Command::new(self.path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()?;
+
+            let mut stdin = process.stdin.take().expect("to access stdin");
+            stdin
+                .write_all(&request.encode_length_delimited_to_vec())
+                .await?;
+            drop(stdin);
+
+            decode(&process.wait_with_output().await?)
+        };
+
+        let response = tokio::time::timeout(timeout, call)
+            .await
+            .map_err(|_| Error::Timeout("waiting on the backend plugin"))??;
+
+        match response.kind {
+            Some(response::Kind::Failed(response::Failed { reason })) => Err(Error::Plugin(reason)),
+            _ => Ok(response),
+        }
+    }
+}
+
+fn decode(output: &std::process::Output) -> Result<Response> {
+    if output.stdout.is_empty() {
+        return Err(Error::Plugin(format!(
+            "backend plugin exited without responding, {}",
+            output.status
+        )));
+    }
+
+    Response::decode_length_delimited(&*output.stdout)
+        .map_err(|err| gistit_proto::Error::from(err).into())
+}
+
+#[async_trait]
+impl Backend for Plugin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Plugins that don't answer within [`PROBE_TIMEOUT`] support nothing and are never sent to
+    /// by default
+    async fn probe(&self) {
+        let capabilities = match self.call(Request::capabilities(), PROBE_TIMEOUT).await {
+            Ok(Response {
+                kind: Some(response::Kind::Capabilities(capabilities)),
+            }) => capabilities,
+            _ => response::Capabilities::default(),
+        };
+        *self.capabilities.lock().expect("poisoned lock") = capabilities;
+    }
+
+    fn available(&self) -> bool {
+        self.capabilities().available
+    }
+
+    fn supports_encryption(&self) -> bool {
+        self.capabilities().encryption
+    }
+
+    fn supports_binary(&self) -> bool {
+        self.capabilities().binary
+    }
+
+    fn supports_once(&self) -> bool {
+        self.capabilities().once
+    }
+
+    fn max_size(&self) -> u64 {
+        match self.capabilities().max_size {
+            0 => var::GISTIT_MAX_SIZE as u64,
+            max_size => max_size,
+        }
+    }
+
+    async fn publish(&self, gistit: &Gistit) -> Result<Published> {
+        match self
+            .call(Request::publish(gistit.clone(), self.once), self.timeout)
+            .await?
+            .kind
+        {
+            Some(response::Kind::Published(response::Published { hash, url })) => Ok(Published {
+                hash,
+                url,
+                origin: history::Backend::Plugin(self.name.to_owned()),
+            }),
+            _ => Err(Error::Plugin(
+                "unexpected response from backend plugin".to_owned(),
+            )),
+        }
+    }
+
+    async fn retrieve(&self, hash: &str) -> Result<Option<(Gistit, history::Backend)>> {
+        match self
+            .call(Request::retrieve(hash.to_owned()), self.timeout)
+            .await?
+            .kind
+        {
+            Some(response::Kind::Retrieved(response::Retrieved { gistit })) => {
+                Ok(gistit.map(|gistit| (gistit, history::Backend::Plugin(self.name.to_owned()))))
+            }
+            _ => Err(Error::Plugin(
+                "unexpected response from backend plugin".to_owned(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Stores gistits on the gistit server of the profile picked with `--server`
+use async_trait::async_trait;
+use reqwest::StatusCode;
+
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::backend::{Backend, Context, Published};
+use crate::history;
+use crate::server::Profile;
+use crate::{Error, Result};
+
+pub const NAME: &str = "server";
+
+pub struct Server {
+    profile: Profile,
+}
+
+impl Server {
+    #[must_use]
+    pub fn new(context: &Context) -> Self {
+        Self {
+            profile: context.server.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for Server {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    /// As set for the server profile, see `servers.<name>.max_size`
+    fn max_size(&self) -> u64 {
+        self.profile.max_size
+    }
+
+    /// Uploads the gistit. The web app can't decrypt, so encrypted gistits get no url
+    async fn publish(&self, gistit: &Gistit) -> Result<Published> {
+        let response = reqwest::Client::new()
+            .post(self.profile.load_url())
+            .header("content-type", "application/x-protobuf")
+            .body(gistit.encode_to_vec())
+            .send()
+            .await?;
+
+        let hash = match response.status() {
+            StatusCode::OK => Gistit::from_bytes(response.bytes().await?)?.hash,
+            StatusCode::UNPROCESSABLE_ENTITY | StatusCode::BAD_REQUEST => {
+                return Err(Error::Server("invalid gistit payload"))
+            }
+            _ => return Err(Error::Server("invalid server response")),
+        };
+
+        let url = if gistit.is_encrypted() {
+            None
+        } else {
+            self.profile.share_url(&hash)
+        };
+        Ok(Published {
+            hash: Some(hash),
+            url,
+            origin: history::Backend::Server,
+        })
+    }
+
+    async fn retrieve(&self, hash: &str) -> Result<Option<(Gistit, history::Backend)>> {
+        let request = Gistit {
+            hash: hash.to_owned(),
+            ..Gistit::default()
+        };
+
+        let response = reqwest::Client::new()
+            .post(self.profile.get_url())
+            .header("content-type", "application/x-protobuf")
+            .body(request.encode_to_vec())
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Some((
+                Gistit::from_bytes(response.bytes().await?)?,
+                history::Backend::Server,
+            ))),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(Error::Server("unexpected response")),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! `gistit config`, reads and writes the settings shared by the cli and the daemon
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+use gistit_project::settings::{self, Settings};
+
+use crate::dispatch::Dispatch;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub command: &'static str,
+    pub key: Option<&'static str>,
+    pub value: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        let (command, args) = args
+            .subcommand()
+            .ok_or(Error::Argument("missing subcommand", "get|set|list"))?;
+
+        let (key, value) = match command {
+            "get" => (args.value_of("KEY"), None),
+            "set" => (args.value_of("KEY"), args.value_of("VALUE")),
+            _ => (None, None),
+        };
+
+        Ok(Box::new(Self {
+            command,
+            key,
+            value,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SettingsCommand {
+    Get(&'static str),
+    Set(&'static str, &'static str),
+    List,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    command: SettingsCommand,
+    settings: Settings,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        let command = match (self.command, self.key, self.value) {
+            ("get", Some(key), _) => SettingsCommand::Get(key),
+            ("set", Some(key), Some(value)) => SettingsCommand::Set(key, value),
+            ("list", _, _) => SettingsCommand::List,
+            _ => return Err(Error::Argument("missing argument", "[KEY] [VALUE]")),
+        };
+        if let SettingsCommand::Get(key) | SettingsCommand::Set(key, _) = command {
+            settings::key(key)?;
+        }
+
+        Ok(Config {
+            command,
+            settings: Settings::load(&path::config()?)?,
+        })
+    }
+
+    async fn dispatch(&self, mut config: Self::InnerData) -> Result<()> {
+        match config.command {
+            SettingsCommand::Get(key) => match config.settings.get(key)? {
+                Some((value, _)) => println!("{}", value),
+                // Like `git config`, unset keys print nothing
+                None => std::process::exit(1),
+            },
+            SettingsCommand::Set(key, value) => {
+                config.settings.set(key, value)?;
+                config.settings.save()?;
+            }
+            SettingsCommand::List => {
+                for (key, value, origin) in config.settings.all() {
+                    println!("{} = {}  {}", key, value, style(origin).dim());
+                }
+            }
+        }
+        Ok(())
+    }
+}
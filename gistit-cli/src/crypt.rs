@@ -0,0 +1,670 @@
+//! The encryption module
+//!
+//! Inner file contents are encrypted with `XChaCha20Poly1305` before hashing, so neither the
+//! server nor the DHT ever see plaintext. Ciphertext is base64 encoded into [`Inner::data`] with
+//! its random nonce prepended.
+//!
+//! The key is either random and shared next to the hash, derived from a passphrase with
+//! argon2id, in which case the salt travels in the payload, or wrapped for a set of recipient
+//! peers. Recipients' ed25519 node keys are recovered from their peer ids and converted to
+//! X25519 to agree on a wrapping key with an ephemeral key pair.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use console::Term;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p_core::identity::{self, ed25519};
+use libp2p_core::PeerId;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+use gistit_project::env;
+
+use gistit_proto::payload::gistit::encryption::{Recipient, Scheme};
+use gistit_proto::payload::gistit::Encryption;
+use gistit_proto::{Gistit, Inner};
+
+use crate::fmt::PROGRESS;
+use crate::{Error, Result};
+
+pub const KEY_LENGTH: usize = 32;
+
+const NONCE_LENGTH: usize = 24;
+
+const SALT_LENGTH: usize = 16;
+
+/// Multihash code of peer ids that embed their public key
+const IDENTITY_MULTIHASH_CODE: u64 = 0x00;
+
+/// Domain separation for recipient wrapping keys
+const WRAP_CONTEXT: &[u8] = b"gistit-recipient-wrap";
+
+/// Separates the hash from the key in a share token
+const TOKEN_SEPARATOR: char = '#';
+
+/// A symmetric key used to encrypt inner file contents
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretKey([u8; KEY_LENGTH]);
+
+impl SecretKey {
+    #[must_use]
+    pub fn generate() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+
+    /// Derives a key from a passphrase with argon2id
+    ///
+    /// # Errors
+    ///
+    /// Fails if the salt is too short
+    pub fn derive(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = [0_u8; KEY_LENGTH];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|_| Error::Encryption("failed to derive key from passphrase"))?;
+
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&Key::from(self.0))
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+impl fmt::Display for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&base64::encode_config(self.0, base64::URL_SAFE_NO_PAD))
+    }
+}
+
+impl FromStr for SecretKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = base64::decode_config(s, base64::URL_SAFE_NO_PAD)?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| Error::Argument("invalid key length", "[HASH]#[KEY]"))?;
+
+        Ok(Self(key))
+    }
+}
+
+/// The secret an encrypted gistit is sealed with
+#[derive(Debug, Clone)]
+pub enum Secret {
+    /// A random key, shared next to the hash
+    Key(SecretKey),
+    /// A key derived from a passphrase and salt
+    Passphrase { key: SecretKey, salt: Vec<u8> },
+    /// A random key wrapped for each recipient peer
+    Recipients {
+        key: SecretKey,
+        ephemeral_key: Vec<u8>,
+        recipients: Vec<Recipient>,
+    },
+}
+
+impl Secret {
+    /// Derives a key from `passphrase` with a fresh random salt
+    ///
+    /// # Errors
+    ///
+    /// Fails if the key can't be derived
+    pub fn from_passphrase(passphrase: &str) -> Result<Self> {
+        let salt: [u8; SALT_LENGTH] = rand::thread_rng().gen();
+
+        Ok(Self::Passphrase {
+            key: SecretKey::derive(passphrase, &salt)?,
+            salt: salt.to_vec(),
+        })
+    }
+
+    /// Generates a content key and wraps it for each of `peers`
+    ///
+    /// # Errors
+    ///
+    /// Fails if a peer id doesn't embed an ed25519 public key
+    pub fn for_recipients(peers: &[PeerId]) -> Result<Self> {
+        let key = SecretKey::generate();
+        let ephemeral = StaticSecret::from(rand::thread_rng().gen::<[u8; 32]>());
+        let ephemeral_public = X25519PublicKey::from(&ephemeral);
+
+        let recipients = peers
+            .iter()
+            .map(|peer_id| {
+                let recipient_public = x25519_public_key(peer_id)?;
+                let shared = ephemeral.diffie_hellman(&recipient_public);
+                let wrapping_key =
+                    wrapping_key(shared.as_bytes(), &ephemeral_public, &recipient_public);
+
+                let nonce: [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+                let ciphertext = wrapping_key
+                    .cipher()
+                    .encrypt(&XNonce::from(nonce), &key.0[..])
+                    .map_err(|_| Error::Encryption("failed to wrap key for recipient"))?;
+
+                let mut wrapped_key = nonce.to_vec();
+                wrapped_key.extend(ciphertext);
+
+                Ok(Recipient {
+                    peer_id: peer_id.to_base58(),
+                    wrapped_key,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::Recipients {
+            key,
+            ephemeral_key: ephemeral_public.as_bytes().to_vec(),
+            recipients,
+        })
+    }
+
+    #[must_use]
+    pub const fn key(&self) -> &SecretKey {
+        match self {
+            Self::Key(key) | Self::Passphrase { key, .. } | Self::Recipients { key, .. } => key,
+        }
+    }
+
+    #[must_use]
+    pub fn encryption(&self) -> Encryption {
+        match self {
+            Self::Key(_) => Encryption {
+                scheme: Scheme::Key as i32,
+                ..Encryption::default()
+            },
+            Self::Passphrase { salt, .. } => Encryption {
+                scheme: Scheme::Passphrase as i32,
+                salt: salt.clone(),
+                ..Encryption::default()
+            },
+            Self::Recipients {
+                ephemeral_key,
+                recipients,
+                ..
+            } => Encryption {
+                scheme: Scheme::Recipients as i32,
+                ephemeral_key: ephemeral_key.clone(),
+                recipients: recipients.clone(),
+                ..Encryption::default()
+            },
+        }
+    }
+}
+
+/// Unwraps the content key of a gistit encrypted for recipient peers with the local node key
+///
+/// # Errors
+///
+/// Fails if the local node isn't a recipient or the wrapped key can't be opened
+pub fn unwrap_key(encryption: &Encryption, keypair: &ed25519::Keypair) -> Result<SecretKey> {
+    let local_peer_id = PeerId::from(identity::PublicKey::Ed25519(keypair.public())).to_base58();
+    let recipient = encryption
+        .recipients
+        .iter()
+        .find(|recipient| recipient.peer_id == local_peer_id)
+        .ok_or(Error::Encryption(
+            "this gistit wasn't encrypted for your node",
+        ))?;
+
+    let ephemeral_public: [u8; 32] = encryption
+        .ephemeral_key
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::Encryption("encrypted gistit is corrupted"))?;
+    let ephemeral_public = X25519PublicKey::from(ephemeral_public);
+
+    let local = x25519_secret(keypair);
+    let local_public = X25519PublicKey::from(&local);
+    let shared = local.diffie_hellman(&ephemeral_public);
+    let wrapping_key = wrapping_key(shared.as_bytes(), &ephemeral_public, &local_public);
+
+    if recipient.wrapped_key.len() < NONCE_LENGTH {
+        return Err(Error::Encryption("encrypted gistit is corrupted"));
+    }
+    let (nonce, ciphertext) = recipient.wrapped_key.split_at(NONCE_LENGTH);
+    let nonce: [u8; NONCE_LENGTH] = nonce
+        .try_into()
+        .map_err(|_| Error::Encryption("encrypted gistit is corrupted"))?;
+
+    let key = wrapping_key
+        .cipher()
+        .decrypt(&XNonce::from(nonce), ciphertext)
+        .map_err(|_| Error::Encryption("failed to unwrap key, was your node key rotated?"))?;
+
+    Ok(SecretKey(key.try_into().map_err(|_| {
+        Error::Encryption("encrypted gistit is corrupted")
+    })?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeKey {
+    identity: Identity,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Identity {
+    priv_key: String,
+}
+
+/// Reads the local node key written by `gistit-daemon` to `node-config`
+///
+/// # Errors
+///
+/// Fails if the node was never started or the key isn't ed25519
+pub fn node_keypair(config_path: &Path) -> Result<ed25519::Keypair> {
+    let node_config = config_path.join("node-config");
+    let contents = fs::read_to_string(&node_config)
+        .map_err(|_| Error::Encryption("missing node key, run `gistit node --start` once"))?;
+    let node_key: NodeKey = serde_json::from_str(&contents)?;
+
+    match identity::Keypair::from_protobuf_encoding(&base64::decode(node_key.identity.priv_key)?) {
+        Ok(identity::Keypair::Ed25519(keypair)) => Ok(keypair),
+        _ => Err(Error::Encryption("node key is not a valid ed25519 key")),
+    }
+}
+
+/// Options to open files holding keys with, readable and writable by the owner only
+#[must_use]
+pub fn private_file() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// Recovers a peer's ed25519 public key from its id and converts it to X25519
+fn x25519_public_key(peer_id: &PeerId) -> Result<X25519PublicKey> {
+    let multihash = peer_id.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH_CODE {
+        return Err(Error::Argument(
+            "peer id doesn't embed a public key",
+            "--to",
+        ));
+    }
+
+    let public = match identity::PublicKey::from_protobuf_encoding(multihash.digest()) {
+        Ok(identity::PublicKey::Ed25519(public)) => public,
+        _ => return Err(Error::Argument("peer id isn't an ed25519 key", "--to")),
+    };
+
+    let montgomery = CompressedEdwardsY(public.encode())
+        .decompress()
+        .ok_or(Error::Argument("invalid peer public key", "--to"))?
+        .to_montgomery();
+
+    Ok(X25519PublicKey::from(montgomery.to_bytes()))
+}
+
+/// Converts an ed25519 secret key to X25519 the same way the public key is
+fn x25519_secret(keypair: &ed25519::Keypair) -> StaticSecret {
+    let hash = Sha512::digest(keypair.secret().as_ref());
+    let mut scalar = [0_u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+
+    // Clamped on construction
+    StaticSecret::from(scalar)
+}
+
+fn wrapping_key(
+    shared: &[u8; 32],
+    ephemeral_public: &X25519PublicKey,
+    recipient_public: &X25519PublicKey,
+) -> SecretKey {
+    let mut hasher = Sha256::new();
+    hasher.update(WRAP_CONTEXT);
+    hasher.update(shared);
+    hasher.update(ephemeral_public.as_bytes());
+    hasher.update(recipient_public.as_bytes());
+
+    SecretKey(hasher.finalize().into())
+}
+
+/// Reads a passphrase from `GISTIT_PASSWORD` or prompts for it
+///
+/// # Errors
+///
+/// Fails if the terminal can't be read or confirmation doesn't match
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(env::GISTIT_PASSWORD_VAR) {
+        return Ok(passphrase);
+    }
+
+    // Keep the spinner from drawing over the prompt
+    PROGRESS.disable_steady_tick();
+    let term = Term::stderr();
+    let prompt = |msg: &str| -> Result<String> {
+        term.clear_line()?;
+        term.write_str(msg)?;
+        Ok(term.read_secure_line()?)
+    };
+
+    let passphrase = prompt("Passphrase: ")?;
+    let confirmed = !confirm || prompt("Confirm passphrase: ")? == passphrase;
+    PROGRESS.enable_steady_tick(100);
+
+    if passphrase.is_empty() {
+        Err(Error::Argument("empty passphrase", "--password"))
+    } else if confirmed {
+        Ok(passphrase)
+    } else {
+        Err(Error::Argument("passphrases don't match", "--password"))
+    }
+}
+
+/// Builds the token to share an encrypted gistit: `hash#key`
+#[must_use]
+pub fn share_token(hash: &str, key: &SecretKey) -> String {
+    format!("{}{}{}", hash, TOKEN_SEPARATOR, key)
+}
+
+/// Splits a share token into its hash and optional key
+///
+/// # Errors
+///
+/// Fails if the key part can't be decoded
+pub fn parse_token(token: &str) -> Result<(&str, Option<SecretKey>)> {
+    match token.split_once(TOKEN_SEPARATOR) {
+        Some((hash, key)) => Ok((hash, Some(key.parse()?))),
+        None => Ok((token, None)),
+    }
+}
+
+/// Encrypts every inner file in place, binary contents are moved out of `raw` into `data` too
+///
+/// # Errors
+///
+/// Fails if the underlying cipher fails
+pub fn encrypt_inner(inner: &mut [Inner], secret: &Secret) -> Result<Encryption> {
+    let cipher = secret.key().cipher();
+
+    for file in inner {
+        let nonce: [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+        let ciphertext = cipher
+            .encrypt(&XNonce::from(nonce), file.contents())
+            .map_err(|_| Error::Encryption("failed to encrypt gistit"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        file.data = base64::encode(sealed);
+        file.raw.clear();
+    }
+
+    Ok(secret.encryption())
+}
+
+/// Decrypts every inner file in place, clearing the encryption marker
+///
+/// # Errors
+///
+/// Fails if the key doesn't match or the payload is corrupted
+pub fn decrypt(gistit: &mut Gistit, key: &SecretKey) -> Result<()> {
+    let cipher = key.cipher();
+    let with_passphrase = scheme(gistit)? == Some(Scheme::Passphrase);
+
+    for file in &mut gistit.inner {
+        let sealed = base64::decode(&file.data)?;
+        if sealed.len() < NONCE_LENGTH {
+            return Err(Error::Encryption("encrypted gistit is corrupted"));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let nonce: [u8; NONCE_LENGTH] = nonce
+            .try_into()
+            .map_err(|_| Error::Encryption("encrypted gistit is corrupted"))?;
+        let plaintext = cipher
+            .decrypt(&XNonce::from(nonce), ciphertext)
+            .map_err(|_| {
+                if with_passphrase {
+                    Error::WrongPassphrase
+                } else {
+                    Error::Encryption("failed to decrypt gistit, is the key correct?")
+                }
+            })?;
+
+        if file.is_binary() || file.is_compressed() {
+            file.data.clear();
+            file.raw = plaintext;
+        } else {
+            file.data = String::from_utf8(plaintext)
+                .map_err(|_| Error::Encryption("decrypted gistit is not valid utf8"))?;
+        }
+    }
+    gistit.encryption = None;
+
+    Ok(())
+}
+
+/// The encryption scheme of a gistit, if any
+///
+/// # Errors
+///
+/// Fails if the gistit is encrypted with a scheme we don't know of
+pub fn scheme(gistit: &Gistit) -> Result<Option<Scheme>> {
+    gistit
+        .encryption
+        .as_ref()
+        .map(|encryption| {
+            Scheme::from_i32(encryption.scheme).ok_or(Error::Encryption(
+                "gistit is encrypted with an unknown scheme",
+            ))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_inner() -> Vec<Inner> {
+        vec![
+            Gistit::new_inner(
+                "foo.rs".to_owned(),
+                "rust".to_owned(),
+                9,
+                "fn foo() {}".to_owned(),
+            ),
+            Gistit::new_inner(
+                "bar.rs".to_owned(),
+                "rust".to_owned(),
+                9,
+                "fn bar() {}".to_owned(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn crypt_encrypt_decrypt_roundtrip() {
+        let secret = Secret::Key(SecretKey::generate());
+        let mut inner = test_inner();
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+
+        assert_ne!(inner, test_inner());
+
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        gistit.encryption = Some(encryption);
+        decrypt(&mut gistit, secret.key()).unwrap();
+
+        assert_eq!(gistit.inner, test_inner());
+        assert!(!gistit.is_encrypted());
+    }
+
+    #[test]
+    fn crypt_binary_roundtrip() {
+        let secret = Secret::Key(SecretKey::generate());
+        let raw = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe];
+        let binary = vec![Gistit::new_binary_inner(
+            "shot.png".to_owned(),
+            "binary".to_owned(),
+            7,
+            raw.clone(),
+        )];
+        let mut inner = binary.clone();
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+
+        assert!(inner[0].raw.is_empty());
+        assert!(!inner[0].data.is_empty());
+
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        gistit.encryption = Some(encryption);
+        assert!(gistit.check_encoding().is_ok());
+        decrypt(&mut gistit, secret.key()).unwrap();
+
+        assert_eq!(gistit.inner, binary);
+        assert_eq!(gistit.inner[0].contents(), raw.as_slice());
+    }
+
+    #[test]
+    fn crypt_compressed_roundtrip() {
+        let secret = Secret::Key(SecretKey::generate());
+        let log = "INFO all good\n".repeat(1000);
+        let mut compressed = Gistit::new_inner(
+            "app.log".to_owned(),
+            "text".to_owned(),
+            log.len() as u32,
+            log.clone(),
+        );
+        compressed.compress();
+        let mut inner = vec![compressed.clone()];
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        gistit.encryption = Some(encryption);
+        assert!(gistit.check_encoding().is_ok());
+        decrypt(&mut gistit, secret.key()).unwrap();
+
+        assert_eq!(gistit.inner[0], compressed);
+        gistit.decompress().unwrap();
+        assert_eq!(gistit.inner[0].data, log);
+    }
+
+    #[test]
+    fn crypt_decrypt_wrong_key() {
+        let mut inner = test_inner();
+        let secret = Secret::Key(SecretKey::generate());
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        gistit.encryption = Some(encryption);
+
+        assert!(matches!(
+            decrypt(&mut gistit, &SecretKey::generate()),
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn crypt_unknown_scheme() {
+        let mut inner = test_inner();
+        let secret = Secret::Key(SecretKey::generate());
+        let mut encryption = encrypt_inner(&mut inner, &secret).unwrap();
+        encryption.scheme = 42;
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        assert!(matches!(scheme(&gistit), Ok(None)));
+
+        gistit.encryption = Some(encryption);
+        assert!(matches!(scheme(&gistit), Err(Error::Encryption(_))));
+        assert!(matches!(
+            decrypt(&mut gistit, &SecretKey::generate()),
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn crypt_passphrase_roundtrip() {
+        let secret = Secret::from_passphrase("alright alright alright").unwrap();
+        let mut inner = test_inner();
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+        let mut gistit = Gistit::new(String::new(), String::new(), None, String::new(), inner);
+        gistit.encryption = Some(encryption);
+
+        assert_eq!(scheme(&gistit).unwrap(), Some(Scheme::Passphrase));
+        let salt = gistit.encryption.as_ref().unwrap().salt.clone();
+
+        let wrong_key = SecretKey::derive("alright alright", &salt).unwrap();
+        assert!(matches!(
+            decrypt(&mut gistit.clone(), &wrong_key),
+            Err(Error::WrongPassphrase)
+        ));
+
+        let key = SecretKey::derive("alright alright alright", &salt).unwrap();
+        decrypt(&mut gistit, &key).unwrap();
+        assert_eq!(gistit.inner, test_inner());
+    }
+
+    #[test]
+    fn crypt_recipients_roundtrip() {
+        let alice = ed25519::Keypair::generate();
+        let bob = ed25519::Keypair::generate();
+        let eve = ed25519::Keypair::generate();
+        let peer_id = |keypair: &ed25519::Keypair| {
+            PeerId::from(identity::PublicKey::Ed25519(keypair.public()))
+        };
+
+        let secret = Secret::for_recipients(&[peer_id(&alice), peer_id(&bob)]).unwrap();
+        let mut inner = test_inner();
+        let encryption = encrypt_inner(&mut inner, &secret).unwrap();
+        assert_eq!(encryption.recipients.len(), 2);
+
+        for keypair in [&alice, &bob] {
+            let key = unwrap_key(&encryption, keypair).unwrap();
+            let mut gistit = Gistit::new(
+                String::new(),
+                String::new(),
+                None,
+                String::new(),
+                inner.clone(),
+            );
+            gistit.encryption = Some(encryption.clone());
+
+            decrypt(&mut gistit, &key).unwrap();
+            assert_eq!(gistit.inner, test_inner());
+        }
+
+        assert!(matches!(
+            unwrap_key(&encryption, &eve),
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn crypt_recipients_rejects_invalid_peer_ids() {
+        let random = PeerId::random();
+        assert!(matches!(
+            Secret::for_recipients(&[random]),
+            Err(Error::Argument(_, "--to"))
+        ));
+    }
+
+    #[test]
+    fn crypt_share_token_roundtrip() {
+        let key = SecretKey::generate();
+        let hash = "a".repeat(64);
+        let token = share_token(&hash, &key);
+
+        let (parsed_hash, parsed_key) = parse_token(&token).unwrap();
+        assert_eq!(parsed_hash, hash);
+        assert_eq!(parsed_key, Some(key));
+
+        let (parsed_hash, parsed_key) = parse_token(&hash).unwrap();
+        assert_eq!(parsed_hash, hash);
+        assert_eq!(parsed_key, None);
+    }
+}
@@ -20,6 +20,9 @@ pub enum Error {
     #[error("{0}")]
     JsonParse(#[from] serde_json::Error),
 
+    #[error("{0}")]
+    Base64(#[from] base64::DecodeError),
+
     #[error("{0}")]
     Ipc(#[from] gistit_ipc::Error),
 
@@ -38,10 +41,44 @@ pub enum Error {
     #[error("{0}")]
     Server(&'static str),
 
+    #[error("{0}")]
+    Encryption(&'static str),
+
+    #[error("{0}")]
+    Node(&'static str),
+
+    #[error("{0}")]
+    Plugin(String),
+
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+
+    #[error("gistit has expired")]
+    Expired,
+
+    #[error("invalid signature: {0}")]
+    Signature(&'static str),
+
+    #[error("gistit is not signed")]
+    Unsigned,
+
+    #[error("gistit contents don't match the requested hash")]
+    Integrity,
+
+    #[error("timed out while {0}")]
+    Timeout(&'static str),
+
+    #[error("interrupted while {0}")]
+    Interrupted(&'static str),
+
     /// (Reason, Param)
     #[error("{}", fmt_subcat("PARAM", .0, .1))]
     Argument(&'static str, &'static str),
 
+    /// (Backend, What it can't do)
+    #[error("{}", fmt_subcat("PARAM", &format!("{} can't {}", .0, .1), "--via"))]
+    Unsupported(&'static str, &'static str),
+
     #[error("{}", fmt_suggest("invalid colorscheme parameter", .0.clone()))]
     Colorscheme(String),
 
@@ -63,7 +100,7 @@ Did you mean: '{}'?
     )
 }
 
-fn fmt_subcat(subcat: &'static str, cause: &'static str, param: &'static str) -> String {
+fn fmt_subcat(subcat: &'static str, cause: &str, param: &'static str) -> String {
     format!(
         r#"{}
 
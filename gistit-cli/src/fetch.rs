@@ -1,28 +1,54 @@
-use std::path::PathBuf;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use clap::ArgMatches;
 use console::style;
-use reqwest::StatusCode;
 use serde::Serialize;
 
-use gistit_proto::ipc::{self, Instruction};
+use gistit_proto::payload::gistit::encryption::Scheme;
 use gistit_proto::payload::Gistit;
 use gistit_proto::prost::Message;
 
 use gistit_project::path;
+use gistit_project::settings::Settings;
 
+use crate::backend::{self, Context};
+use crate::crypt::{self, SecretKey};
 use crate::dispatch::Dispatch;
-use crate::file::File;
+use crate::file::{self, File};
+use crate::history::{self, Backend, Direction, Entry};
+use crate::lang;
 use crate::param::check;
-use crate::server::SERVER_URL_GET;
-use crate::{errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
+use crate::server::Profile;
+use crate::sign::{self, Verification};
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+/// How long to wait on the node to find a gistit by default
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// The gistits kept by the history, looked at before any backend by default
+pub const CACHE: &str = "cache";
+
+/// Where `--save` puts files, apart from the history and node state in the data directory
+const SAVE_DIR: &str = "saved";
+
+/// How much of a binary file previews show
+const HEXDUMP_PREVIEW_BYTES: usize = 512;
+
+/// Bytes per hexdump line
+const HEXDUMP_LINE_BYTES: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct Action {
     pub hash: &'static str,
-    pub colorscheme: &'static str,
+    pub colorscheme: Option<&'static str>,
     pub save: bool,
+    pub from: Option<Vec<&'static str>>,
+    pub server: Option<&'static str>,
+    pub timeout: Option<&'static str>,
 }
 
 impl Action {
@@ -33,10 +59,11 @@ impl Action {
             hash: args
                 .value_of("HASH")
                 .ok_or(Error::Argument("missing arugment", "--hash"))?,
-            colorscheme: args
-                .value_of("colorscheme")
-                .unwrap_or("Monokai Extended Origin"), // This is the most decent looking
+            colorscheme: args.value_of("colorscheme"),
             save: args.is_present("save"),
+            from: args.values_of("from").map(Iterator::collect),
+            server: args.value_of("server"),
+            timeout: args.value_of("timeout"),
         }))
     }
 }
@@ -44,22 +71,20 @@ impl Action {
 #[derive(Debug, Serialize)]
 pub struct Config {
     hash: &'static str,
-    colorscheme: &'static str,
+    colorscheme: String,
     save: bool,
+    #[serde(skip)]
+    key: Option<SecretKey>,
+    /// Whether the history keeps the key, see `history.keys`
+    history_keys: bool,
+    sources: Vec<&'static str>,
+    #[serde(skip)]
+    server: Profile,
+    timeout: Duration,
     runtime_path: PathBuf,
     config_path: PathBuf,
     data_path: PathBuf,
-}
-
-impl TryFrom<&Config> for Gistit {
-    type Error = Error;
-
-    fn try_from(value: &Config) -> std::result::Result<Self, Self::Error> {
-        Ok(Self {
-            hash: value.hash.to_owned(),
-            ..Self::default()
-        })
-    }
+    node_max_size: u64,
 }
 
 #[async_trait]
@@ -68,101 +93,354 @@ impl Dispatch for Action {
 
     async fn prepare(&self) -> Result<Self::InnerData> {
         progress!("Preparing");
-        let hash = check::hash(self.hash)?;
-        let colorscheme = check::colorscheme(self.colorscheme)?;
+        let (hash, key) = crypt::parse_token(self.hash)?;
+        let hash = check::hash(hash)?;
+        let config_path = path::config()?;
+        let settings = Settings::load(&config_path)?;
+        let server = Profile::from_settings(&settings, self.server)?;
+        let colorscheme = self
+            .colorscheme
+            .map(ToOwned::to_owned)
+            .or_else(|| settings.text("colorscheme"))
+            .unwrap_or_default();
+        let colorscheme = check::colorscheme(&colorscheme)?.to_owned();
+        let sources = if let Some(ref values) = self.from {
+            check::sources(values)?
+        } else if let Some(values) = settings.list("backends.fetch") {
+            check::sources(&values.iter().map(String::as_str).collect::<Vec<_>>())?
+        } else {
+            iter::once(CACHE).chain(backend::names()).collect()
+        };
+        let timeout = if let Some(value) = self.timeout {
+            check::timeout(value)?
+        } else {
+            FETCH_TIMEOUT
+        };
         updateln!("Prepared");
 
         Ok(Config {
             hash,
             colorscheme,
             save: self.save,
+            key,
+            history_keys: settings.flag("history.keys")?.unwrap_or(false),
+            sources,
+            server,
+            timeout,
             runtime_path: path::runtime()?,
-            config_path: path::config()?,
+            config_path,
             data_path: path::data()?,
+            node_max_size: settings.node_max_size()?,
         })
     }
 
     async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
         progress!("Fetching");
-        let mut bridge = gistit_ipc::client(&config.runtime_path)?;
-
-        if bridge.alive() {
-            warnln!("gistit-daemon running, looking in the DHT");
-            bridge.connect_blocking()?;
-            bridge
-                .send(Instruction::request_fetch(self.hash.to_owned()))
-                .await?;
-
-            if let ipc::instruction::Kind::FetchResponse(ipc::instruction::FetchResponse {
-                gistit: Some(gistit),
-            }) = bridge.recv().await?.expect_response()?
-            {
-                preview_or_save(&gistit, self.save, &config)?;
+        let backends = backend::registry(&Context {
+            runtime_path: config.runtime_path.clone(),
+            data_path: config.data_path.clone(),
+            timeout: config.timeout,
+            once: false,
+            github_token: None,
+            server: config.server.clone(),
+            node_max_size: config.node_max_size,
+        });
+        let mut mismatched = false;
+
+        for source in &config.sources {
+            let found = if *source == CACHE {
+                Ok(history::cached(&config.data_path, config.hash).map(|gistit| (gistit, None)))
             } else {
-                interruptln!();
-                errorln!("gistit hash not found");
-            }
-        } else {
-            let gistit: Gistit = (&config).try_into()?;
-
-            let response = reqwest::Client::new()
-                .post(SERVER_URL_GET.to_string())
-                .header("content-type", "application/x-protobuf")
-                .body(gistit.encode_to_vec())
-                .send()
-                .await?;
-            updateln!("Fetched");
-
-            match response.status() {
-                StatusCode::OK => {
-                    let gistit = Gistit::from_bytes(response.bytes().await?)?;
-                    preview_or_save(&gistit, self.save, &config)?;
+                match backend::select(&backends, &[source]).first() {
+                    Some(backend) => backend
+                        .retrieve(config.hash)
+                        .await
+                        .map(|found| found.map(|(gistit, origin)| (gistit, Some(origin)))),
+                    None => Ok(None),
                 }
-                StatusCode::NOT_FOUND => {
-                    return Err(Error::Server("gistit hash not found"));
+            };
+            let source = if *source == CACHE {
+                "local cache"
+            } else {
+                source
+            };
+
+            match found {
+                // The server or a provider could send anything, make sure it's what we asked for
+                Ok(Some((gistit, origin))) if verifies(&gistit, config.hash) => {
+                    updateln!("Fetched from {}", source);
+                    return open(gistit, origin, self.save, &config);
                 }
-                _ => return Err(Error::Server("unexpected response")),
+                Ok(Some(_)) => {
+                    mismatched = true;
+                    warnln!("{} sent a gistit not matching the requested hash", source);
+                }
+                Ok(None) => (),
+                Err(Error::Interrupted(phase)) => return Err(Error::Interrupted(phase)),
+                Err(err) => warnln!("failed to fetch from {}: {}", source, err),
             }
         }
 
-        Ok(())
+        if mismatched {
+            Err(Error::Integrity)
+        } else {
+            Err(Error::Server("gistit hash not found"))
+        }
     }
 }
 
-pub fn preview_or_save(gistit: &Gistit, save: bool, config: &Config) -> Result<()> {
-    // NOTE: Currently we support one file
-    let inner = gistit.inner.first().expect("to have at least one file");
-    let mut file = File::from_data(&inner.data, &inner.name)?;
-    let save_location = &config.data_path;
+fn verifies(gistit: &Gistit, hash: &str) -> bool {
+    gistit.hash == hash && gistit.compute_hash() == hash
+}
+
+/// Decrypts a fetched payload before showing or saving it. Payloads that didn't come from the
+/// local cache are recorded in the history
+fn open(payload: Gistit, backend: Option<Backend>, save: bool, config: &Config) -> Result<()> {
+    let payload = unexpired(payload)?;
+    let verification = sign::verify(&payload);
+    if let Verification::Invalid(_) = verification {
+        warnln!("{}", style(&verification).red().bold());
+    }
+
+    let gistit = decrypt(payload.clone(), config.key.as_ref(), &config.config_path)?;
+    if let Some(backend) = backend {
+        let key = config.key.as_ref().filter(|_| config.history_keys);
+        let mut entry = Entry::new(&payload, Direction::Fetched, backend, key);
+        if entry.backend == Backend::Server {
+            entry = entry.with_server(&config.server.name);
+        }
+        if let Err(err) = history::record(&config.data_path, Some(&payload), &entry) {
+            warnln!("failed to record history: {}", err);
+        }
+    }
 
     if save {
-        let file_path = save_location.join(file.name());
-        file.save_as(&file_path)?;
+        // Keep the payload as received so it can be verified later
+        let save_location = config.data_path.join(SAVE_DIR);
+        fs::create_dir_all(&save_location)?;
+        let payload_path = save_location.join(format!("{}.gistit", payload.hash));
+        fs::write(&payload_path, payload.encode_to_vec())?;
+        warnln!("payload saved at: `{}`", payload_path.to_string_lossy());
+    }
 
-        warnln!("gistit saved at: `{}`", file_path.to_string_lossy());
-        finish!("💾  Saved");
+    preview_or_save(&gistit, &verification, save, config)
+}
+
+pub fn unexpired(gistit: Gistit) -> Result<Gistit> {
+    if gistit.is_expired(u128::from(history::now())) {
+        Err(Error::Expired)
+    } else {
+        Ok(gistit)
+    }
+}
+
+/// Decrypts with the `hash#key` key, a prompted passphrase or the local node key, then inflates
+/// compressed files
+pub fn decrypt(mut gistit: Gistit, key: Option<&SecretKey>, config_path: &Path) -> Result<Gistit> {
+    let encryption = gistit.encryption.clone().unwrap_or_default();
+
+    match (crypt::scheme(&gistit)?, key) {
+        (None, _) => (),
+        (Some(Scheme::Passphrase), _) => {
+            let key = SecretKey::derive(&crypt::passphrase(false)?, &encryption.salt)?;
+            crypt::decrypt(&mut gistit, &key)?;
+        }
+        (Some(Scheme::Recipients), _) => {
+            let keypair = crypt::node_keypair(config_path)?;
+            let key = crypt::unwrap_key(&encryption, &keypair)?;
+            crypt::decrypt(&mut gistit, &key)?;
+        }
+        (Some(Scheme::Key), Some(key)) => crypt::decrypt(&mut gistit, key)?,
+        (Some(Scheme::Key), None) => {
+            return Err(Error::Argument(
+                "gistit is encrypted, missing key",
+                "[HASH]#[KEY]",
+            ))
+        }
+    }
+
+    gistit.decompress()?;
+    Ok(gistit)
+}
+
+pub fn preview_or_save(
+    gistit: &Gistit,
+    verification: &Verification,
+    save: bool,
+    config: &Config,
+) -> Result<()> {
+    let signer = fmt_signer(verification, &config.config_path);
+
+    if save {
+        let save_location = config.data_path.join(SAVE_DIR);
+
+        for inner in &gistit.inner {
+            // Rebuild the directory tree, never writing outside of the save directory
+            let file_path =
+                save_location.join(file::relative_name_from_path(Path::new(&inner.name)));
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut file = File::from_data(inner.contents(), &inner.name)?;
+            file.save_as(&file_path)?;
+
+            warnln!("gistit saved at: `{}`", file_path.to_string_lossy());
+        }
+        finish!(format!("💾  Saved, by {} | {}", fmt_author(gistit), signer));
     } else {
         finish!("👀  Preview");
+        preview(gistit, &signer, &config.colorscheme)?;
+    }
+    Ok(())
+}
+
+/// Shows the gistit files with syntax highlighting
+pub fn preview(gistit: &Gistit, signer: &str, colorscheme: &str) -> Result<()> {
+    // Binary files are shown as a hexdump of their first bytes
+    let files = gistit
+        .inner
+        .iter()
+        .map(|inner| {
+            let file = if inner.is_binary() {
+                File::from_data(hexdump(inner.contents()), &inner.name)?
+            } else {
+                File::from_data(&inner.data, &inner.name)?
+            };
+            Ok((inner, file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let inputs = files.iter().map(|(inner, file)| {
         let mut header_string = style(&inner.name).green().to_string();
-        header_string.push_str(&format!(" | {}", style(&gistit.author).blue().bold()));
+        header_string.push_str(&format!(" | {}", fmt_author(gistit)));
+        header_string.push_str(&format!(" | {}", signer));
 
         if let Some(ref description) = gistit.description {
             header_string.push_str(&format!(" | {}", style(description).italic()));
         }
 
-        let input = bat::Input::from_reader(&*file)
-            .name(&inner.name)
-            .title(header_string);
-
-        bat::PrettyPrinter::new()
-            .header(true)
-            .grid(true)
-            .input(input)
-            .line_numbers(true)
-            .theme(config.colorscheme)
-            .use_italics(true)
-            .paging_mode(bat::PagingMode::QuitIfOneScreen)
-            .print()?;
-    }
+        if inner.is_binary() {
+            let size = inner.contents().len();
+            header_string.push_str(&format!(
+                " | {}",
+                style(format!(
+                    "binary, {} of {} bytes, --save to keep it",
+                    size.min(HEXDUMP_PREVIEW_BYTES),
+                    size
+                ))
+                .dim()
+            ));
+        }
+
+        if let Some(remaining) = gistit.remaining_millis(u128::from(history::now())) {
+            header_string.push_str(&format!(
+                " | {}",
+                style(format!("expires in {}", fmt_lifetime(remaining))).dim()
+            ));
+        }
+
+        let name = if inner.is_binary() {
+            String::new()
+        } else {
+            syntax_name(&inner.name, &inner.lang)
+        };
+        bat::Input::from_reader(&**file)
+            .name(name)
+            .title(header_string)
+    });
+
+    bat::PrettyPrinter::new()
+        .header(true)
+        .grid(true)
+        .inputs(inputs)
+        .line_numbers(true)
+        .theme(colorscheme)
+        .use_italics(true)
+        .paging_mode(bat::PagingMode::QuitIfOneScreen)
+        .print()?;
     Ok(())
 }
+
+/// Bat picks the syntax from the name, so files whose name doesn't tell their language get the
+/// extension of it. Only bat sees this name, headers show the real one
+fn syntax_name(name: &str, lang: &str) -> String {
+    if lang::from_name(name) == Some(lang) || lang == lang::DEFAULT_LANG {
+        return name.to_owned();
+    }
+
+    lang::extension(lang).map_or_else(
+        || name.to_owned(),
+        |extension| format!("{}.{}", name, extension),
+    )
+}
+
+/// The first bytes of a binary file, `offset  hex bytes  |ascii|` like `hexdump -C`
+fn hexdump(data: &[u8]) -> String {
+    let shown = &data[..data.len().min(HEXDUMP_PREVIEW_BYTES)];
+    let mut dump = String::new();
+
+    for (i, line) in shown.chunks(HEXDUMP_LINE_BYTES).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let (left, right) = hex.split_at(hex.len().min(HEXDUMP_LINE_BYTES / 2));
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        dump.push_str(&format!(
+            "{:08x}  {:<23}  {:<23}  |{}|\n",
+            i * HEXDUMP_LINE_BYTES,
+            left.join(" "),
+            right.join(" "),
+            ascii
+        ));
+    }
+    if data.len() > shown.len() {
+        dump.push_str("...\n");
+    }
+
+    dump
+}
+
+/// The author as shown in headers, the same whether previewed or saved
+#[must_use]
+pub fn fmt_author(gistit: &Gistit) -> String {
+    style(&gistit.author).blue().bold().to_string()
+}
+
+/// Formats the signature status, unverified authors stand out
+pub fn fmt_signer(verification: &Verification, config_path: &Path) -> String {
+    match verification {
+        Verification::Verified(_) => style(format!(
+            "verified: {}",
+            verification.signer(config_path).unwrap_or_default()
+        ))
+        .green()
+        .to_string(),
+        Verification::Invalid(_) => style(format!("⚠ {}", verification))
+            .red()
+            .bold()
+            .to_string(),
+        Verification::Unsigned => style("⚠ unverified author").yellow().to_string(),
+    }
+}
+
+/// Formats a lifetime in its two most significant units, e.g. `2d 3h` or `45m`
+pub fn fmt_lifetime(millis: u128) -> String {
+    let minutes = millis / 60_000;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    match (days, hours, minutes) {
+        (0, 0, 0) => "less than a minute".to_owned(),
+        (0, 0, m) => format!("{}m", m),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
@@ -7,22 +7,24 @@ use std::env::temp_dir;
 use std::ffi::OsStr;
 use std::fs::{self, write};
 use std::io::{BufReader, Read, Seek, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::str;
 
 use phf::{phf_map, Map};
 use rand::{distributions::Alphanumeric, Rng};
 
 use gistit_project::var::GISTIT_MAX_SIZE;
+use gistit_proto::{Gistit, Inner};
 
-use crate::Result;
+use crate::lang;
+use crate::{Error, Result};
 
 /// Supported file extensions
 /// This is a compile time built hashmap to check incomming file extensions against.
 /// Follows the extensions supported by currently UI syntax highlighting lib:
 /// [`react-syntax-highlighter`](https://gist.github.com/ppisarczyk/43962d06686722d26d176fad46879d41)
 ///
-/// `Bat` does autodetection so this doesn't affect it.
+/// `Bat` does autodetection, previews point it at the detected language, see [`crate::lang`].
 ///
 /// Filled with [Programming languages](https://gist.github.com/ppisarczyk/43962d06686722d26d176fad46879d41)
 /// and some google help.
@@ -593,7 +595,9 @@ pub const EXTENSION_TO_LANG_MAPPING: Map<&'static str, &'static str> = phf_map!
 pub struct File {
     handler: fs::File,
     path: PathBuf,
+    name: String,
     size: usize,
+    lang: &'static str,
 }
 
 impl std::ops::Deref for File {
@@ -618,30 +622,128 @@ pub fn name_from_path(path: &Path) -> String {
         .to_string()
 }
 
-impl File {
-    /// Create file from a given path
-    ///
-    /// # Errors
-    ///
-    /// Fails with [`std::io::Error`]
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let handler = fs::File::open(path)?;
-        let size = fs::metadata(path)?.len() as usize;
+/// Builds a `/` separated name out of a relative path, so directory trees can be rebuilt on the
+/// other end. Absolute paths and paths escaping the current directory fallback to
+/// [`name_from_path`].
+#[must_use]
+pub fn relative_name_from_path(path: &Path) -> String {
+    let is_relative = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
 
-        Ok(Self {
-            handler,
-            path: path.to_path_buf(),
-            size,
-        })
+    if is_relative {
+        let name = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if name.is_empty() {
+            name_from_path(path)
+        } else {
+            name
+        }
+    } else {
+        name_from_path(path)
+    }
+}
+
+/// Names a `path` found while walking `root`. The `root` itself is kept in the name unless it is
+/// the current directory.
+#[must_use]
+pub fn walked_name(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    if relative.as_os_str().is_empty() {
+        relative_name_from_path(root)
+    } else if root.components().all(|c| c == Component::CurDir) {
+        relative_name_from_path(relative)
+    } else {
+        format!(
+            "{}/{}",
+            relative_name_from_path(root),
+            relative_name_from_path(relative)
+        )
     }
+}
 
+/// Recursively walks `path` returning every file under it, sorted by path.
+/// Hidden entries inside directories are skipped.
+///
+/// # Errors
+///
+/// Fails with [`std::io::Error`]
+pub fn walk(path: &Path) -> Result<Vec<PathBuf>> {
+    if !fs::metadata(path)?.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for entry in entries {
+        if name_from_path(&entry).starts_with('.') {
+            continue;
+        }
+        files.extend(walk(&entry)?);
+    }
+
+    Ok(files)
+}
+
+/// Whether `data` should be carried as bytes rather than text: it's not valid UTF-8 or holds NUL
+/// bytes, like images, captures or legacy encoded sources
+#[must_use]
+pub fn is_binary(data: &[u8]) -> bool {
+    data.contains(&0) || str::from_utf8(data).is_err()
+}
+
+/// The file sent for `data` named `name`, deflated if that makes it smaller. The language is
+/// detected unless given
+///
+/// # Errors
+///
+/// Fails if `data` is bigger than its size can tell, 4 GiB
+pub fn to_inner(name: String, data: Vec<u8>, lang: Option<&'static str>) -> Result<Inner> {
+    let size = u32::try_from(data.len())
+        .map_err(|_| Error::Argument("file size not allowed", "[FILE]"))?;
+    let binary = is_binary(&data);
+    let lang = lang
+        .unwrap_or_else(|| detect_lang(&name, &data, binary))
+        .to_owned();
+
+    let mut inner = if binary {
+        Gistit::new_binary_inner(name, lang, size, data)
+    } else {
+        let data = String::from_utf8(data).map_err(|err| err.utf8_error())?;
+        Gistit::new_inner(name, lang, size, data)
+    };
+    inner.compress();
+    Ok(inner)
+}
+
+/// The language of a file named `name` holding `data`, binary files only go by their name
+fn detect_lang(name: &str, data: &[u8], binary: bool) -> &'static str {
+    if binary {
+        lang::from_name(name).unwrap_or(lang::BINARY_LANG)
+    } else {
+        lang::detect(name, &String::from_utf8_lossy(data))
+    }
+}
+
+impl File {
     /// Create a file from a decoded vector of bytes
     ///
     /// # Errors
     ///
     /// Fails with [`std::io::Error`]
-    pub fn from_data(data: impl AsRef<str>, name: &str) -> Result<Self> {
+    pub fn from_data(data: impl AsRef<[u8]>, name: &str) -> Result<Self> {
         let data = data.as_ref();
 
         let (handler, path) = {
@@ -653,7 +755,7 @@ impl File {
 
             let mut rng_name = "gistit-".to_owned();
             rng_name.push_str(&rng_string);
-            rng_name.push_str(name);
+            rng_name.push_str(&name_from_path(Path::new(name)));
 
             let path = temp_dir().join(&rng_name);
             let mut handler = fs::OpenOptions::new()
@@ -663,7 +765,7 @@ impl File {
                 .truncate(true)
                 .open(&path)?;
 
-            handler.write_all(data.as_bytes())?;
+            handler.write_all(data)?;
             handler.rewind()?;
 
             (handler, path)
@@ -672,7 +774,9 @@ impl File {
         Ok(Self {
             handler,
             path,
+            name: name.to_owned(),
             size: data.len(),
+            lang: detect_lang(name, data, is_binary(data)),
         })
     }
 
@@ -683,15 +787,12 @@ impl File {
 
     #[must_use]
     pub fn name(&self) -> String {
-        name_from_path(&self.path)
+        self.name.clone()
     }
 
     #[must_use]
-    pub fn lang(&self) -> &str {
-        self.path.extension().map_or("text", |ext| {
-            let ext_str = OsStr::to_str(ext).expect("file to contain valid utf8 extension");
-            EXTENSION_TO_LANG_MAPPING.get(ext_str).unwrap_or(&"text")
-        })
+    pub const fn lang(&self) -> &'static str {
+        self.lang
     }
 
     #[must_use]
@@ -712,13 +813,26 @@ impl File {
         Ok(buf)
     }
 
+    /// Reads the file using [`BufReader`] and returns contents as bytes, binary or not
+    ///
+    /// # Errors
+    ///
+    /// Fails if can't read the file
+    pub fn read_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(GISTIT_MAX_SIZE);
+        let mut reader = BufReader::new(&**self);
+        reader.read_to_end(&mut buf)?;
+
+        Ok(buf)
+    }
+
     /// Reads the contents and save the file to given path
     ///
     /// # Errors
     ///
     /// Fails with [`std::io::Error`]
     pub fn save_as(&mut self, path: &Path) -> Result<()> {
-        let content = self.read()?;
+        let content = self.read_bytes()?;
         Ok(write(path, content)?)
     }
 }
@@ -747,6 +861,71 @@ mod tests {
         assert_eq!(n6, "😁");
     }
 
+    #[test]
+    fn file_relative_name_from_path_edge_cases() {
+        let n1 = relative_name_from_path(Path::new("foo.txt"));
+        let n2 = relative_name_from_path(Path::new("./src/foo.rs"));
+        let n3 = relative_name_from_path(Path::new("/src/foo.rs"));
+        let n4 = relative_name_from_path(Path::new("../../foo.rs"));
+        let n5 = relative_name_from_path(Path::new("src/../../foo.rs"));
+
+        assert_eq!(n1, "foo.txt");
+        assert_eq!(n2, "src/foo.rs");
+        assert_eq!(n3, "foo.rs");
+        assert_eq!(n4, "foo.rs");
+        assert_eq!(n5, "foo.rs");
+    }
+
+    #[test]
+    fn file_walk_directory_tree() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        tmp.child("Cargo.toml").touch().unwrap();
+        tmp.child("src/main.rs").touch().unwrap();
+        tmp.child("src/bin/foo.rs").touch().unwrap();
+        tmp.child(".git/HEAD").touch().unwrap();
+
+        let files = walk(&tmp).unwrap();
+
+        assert_eq!(
+            files,
+            vec![
+                tmp.join("Cargo.toml"),
+                tmp.join("src/bin/foo.rs"),
+                tmp.join("src/main.rs"),
+            ]
+        );
+
+        // Sent under their path from the walked directory
+        let dir = name_from_path(&tmp);
+        let names: Vec<String> = files
+            .iter()
+            .map(|path| to_inner(walked_name(&tmp, path), fs::read(path).unwrap(), None))
+            .map(|file| file.unwrap().name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                format!("{}/Cargo.toml", dir),
+                format!("{}/src/bin/foo.rs", dir),
+                format!("{}/src/main.rs", dir),
+            ]
+        );
+
+        let root = Path::new("./src");
+        assert_eq!(
+            walked_name(Path::new("."), Path::new("./src/main.rs")),
+            "src/main.rs"
+        );
+        assert_eq!(
+            walked_name(root, Path::new("./src/bin/foo.rs")),
+            "src/bin/foo.rs"
+        );
+        assert_eq!(
+            walked_name(Path::new("main.rs"), Path::new("main.rs")),
+            "main.rs"
+        );
+    }
+
     #[test]
     fn file_spawn_random_and_write() {
         let data: String = rand::thread_rng()
@@ -760,8 +939,13 @@ mod tests {
         assert_eq!(data, read);
     }
 
+    /// The file sent for `path`, the way `send` reads it
+    fn sent(path: &Path) -> Inner {
+        to_inner(walked_name(path, path), fs::read(path).unwrap(), None).unwrap()
+    }
+
     #[test]
-    fn file_structure_new_from_path() {
+    fn file_to_inner_from_path() {
         let data: String = rand::thread_rng()
             .sample_iter(&Alphanumeric)
             .take(512)
@@ -773,11 +957,10 @@ mod tests {
         input_file.touch().unwrap();
         input_file.write_binary(data.as_bytes()).unwrap();
 
-        let mut file = File::from_path(&input_file).unwrap();
-        assert_eq!(file.size(), 512);
-        let mut content = file.read().unwrap();
-        assert_eq!(content, data);
-        assert_eq!(file.name(), "foo.txt".to_owned());
+        let file = sent(&input_file);
+        assert_eq!(file.size, 512);
+        assert_eq!(file.contents(), data.as_bytes());
+        assert_eq!(file.name, "foo.txt");
     }
 
     #[test]
@@ -801,12 +984,8 @@ mod tests {
             .map(char::from)
             .collect();
         let tmp = assert_fs::TempDir::new().unwrap();
-        let tmp_file = tmp.child("foo.txt");
 
-        tmp_file.touch().unwrap();
-        tmp_file.write_binary(data.as_bytes()).unwrap();
-
-        let mut file = File::from_path(&tmp_file).unwrap();
+        let mut file = File::from_data(&data, "foo.txt").unwrap();
         file.save_as(&tmp.join("bar.txt")).unwrap();
         tmp.assert(predicates::path::exists());
 
@@ -832,10 +1011,74 @@ mod tests {
         let brainfuck = tmp.child("rly.bf");
         brainfuck.touch().unwrap();
 
-        assert_eq!(File::from_path(&rust).unwrap().lang(), "rust");
-        assert_eq!(File::from_path(&js).unwrap().lang(), "javascript");
-        assert_eq!(File::from_path(&cpp).unwrap().lang(), "cpp");
-        assert_eq!(File::from_path(&brainfuck).unwrap().lang(), "brainfuck");
+        assert_eq!(sent(&rust).lang, "rust");
+        assert_eq!(sent(&js).lang, "javascript");
+        assert_eq!(sent(&cpp).lang, "cpp");
+        assert_eq!(sent(&brainfuck).lang, "brainfuck");
+    }
+
+    #[test]
+    fn file_structure_lang_without_extension() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+
+        let dockerfile = tmp.child("Dockerfile");
+        dockerfile.write_str("FROM rust:latest\n").unwrap();
+
+        let script = tmp.child("deploy");
+        script
+            .write_str("#!/usr/bin/env bash\necho alright\n")
+            .unwrap();
+
+        assert_eq!(sent(&dockerfile).lang, "docker");
+        assert_eq!(sent(&script).lang, "bash");
+        assert_eq!(
+            to_inner("stdin".to_owned(), b"alright".to_vec(), None)
+                .unwrap()
+                .lang,
+            "text"
+        );
+        // `--lang` wins over detection
+        assert_eq!(
+            to_inner(
+                "deploy".to_owned(),
+                fs::read(&script).unwrap(),
+                Some("rust")
+            )
+            .unwrap()
+            .lang,
+            "rust"
+        );
+    }
+
+    #[test]
+    fn file_structure_binary_contents() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+
+        let png = [
+            0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0x00, 0x00,
+        ];
+        let image = tmp.child("shot.png");
+        image.write_binary(&png).unwrap();
+
+        // "café" in Latin-1
+        let legacy = tmp.child("legacy.c");
+        legacy.write_binary(b"/* caf\xe9 */\nint main;\n").unwrap();
+
+        let text = tmp.child("notes.txt");
+        text.write_str("café\n").unwrap();
+
+        let image = sent(&image);
+        assert!(image.is_binary());
+        assert_eq!(image.lang, lang::BINARY_LANG);
+        assert_eq!(image.contents(), png);
+
+        let legacy = sent(&legacy);
+        assert!(legacy.is_binary());
+        assert_eq!(legacy.lang, "c");
+
+        assert!(!sent(&text).is_binary());
+        assert!(is_binary(&png));
+        assert!(!is_binary(b"alright"));
     }
 
     #[test]
@@ -846,12 +1089,7 @@ mod tests {
             .map(char::from)
             .collect();
 
-        let tmp = assert_fs::TempDir::new().unwrap();
-        let tmp_file = tmp.child("foo");
-        tmp_file.touch().unwrap();
-        tmp_file.write_binary(data.as_bytes()).unwrap();
-
-        let file = File::from_path(&tmp_file).unwrap();
+        let file = File::from_data(&data, "foo").unwrap();
 
         assert_eq!(file.name(), "foo");
         assert_eq!(file.size(), 512);
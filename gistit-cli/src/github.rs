@@ -1,6 +1,7 @@
 use std::env;
 use std::fs;
 use std::io::Write;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
@@ -10,10 +11,9 @@ use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::patch::webbrowser::{self, BrowserOptions};
-use crate::server::SERVER_URL_TOKEN;
+use crate::server::Profile;
 use crate::{Error, Result};
 
-pub const GITHUB_OAUTH_CLIENT_ID: &str = "265cd618948a2e58042e";
 pub const GITHUB_OAUTH_BASE_URL: &str = "https://github.com/login/oauth/authorize";
 pub const GITHUB_GISTS_API_URL: &str = "https://api.github.com/gists";
 
@@ -21,6 +21,13 @@ pub const GITHUB_GISTS_API_URL: &str = "https://api.github.com/gists";
 pub struct Oauth {
     pub state: String,
     pub token: Option<Token>,
+    /// The OAuth app of the server profile, its server exchanges the code for a token
+    #[serde(skip)]
+    client_id: Option<String>,
+    #[serde(skip)]
+    token_url: Url,
+    #[serde(skip)]
+    token_path: PathBuf,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -48,15 +55,20 @@ pub struct CreateResponse {
 }
 
 impl Oauth {
-    /// Looks for token in project config dir and initializes state.
+    /// Looks for the token of the server profile in project config dir and initializes state.
     /// Will not fail if token file is missing.
     ///
     /// # Errors
     ///
     /// Fails if cannot read token file
-    pub fn new() -> Result<Self> {
+    pub fn new(profile: &Profile) -> Result<Self> {
         let config = gistit_project::path::config()?;
-        let token_path = config.join("github");
+        // Tokens belong to the OAuth app of the profile
+        let token_path = if profile.is_default() {
+            config.join("github")
+        } else {
+            config.join(format!("github-{}", profile.name))
+        };
         let state = unguessable_state();
 
         let token = if fs::metadata(&token_path).is_ok() {
@@ -65,7 +77,13 @@ impl Oauth {
             None
         };
 
-        Ok(Self { state, token })
+        Ok(Self {
+            state,
+            token,
+            client_id: profile.github_client_id.clone(),
+            token_url: profile.token_url(),
+            token_path,
+        })
     }
 
     /// Attempts to open a web browser and authorize GitHub OAuth
@@ -74,10 +92,14 @@ impl Oauth {
     ///
     /// Fails if cannot find a suitable browser or outside a display environment
     pub fn authorize(&self) -> Result<()> {
+        let client_id = self.client_id.as_deref().ok_or(Error::Argument(
+            "this server profile has no github client id",
+            "--server",
+        ))?;
         let url = Url::parse_with_params(
             GITHUB_OAUTH_BASE_URL,
             &[
-                ("client_id", GITHUB_OAUTH_CLIENT_ID),
+                ("client_id", client_id),
                 ("state", &self.state),
                 ("scope", "gist"),
             ],
@@ -107,7 +129,7 @@ impl Oauth {
         let mut retry = 0_usize;
         let token: Token = loop {
             let response = reqwest::Client::new()
-                .post(SERVER_URL_TOKEN.to_string())
+                .post(self.token_url.clone())
                 .json(self)
                 .send()
                 .await?;
@@ -128,8 +150,7 @@ impl Oauth {
             }
         };
 
-        let config = gistit_project::path::config()?;
-        fs::File::create(config.join("github"))?.write_all(&serde_json::to_vec(&token)?)?;
+        fs::File::create(&self.token_path)?.write_all(&serde_json::to_vec(&token)?)?;
 
         self.token = Some(token);
 
@@ -142,6 +163,12 @@ impl Oauth {
     }
 }
 
+/// GitHub Gists don't support directories, so relative paths are flattened into the file name
+#[must_use]
+pub fn gist_file_name(name: &str) -> String {
+    name.replace('/', "_")
+}
+
 #[must_use]
 pub fn unguessable_state() -> String {
     rand::thread_rng()
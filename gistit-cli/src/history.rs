@@ -0,0 +1,481 @@
+//! The history module
+//!
+//! Every successful send and fetch is appended to `history.jsonl` in the data directory, one
+//! entry per line. The payload is kept as it went over the wire under `history/`, so entries
+//! can be previewed again without hitting the network. Payloads of one-shot gistits aren't kept,
+//! those of expiring ones are dropped once they expire.
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::crypt::{self, SecretKey};
+use crate::Result;
+
+/// The history index, relative to the data directory
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// Where payloads are kept, relative to the data directory
+const HISTORY_PAYLOADS_DIR: &str = "history";
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Fetched,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sent => f.write_str("sent"),
+            Self::Fetched => f.write_str("fetched"),
+        }
+    }
+}
+
+/// Where a gistit was sent to or fetched from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Server,
+    P2p,
+    /// Posted straight to GitHub as the gist at this url
+    Github(String),
+    /// A `gistit-backend-<name>` plugin, by name
+    Plugin(String),
+}
+
+impl Backend {
+    /// A send keeps a single entry, for the backend that makes fetching it again the easiest
+    #[must_use]
+    pub const fn precedence(&self) -> u8 {
+        match self {
+            Self::P2p => 0,
+            Self::Server | Self::Plugin(_) => 1,
+            // Only the history knows where the gist is
+            Self::Github(_) => 2,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Server => f.write_str("server"),
+            Self::P2p => f.write_str("p2p"),
+            Self::Github(url) => f.write_str(url),
+            Self::Plugin(name) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub hash: String,
+    /// The random key of a `hash#key` encrypted gistit, only kept if `history.keys` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub files: Vec<String>,
+    /// Distinct languages of the files
+    pub lang: Vec<String>,
+    pub author: String,
+    pub description: Option<String>,
+    /// Unix timestamp in milliseconds of the send or fetch
+    pub timestamp: u64,
+    pub backend: Backend,
+    /// The server profile of entries sent to or fetched from a server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    pub direction: Direction,
+    /// Unix timestamp in milliseconds the gistit expires at, its payload is dropped then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    #[must_use]
+    pub fn new(
+        payload: &Gistit,
+        direction: Direction,
+        backend: Backend,
+        key: Option<&SecretKey>,
+    ) -> Self {
+        let mut lang: Vec<String> = Vec::new();
+        for inner in &payload.inner {
+            if !lang.contains(&inner.lang) {
+                lang.push(inner.lang.clone());
+            }
+        }
+
+        Self {
+            hash: payload.hash.clone(),
+            key: key.map(ToString::to_string),
+            files: payload
+                .inner
+                .iter()
+                .map(|inner| inner.name.clone())
+                .collect(),
+            lang,
+            author: payload.author.clone(),
+            description: payload.description.clone(),
+            timestamp: now(),
+            backend,
+            server: None,
+            direction,
+            // Malformed expiry timestamps are treated as already expired, see `Gistit::is_expired`
+            expires_at: payload
+                .expires_at
+                .as_ref()
+                .map(|expires_at| expires_at.parse().unwrap_or_default()),
+        }
+    }
+
+    #[must_use]
+    pub fn with_server(mut self, profile: &str) -> Self {
+        self.server = Some(profile.to_owned());
+        self
+    }
+}
+
+/// Narrows down `gistit log`
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Unix timestamp in milliseconds
+    pub since: Option<u64>,
+    pub lang: Option<String>,
+    pub direction: Option<Direction>,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.since.map_or(true, |since| entry.timestamp >= since)
+            && self.lang.as_ref().map_or(true, |lang| {
+                entry
+                    .lang
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(lang))
+            })
+            && self
+                .direction
+                .map_or(true, |direction| entry.direction == direction)
+    }
+}
+
+/// Appends an entry and keeps `payload` around, unless it's `None` for a one-shot gistit.
+/// Payloads that expired since are dropped
+///
+/// # Errors
+///
+/// Fails if the data directory can't be written
+pub fn record(data_path: &Path, payload: Option<&Gistit>, entry: &Entry) -> Result<()> {
+    prune(data_path)?;
+    if let Some(payload) = payload {
+        let payloads_path = data_path.join(HISTORY_PAYLOADS_DIR);
+        fs::create_dir_all(&payloads_path)?;
+        fs::write(
+            payload_path(data_path, &entry.hash),
+            payload.encode_to_vec(),
+        )?;
+    }
+
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    // It may hold keys
+    crypt::private_file()
+        .create(true)
+        .append(true)
+        .open(data_path.join(HISTORY_FILE))?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads all entries, oldest first. Lines that can't be parsed are skipped
+///
+/// # Errors
+///
+/// Fails if the history exists but can't be read
+pub fn read(data_path: &Path) -> Result<Vec<Entry>> {
+    let contents = match fs::read_to_string(data_path.join(HISTORY_FILE)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Removes the payloads of expired gistits
+fn prune(data_path: &Path) -> Result<()> {
+    let now = now();
+    for entry in read(data_path)? {
+        if entry
+            .expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+        {
+            match fs::remove_file(payload_path(data_path, &entry.hash)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads the payload kept for an entry
+///
+/// # Errors
+///
+/// Fails if the payload is missing or corrupted
+pub fn payload(data_path: &Path, entry: &Entry) -> Result<Gistit> {
+    Ok(Gistit::from_bytes(fs::read(payload_path(
+        data_path,
+        &entry.hash,
+    ))?)?)
+}
+
+/// The payload of the latest entry for `hash`, `None` if it was never sent or fetched
+#[must_use]
+pub fn cached(data_path: &Path, hash: &str) -> Option<Gistit> {
+    read(data_path)
+        .ok()?
+        .iter()
+        .rev()
+        .filter(|entry| entry.hash == hash)
+        .find_map(|entry| payload(data_path, entry).ok())
+}
+
+fn payload_path(data_path: &Path, hash: &str) -> PathBuf {
+    data_path
+        .join(HISTORY_PAYLOADS_DIR)
+        .join(format!("{}.gistit", hash))
+}
+
+/// Parses a `YYYY-MM-DD` date into a unix timestamp in milliseconds, at midnight UTC
+#[must_use]
+pub fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day)
+    {
+        return None;
+    }
+
+    u64::try_from(days_from_civil(year, month, day))
+        .ok()
+        .map(|days| days * MILLIS_PER_DAY)
+}
+
+/// Formats a unix timestamp in milliseconds as `YYYY-MM-DD HH:MM`, in UTC
+#[must_use]
+pub fn fmt_date(timestamp: u64) -> String {
+    #[allow(clippy::cast_possible_wrap)]
+    let (year, month, day) = civil_from_days((timestamp / MILLIS_PER_DAY) as i64);
+    let minutes = timestamp % MILLIS_PER_DAY / 60_000;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+const fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's `days_from_civil`, days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// And its inverse, `civil_from_days`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Milliseconds since the unix epoch, what gistit timestamps count
+///
+/// # Panics
+///
+/// If the system clock is set before the epoch
+#[allow(clippy::cast_possible_truncation)]
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Check your system time")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_gistit() -> Gistit {
+        let data = "fn main() { println!(\"hello\"); }".to_owned();
+        Gistit::new(
+            "a".repeat(64),
+            "Matthew McConaughey".to_owned(),
+            Some("alright alright".to_owned()),
+            "0".to_owned(),
+            vec![
+                Gistit::new_inner(
+                    "main.rs".to_owned(),
+                    "rust".to_owned(),
+                    data.len() as u32,
+                    data.clone(),
+                ),
+                Gistit::new_inner(
+                    "src/lib.rs".to_owned(),
+                    "rust".to_owned(),
+                    data.len() as u32,
+                    data,
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn history_record_and_read() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        assert!(read(tmp.path()).unwrap().is_empty());
+
+        let gistit = test_gistit();
+        let sent = Entry::new(&gistit, Direction::Sent, Backend::P2p, None);
+        let fetched = Entry::new(
+            &gistit,
+            Direction::Fetched,
+            Backend::Github("https://gist.github.com/rust".to_owned()),
+            Some(&SecretKey::generate()),
+        );
+        record(tmp.path(), Some(&gistit), &sent).unwrap();
+        record(tmp.path(), Some(&gistit), &fetched).unwrap();
+
+        let entries = read(tmp.path()).unwrap();
+        assert_eq!(entries, vec![sent.clone(), fetched]);
+        assert_eq!(entries[0].files, vec!["main.rs", "src/lib.rs"]);
+        assert_eq!(entries[0].lang, vec!["rust"]);
+        assert_eq!(payload(tmp.path(), &sent).unwrap(), test_gistit());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(tmp.path().join(HISTORY_FILE)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn history_drops_expired_and_one_shot_payloads() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+
+        let mut expiring = test_gistit();
+        expiring.expires_at = Some((now() - 1).to_string());
+        let expired = Entry::new(&expiring, Direction::Fetched, Backend::P2p, None);
+        record(tmp.path(), Some(&expiring), &expired).unwrap();
+        assert!(payload(tmp.path(), &expired).is_ok());
+
+        let mut once = test_gistit();
+        once.hash = "b".repeat(64);
+        let sent = Entry::new(&once, Direction::Sent, Backend::P2p, None);
+        record(tmp.path(), None, &sent).unwrap();
+
+        assert_eq!(
+            read(tmp.path()).unwrap(),
+            vec![expired.clone(), sent.clone()]
+        );
+        assert!(payload(tmp.path(), &expired).is_err());
+        assert!(payload(tmp.path(), &sent).is_err());
+    }
+
+    #[test]
+    fn history_filter() {
+        let entry = Entry::new(&test_gistit(), Direction::Sent, Backend::Server, None);
+
+        assert!(Filter::default().matches(&entry));
+        assert!(Filter {
+            since: Some(entry.timestamp),
+            lang: Some("Rust".to_owned()),
+            direction: Some(Direction::Sent),
+        }
+        .matches(&entry));
+        assert!(!Filter {
+            since: Some(entry.timestamp + 1),
+            ..Filter::default()
+        }
+        .matches(&entry));
+        assert!(!Filter {
+            lang: Some("python".to_owned()),
+            ..Filter::default()
+        }
+        .matches(&entry));
+        assert!(!Filter {
+            direction: Some(Direction::Fetched),
+            ..Filter::default()
+        }
+        .matches(&entry));
+    }
+
+    #[test]
+    fn history_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2022-03-01"), Some(1_646_092_800_000));
+        assert_eq!(parse_date("2024-02-29"), Some(1_709_164_800_000));
+        for invalid in [
+            "",
+            "2022",
+            "2022-13-01",
+            "2023-02-29",
+            "1969-12-31",
+            "2022-1-x",
+        ] {
+            assert_eq!(parse_date(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(fmt_date(0), "1970-01-01 00:00");
+        assert_eq!(
+            fmt_date(1_646_092_800_000 + 61 * 60_000),
+            "2022-03-01 01:01"
+        );
+        assert_eq!(fmt_date(1_709_164_800_000), "2024-02-29 00:00");
+    }
+}
@@ -0,0 +1,276 @@
+//! The lang module
+//!
+//! Detects the language of a file, which becomes the `lang` of the gistit and picks the syntax of
+//! previews. Well-known file names come first, then the extension, the shebang line and editor
+//! modelines. Stdin has no name to go by, so its contents are guessed at as a last resort.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use phf::{phf_map, Map};
+
+use crate::file::EXTENSION_TO_LANG_MAPPING;
+
+/// The language of files with nothing else to go by
+pub const DEFAULT_LANG: &str = "text";
+
+/// The language of binary files whose name says nothing
+pub const BINARY_LANG: &str = "binary";
+
+/// Files recognized by their whole name, looked at before the extension
+pub const FILENAME_TO_LANG_MAPPING: Map<&'static str, &'static str> = phf_map! {
+    "Dockerfile" => "docker",
+    "Containerfile" => "docker",
+    "Makefile" => "makefile",
+    "makefile" => "makefile",
+    "GNUmakefile" => "makefile",
+    "Justfile" => "makefile",
+    "justfile" => "makefile",
+    "CMakeLists.txt" => "cmake",
+    "Cargo.lock" => "toml",
+    "Pipfile" => "toml",
+    "Gemfile" => "ruby",
+    "Rakefile" => "ruby",
+    "Vagrantfile" => "ruby",
+    "Jenkinsfile" => "groovy",
+    "PKGBUILD" => "bash",
+    ".bashrc" => "bash",
+    ".bash_profile" => "bash",
+    ".profile" => "bash",
+    ".zshrc" => "bash",
+};
+
+/// Interpreters found in shebang lines, version suffixes are dropped before looking them up
+const INTERPRETER_TO_LANG_MAPPING: Map<&'static str, &'static str> = phf_map! {
+    "sh" => "bash",
+    "bash" => "bash",
+    "dash" => "bash",
+    "ksh" => "bash",
+    "zsh" => "bash",
+    "python" => "python",
+    "pypy" => "python",
+    "node" => "javascript",
+    "nodejs" => "javascript",
+    "deno" => "typescript",
+    "ts-node" => "typescript",
+    "ruby" => "ruby",
+    "perl" => "perl",
+    "php" => "php",
+    "lua" => "lua",
+    "Rscript" => "r",
+    "julia" => "julia",
+    "pwsh" => "powershell",
+    "make" => "makefile",
+    "groovy" => "groovy",
+};
+
+/// How many lines at each end of a file may hold a modeline
+const MODELINE_LINES: usize = 5;
+
+/// The language of a file named `name` holding `data`, without guessing from its contents
+#[must_use]
+pub fn detect(name: &str, data: &str) -> &'static str {
+    from_name(name)
+        .or_else(|| from_shebang(data))
+        .or_else(|| from_modeline(data))
+        .unwrap_or(DEFAULT_LANG)
+}
+
+/// The language of a file by its name or extension, `None` if that says nothing
+#[must_use]
+pub fn from_name(name: &str) -> Option<&'static str> {
+    let path = Path::new(name);
+    let file_name = path.file_name().and_then(OsStr::to_str)?;
+
+    FILENAME_TO_LANG_MAPPING
+        .get(file_name)
+        .copied()
+        .or_else(|| {
+            let ext = path.extension().and_then(OsStr::to_str)?;
+            EXTENSION_TO_LANG_MAPPING
+                .get(ext)
+                .or_else(|| EXTENSION_TO_LANG_MAPPING.get(&ext.to_lowercase()))
+                .copied()
+                .filter(|lang| *lang != DEFAULT_LANG)
+        })
+}
+
+/// A known language by its name or one of its extensions, as given to `--lang`
+#[must_use]
+pub fn parse(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    let lower = value.to_lowercase();
+
+    EXTENSION_TO_LANG_MAPPING
+        .values()
+        .find(|lang| lang.eq_ignore_ascii_case(value))
+        .or_else(|| EXTENSION_TO_LANG_MAPPING.get(&lower))
+        .or_else(|| INTERPRETER_TO_LANG_MAPPING.get(value))
+        .copied()
+}
+
+/// An extension of `lang`, the shortest one, so tools detecting by name can be pointed at it
+#[must_use]
+pub fn extension(lang: &str) -> Option<&'static str> {
+    EXTENSION_TO_LANG_MAPPING
+        .entries()
+        .filter(|(ext, other)| **other == lang && !ext.is_empty())
+        .map(|(ext, _)| *ext)
+        .min_by_key(|ext| (ext.len(), *ext))
+}
+
+/// `#!/usr/bin/env python3 -u` and the likes
+fn from_shebang(data: &str) -> Option<&'static str> {
+    let line = data.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+
+    let mut program = Path::new(words.next()?).file_name()?.to_str()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+
+    INTERPRETER_TO_LANG_MAPPING
+        .get(program)
+        .copied()
+        .or_else(|| {
+            let unversioned = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+            INTERPRETER_TO_LANG_MAPPING.get(unversioned).copied()
+        })
+}
+
+/// Vim `vim: set ft=python:` and Emacs `-*- mode: python -*-` modelines, at either end of the file
+fn from_modeline(data: &str) -> Option<&'static str> {
+    let lines: Vec<&str> = data.lines().collect();
+    let head = lines.iter().take(MODELINE_LINES);
+    let tail = lines.iter().skip(MODELINE_LINES).rev().take(MODELINE_LINES);
+
+    head.chain(tail)
+        .find_map(|line| vim_modeline(line).or_else(|| emacs_modeline(line)))
+        .and_then(parse)
+}
+
+fn vim_modeline(line: &str) -> Option<&str> {
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .find_map(|marker| line.find(marker).map(|i| i + marker.len()))?;
+
+    line[start..]
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax").then(|| value)
+        })
+}
+
+fn emacs_modeline(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (inner, _) = rest.split_once("-*-")?;
+
+    if inner.contains(':') {
+        inner.split(';').find_map(|variable| {
+            let (key, value) = variable.split_once(':')?;
+            (key.trim() == "mode").then(|| value.trim())
+        })
+    } else {
+        Some(inner.trim())
+    }
+}
+
+/// A lightweight look at the contents of stdin once [`detect`] came up empty, `None` unless it's
+/// fairly obvious
+#[must_use]
+pub fn guess(data: &str) -> Option<&'static str> {
+    let trimmed = data.trim_start();
+    let has_line = |predicate: fn(&str) -> bool| data.lines().any(predicate);
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(data).is_ok()
+    {
+        Some("json")
+    } else if trimmed.starts_with("<?php") {
+        Some("php")
+    } else if trimmed.starts_with("<?xml") {
+        Some("xmlDoc")
+    } else if trimmed.starts_with("diff --git")
+        || (trimmed.starts_with("--- ") && data.contains("\n+++ "))
+    {
+        Some("diff")
+    } else if has_line(|line| {
+        line.starts_with("fn ") || line.starts_with("use std::") || line.starts_with("pub fn ")
+    }) {
+        Some("rust")
+    } else if has_line(|line| line.starts_with("package "))
+        && has_line(|line| line.starts_with("func "))
+    {
+        Some("go")
+    } else if has_line(|line| line.starts_with("#include ")) {
+        Some("cpp")
+    } else if has_line(|line| line.starts_with("def ") && line.trim_end().ends_with(':')) {
+        Some("python")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lang_from_well_known_names() {
+        assert_eq!(from_name("Dockerfile"), Some("docker"));
+        assert_eq!(from_name("docker/Dockerfile"), Some("docker"));
+        assert_eq!(from_name("Makefile"), Some("makefile"));
+        assert_eq!(from_name("CMakeLists.txt"), Some("cmake"));
+        assert_eq!(from_name("main.rs"), Some("rust"));
+        assert_eq!(from_name("MAIN.RS"), Some("rust"));
+        assert_eq!(from_name("notes.txt"), None);
+        assert_eq!(from_name("deploy"), None);
+    }
+
+    #[test]
+    fn lang_from_shebangs_and_modelines() {
+        assert_eq!(detect("deploy", "#!/bin/sh\necho hi\n"), "bash");
+        assert_eq!(detect("run", "#!/usr/bin/env python3.10 -u\n"), "python");
+        assert_eq!(detect("run", "#!/usr/bin/env -S deno run\n"), "typescript");
+        assert_eq!(detect("conf", "# vim: set ft=yaml:\nkey: value\n"), "yaml");
+        assert_eq!(
+            detect("conf", "; -*- mode: lisp; tab-width: 2 -*-\n"),
+            "lisp"
+        );
+        assert_eq!(
+            detect("conf", "a\nb\nc\nd\ne\nf\n# vi: filetype=toml\n"),
+            "toml"
+        );
+        assert_eq!(detect("notes", "just some words\n"), DEFAULT_LANG);
+        // The name wins
+        assert_eq!(detect("main.rs", "#!/bin/sh\n"), "rust");
+    }
+
+    #[test]
+    fn lang_guess_stdin() {
+        assert_eq!(guess("{\"alright\": [1, 2, 3]}\n"), Some("json"));
+        assert_eq!(
+            guess("fn main() {\n    println!(\"alright\");\n}\n"),
+            Some("rust")
+        );
+        assert_eq!(guess("package main\n\nfunc main() {}\n"), Some("go"));
+        assert_eq!(guess("def alright():\n    pass\n"), Some("python"));
+        assert_eq!(guess("{ not json"), None);
+        assert_eq!(
+            guess("total 0\ndrwxr-xr-x  2 root root  40 Jan  1 00:00 .\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn lang_parse_and_extension() {
+        assert_eq!(parse("Rust"), Some("rust"));
+        assert_eq!(parse("rs"), Some("rust"));
+        assert_eq!(parse("sh"), Some("bash"));
+        assert_eq!(parse("klingon"), None);
+        assert_eq!(extension("rust"), Some("rs"));
+        assert_eq!(extension("docker"), Some("dockerfile"));
+        assert_eq!(extension("klingon"), None);
+    }
+}
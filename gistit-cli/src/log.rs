@@ -0,0 +1,175 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+use gistit_project::settings::Settings;
+
+use crate::crypt::SecretKey;
+use crate::dispatch::Dispatch;
+use crate::fetch;
+use crate::history::{self, Direction, Entry, Filter};
+use crate::param::check;
+use crate::sign::{self, Verification};
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub since: Option<&'static str>,
+    pub lang: Option<&'static str>,
+    pub direction: Option<&'static str>,
+    pub show: Option<&'static str>,
+    pub colorscheme: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            since: args.value_of("since"),
+            lang: args.value_of("lang"),
+            direction: args.value_of("direction"),
+            show: args.value_of("show"),
+            colorscheme: args.value_of("colorscheme"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// Newest first
+    entries: Vec<Entry>,
+    filter: Filter,
+    show: Option<usize>,
+    colorscheme: String,
+    config_path: PathBuf,
+    data_path: PathBuf,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        let since = if let Some(value) = self.since {
+            Some(check::since(value, history::now())?)
+        } else {
+            None
+        };
+        let direction = match self.direction {
+            Some("sent") => Some(Direction::Sent),
+            Some("fetched") => Some(Direction::Fetched),
+            Some(_) => return Err(Error::Argument("invalid direction", "--direction")),
+            None => None,
+        };
+        let show = if let Some(value) = self.show {
+            Some(
+                value
+                    .parse()
+                    .map_err(|_| Error::Argument("invalid history entry", "--show"))?,
+            )
+        } else {
+            None
+        };
+
+        let config_path = path::config()?;
+        let settings = Settings::load(&config_path)?;
+        let colorscheme = self
+            .colorscheme
+            .map(ToOwned::to_owned)
+            .or_else(|| settings.text("colorscheme"))
+            .unwrap_or_default();
+
+        let data_path = path::data()?;
+        let mut entries = history::read(&data_path)?;
+        entries.reverse();
+
+        Ok(Config {
+            entries,
+            filter: Filter {
+                since,
+                lang: self.lang.map(ToOwned::to_owned),
+                direction,
+            },
+            show,
+            colorscheme: check::colorscheme(&colorscheme)?.to_owned(),
+            config_path,
+            data_path,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        if let Some(n) = config.show {
+            return show(n, &config);
+        }
+
+        // Numbered over the whole history so `--show` picks the same entry regardless of filters
+        for (n, entry) in config
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| config.filter.matches(entry))
+        {
+            println!("{}", fmt_entry(n + 1, entry));
+        }
+        Ok(())
+    }
+}
+
+fn show(n: usize, config: &Config) -> Result<()> {
+    let entry = n
+        .checked_sub(1)
+        .and_then(|i| config.entries.get(i))
+        .ok_or(Error::Argument("no such history entry", "--show"))?;
+
+    progress!("Opening");
+    let payload = fetch::unexpired(history::payload(&config.data_path, entry)?)?;
+
+    let verification = sign::verify(&payload);
+    if let Verification::Invalid(_) = verification {
+        warnln!("{}", style(&verification).red().bold());
+    }
+
+    let key = entry
+        .key
+        .as_deref()
+        .map(str::parse::<SecretKey>)
+        .transpose()?;
+    let gistit = fetch::decrypt(payload, key.as_ref(), &config.config_path)?;
+    updateln!("Opened");
+
+    finish!("👀  Preview");
+    fetch::preview(
+        &gistit,
+        &fetch::fmt_signer(&verification, &config.config_path),
+        &config.colorscheme,
+    )
+}
+
+fn fmt_entry(n: usize, entry: &Entry) -> String {
+    let mut line = format!(
+        "{:>4}  {}  {:<7}  {}  {}  {}  {}",
+        n,
+        style(history::fmt_date(entry.timestamp)).dim(),
+        entry.direction,
+        style(&entry.hash[..entry.hash.len().min(12)]).bold(),
+        style(entry.lang.join(",")).cyan(),
+        entry.files.join(", "),
+        style(&entry.author).blue(),
+    );
+
+    if let Some(ref description) = entry.description {
+        line.push_str(&format!(" | {}", style(description).italic()));
+    }
+    match entry.server {
+        Some(ref server) => line.push_str(&format!(
+            " | {}",
+            style(format!("{} {}", entry.backend, server)).dim()
+        )),
+        None => line.push_str(&format!(" | {}", style(&entry.backend).dim())),
+    }
+    line
+}
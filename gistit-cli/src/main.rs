@@ -22,20 +22,29 @@
 )]
 
 mod arg;
+mod backend;
+mod config;
 mod dispatch;
 mod fetch;
 mod fmt;
+mod log;
 mod node;
 mod param;
 mod send;
 mod stdin;
+mod verify;
+mod whoami;
 
 pub mod clipboard;
+pub mod crypt;
 pub mod error;
 pub mod file;
 pub mod github;
+pub mod history;
+pub mod lang;
 pub mod patch;
 pub mod server;
+pub mod sign;
 
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -72,18 +81,33 @@ async fn run() -> Result<()> {
             let payload = action.prepare().await?;
             action.dispatch(payload).await?;
         }
+        ("verify", Some(args)) => {
+            let action = verify::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("log", Some(args)) => {
+            let action = log::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("config", Some(args)) => {
+            let action = config::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("whoami", Some(args)) => {
+            let action = whoami::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
         ("node", Some(args)) => {
             let action = node::Action::from_args(args)?;
             let payload = action.prepare().await?;
             action.dispatch(payload).await?;
         }
         _ => {
-            let default_action = if matches.is_present("FILE") {
-                send::Action::from_args(matches, None)?
-            } else {
-                let stdin = stdin::read_to_end();
-                send::Action::from_args(matches, Some(stdin))?
-            };
+            let default_action = send::Action::from_args(matches)?;
 
             let payload = default_action.prepare().await?;
             default_action.dispatch(payload).await?;
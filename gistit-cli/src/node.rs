@@ -9,14 +9,20 @@ use async_trait::async_trait;
 use clap::ArgMatches;
 use console::style;
 
+use gistit_ipc::{Bridge, Client};
 use gistit_project::path;
 use gistit_proto::{ipc, Instruction};
 
 use crate::arg::app;
 use crate::dispatch::Dispatch;
+use crate::fetch::fmt_lifetime;
+use crate::history;
 use crate::param::check;
 use crate::{cleanln, errorln, finish, interruptln, progress, updateln, Error, Result};
 
+/// How long `gistit node` waits on the node by default
+const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Action {
@@ -24,10 +30,14 @@ pub struct Action {
     pub stop: bool,
     pub status: bool,
     pub attach: bool,
+    pub hosting: bool,
+    pub unhost: Option<&'static str>,
+    pub republish: bool,
+    pub timeout: Option<&'static str>,
     // Hidden args
     dial: Option<&'static str>,
-    host: &'static str,
-    port: &'static str,
+    host: Option<&'static str>,
+    port: Option<&'static str>,
 }
 
 impl Action {
@@ -39,13 +49,13 @@ impl Action {
             stop: args.is_present("stop"),
             status: args.is_present("status"),
             attach: args.is_present("attach"),
+            hosting: args.is_present("hosting"),
+            unhost: args.value_of("unhost"),
+            republish: args.is_present("republish"),
+            timeout: args.value_of("timeout"),
             dial: args.value_of("dial"),
-            host: args
-                .value_of("host")
-                .ok_or(Error::Argument("missing argument", "--host"))?,
-            port: args
-                .value_of("port")
-                .ok_or(Error::Argument("missing argument", "--host"))?,
+            host: args.value_of("host"),
+            port: args.value_of("port"),
         }))
     }
 }
@@ -57,12 +67,17 @@ enum ProcessCommand {
     Stop,
     Attach,
     Dial(&'static str),
+    Hosting,
+    Unhost(&'static str),
+    Republish,
 }
 
 pub struct Config {
     commands: Vec<ProcessCommand>,
-    host: &'static str,
-    port: &'static str,
+    /// Unless given, the node uses the `daemon.host` and `daemon.port` settings
+    host: Option<&'static str>,
+    port: Option<&'static str>,
+    timeout: Duration,
     runtime_path: PathBuf,
     config_path: PathBuf,
 }
@@ -74,74 +89,104 @@ impl Dispatch for Action {
     async fn prepare(&self) -> Result<Self::InnerData> {
         progress!("Preparing");
         let mut commands: Vec<ProcessCommand> = Vec::new();
-        match (self.start, self.stop, self.status, self.attach, self.dial) {
-            // Matching:
-            // - start
-            // - start [attach]
-            // - start [dial]
-            // - start [attach] [dial]
-            (true, false, false, attach, dial) => {
-                commands.push(ProcessCommand::Start);
-
-                if let Some(addr) = dial {
-                    commands.push(ProcessCommand::Dial(addr));
+        let management = if self.hosting {
+            Some(ProcessCommand::Hosting)
+        } else if let Some(hash) = self.unhost {
+            Some(ProcessCommand::Unhost(check::hash(hash)?))
+        } else if self.republish {
+            Some(ProcessCommand::Republish)
+        } else {
+            None
+        };
+
+        if let Some(command) = management {
+            // Hosting management doesn't combine with anything else
+            commands.push(command);
+        } else {
+            match (self.start, self.stop, self.status, self.attach, self.dial) {
+                // Matching:
+                // - start
+                // - start [attach]
+                // - start [dial]
+                // - start [attach] [dial]
+                (true, false, false, attach, dial) => {
+                    commands.push(ProcessCommand::Start);
+
+                    if let Some(addr) = dial {
+                        commands.push(ProcessCommand::Dial(addr));
+                    }
+
+                    if attach {
+                        commands.push(ProcessCommand::Attach);
+                    }
                 }
+                // Matching:
+                // - status
+                // - status [attach]
+                // - status [dial]
+                // - status [attach] [dial]
+                (false, false, true, attach, dial) => {
+                    commands.push(ProcessCommand::Status);
+
+                    if let Some(addr) = dial {
+                        commands.push(ProcessCommand::Dial(addr));
+                    }
 
-                if attach {
+                    if attach {
+                        commands.push(ProcessCommand::Attach);
+                    }
+                }
+                // Matching:
+                // - attach
+                // - attach [dial]
+                (false, false, false, true, dial) => {
                     commands.push(ProcessCommand::Attach);
+
+                    if let Some(addr) = dial {
+                        commands.push(ProcessCommand::Dial(addr));
+                    }
                 }
-            }
-            // Matching:
-            // - status
-            // - status [attach]
-            // - status [dial]
-            // - status [attach] [dial]
-            (false, false, true, attach, dial) => {
-                commands.push(ProcessCommand::Status);
-
-                if let Some(addr) = dial {
+                // Matching:
+                // - dial
+                // - dial [attach]
+                (false, false, false, attach, Some(addr)) => {
                     commands.push(ProcessCommand::Dial(addr));
-                }
 
-                if attach {
-                    commands.push(ProcessCommand::Attach);
+                    if attach {
+                        commands.push(ProcessCommand::Attach);
+                    }
                 }
-            }
-            // Matching:
-            // - attach
-            // - attach [dial]
-            (false, false, false, true, dial) => {
-                commands.push(ProcessCommand::Attach);
-
-                if let Some(addr) = dial {
-                    commands.push(ProcessCommand::Dial(addr));
+                // Matching:
+                // - stop
+                (false, true, false, false, None) => commands.push(ProcessCommand::Stop),
+                // No match. Clap should not let this branch happen
+                (_, _, _, _, _) => {
+                    app().print_help()?;
+                    std::process::exit(1);
                 }
             }
-            // Matching:
-            // - dial
-            // - dial [attach]
-            (false, false, false, attach, Some(addr)) => {
-                commands.push(ProcessCommand::Dial(addr));
+        }
 
-                if attach {
-                    commands.push(ProcessCommand::Attach);
-                }
-            }
-            // Matching:
-            // - stop
-            (false, true, false, false, None) => commands.push(ProcessCommand::Stop),
-            // No match. Clap should not let this branch happen
-            (_, _, _, _, _) => {
-                app().print_help()?;
-                std::process::exit(1);
-            }
+        let host = if let Some(value) = self.host {
+            Some(check::host(value)?)
+        } else {
+            None
+        };
+        let port = if let Some(value) = self.port {
+            Some(check::port(value)?)
+        } else {
+            None
+        };
+        let timeout = if let Some(value) = self.timeout {
+            check::timeout(value)?
+        } else {
+            NODE_TIMEOUT
         };
-
-        let (host, port) = check::host_port(self.host, self.port)?;
         let config = Config {
             commands,
             host,
             port,
+            timeout,
             runtime_path: path::runtime()?,
             config_path: path::config()?,
         };
@@ -159,10 +204,16 @@ impl Dispatch for Action {
                 ProcessCommand::Start => {
                     if bridge.alive() {
                         bridge.connect_blocking()?;
-                        bridge.send(Instruction::request_status()).await?;
+                        let response = request(
+                            &mut bridge,
+                            Instruction::request_status(),
+                            "requesting status",
+                            config.timeout,
+                        )
+                        .await?;
 
                         if let ipc::instruction::Kind::StatusResponse(response) =
-                            bridge.recv().await?.expect_response()?
+                            response.expect_response()?
                         {
                             format_daemon_status(&response);
                         }
@@ -176,9 +227,14 @@ impl Dispatch for Action {
                         // FIXME: Fix this before release
                         let daemon = "gistit-daemon";
 
-                        Command::new(daemon)
-                            .args(&["--host", config.host])
-                            .args(&["--port", config.port])
+                        let mut command = Command::new(daemon);
+                        if let Some(host) = config.host {
+                            command.args(["--host", host]);
+                        }
+                        if let Some(port) = config.port {
+                            command.args(["--port", port]);
+                        }
+                        command
                             .args(&["--runtime-path", &*config.runtime_path.to_string_lossy()])
                             .args(&["--config-path", &*config.config_path.to_string_lossy()])
                             .arg("--bootstrap")
@@ -190,11 +246,17 @@ impl Dispatch for Action {
 
                     updateln!("Gistit node started, pid: {}", style(pid).blue());
                     bridge.connect_blocking()?;
-                    bridge.send(Instruction::request_status()).await?;
+                    let response = request(
+                        &mut bridge,
+                        Instruction::request_status(),
+                        "starting the node",
+                        config.timeout,
+                    )
+                    .await?;
 
                     if let ipc::instruction::Kind::StatusResponse(
                         ipc::instruction::StatusResponse { peer_id, .. },
-                    ) = bridge.recv().await?.expect_response()?
+                    ) = response.expect_response()?
                     {
                         cleanln!(format!("\n    peer id: '{}'\n\n", style(peer_id).bold()));
                     }
@@ -220,10 +282,16 @@ impl Dispatch for Action {
                     progress!("Requesting status");
                     if bridge.alive() {
                         bridge.connect_blocking()?;
-                        bridge.send(Instruction::request_status()).await?;
+                        let response = request(
+                            &mut bridge,
+                            Instruction::request_status(),
+                            "requesting status",
+                            config.timeout,
+                        )
+                        .await?;
 
                         if let ipc::instruction::Kind::StatusResponse(response) =
-                            bridge.recv().await?.expect_response()?
+                            response.expect_response()?
                         {
                             format_daemon_status(&response);
                         }
@@ -249,6 +317,86 @@ impl Dispatch for Action {
                     }
                 }
 
+                ProcessCommand::Hosting => {
+                    progress!("Requesting hosted gistits");
+                    if bridge.alive() {
+                        bridge.connect_blocking()?;
+                        let response = request(
+                            &mut bridge,
+                            Instruction::request_hosting(),
+                            "listing hosted gistits",
+                            config.timeout,
+                        )
+                        .await?;
+
+                        if let ipc::instruction::Kind::HostingResponse(response) =
+                            response.expect_response()?
+                        {
+                            format_hosted(&response.hosted);
+                        }
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(1);
+                    }
+                }
+
+                ProcessCommand::Unhost(hash) => {
+                    progress!("Unhosting");
+                    if bridge.alive() {
+                        bridge.connect_blocking()?;
+                        let response = request(
+                            &mut bridge,
+                            Instruction::request_unhost((*hash).to_string()),
+                            "unhosting",
+                            config.timeout,
+                        )
+                        .await?;
+
+                        if let ipc::instruction::Kind::UnhostResponse(
+                            ipc::instruction::UnhostResponse { unhosted },
+                        ) = response.expect_response()?
+                        {
+                            if unhosted {
+                                updateln!("Unhosted");
+                            } else {
+                                interruptln!();
+                                errorln!("gistit is not hosted by this node");
+                                std::process::exit(1);
+                            }
+                        }
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(1);
+                    }
+                }
+
+                ProcessCommand::Republish => {
+                    progress!("Republishing");
+                    if bridge.alive() {
+                        bridge.connect_blocking()?;
+                        let response = request(
+                            &mut bridge,
+                            Instruction::request_republish(),
+                            "republishing",
+                            config.timeout,
+                        )
+                        .await?;
+
+                        if let ipc::instruction::Kind::RepublishResponse(
+                            ipc::instruction::RepublishResponse { count },
+                        ) = response.expect_response()?
+                        {
+                            updateln!("Republished {} gistit", count);
+                        }
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(1);
+                    }
+                }
+
                 ProcessCommand::Attach => {
                     attach_to_log(
                         &config.runtime_path,
@@ -265,29 +413,89 @@ impl Dispatch for Action {
     }
 }
 
+/// Sends a request to the node and waits for its response. Gives up after `timeout` or on
+/// `CTRL-C`, telling the node to drop the request
+pub async fn request(
+    bridge: &mut Bridge<Client>,
+    instruction: Instruction,
+    phase: &'static str,
+    timeout: Duration,
+) -> Result<Instruction> {
+    let id = bridge.send(instruction).await?;
+
+    let err = tokio::select! {
+        response = bridge.recv() => return Ok(response?),
+        () = tokio::time::sleep(timeout) => Error::Timeout(phase),
+        _ = tokio::signal::ctrl_c() => Error::Interrupted(phase),
+    };
+
+    bridge.send(Instruction::request_cancel(id)).await?;
+    Err(err)
+}
+
 fn format_daemon_status(response: &ipc::instruction::StatusResponse) {
     let ipc::instruction::StatusResponse {
         peer_id,
         peer_count,
         pending_connections,
         hosting,
+        once,
     } = response;
 
     updateln!("Running status");
     finish!(format!(
         r#"
     peer id: '{}'
-    hosting: {} gistit
+    hosting: {} gistit ({} waiting to be read once)
     peers: {}
     pending connections: {}
         "#,
         style(peer_id).bold(),
         hosting,
+        once,
         style(peer_count).blue(),
         pending_connections,
     ));
 }
 
+fn format_hosted(hosted: &[ipc::instruction::Hosted]) {
+    updateln!("Hosting {} gistit", hosted.len());
+
+    let now = u128::from(history::now());
+
+    let mut list = String::new();
+    for gistit in hosted {
+        let age = gistit.timestamp.parse::<u128>().map_or_else(
+            |_| "unknown age".to_owned(),
+            |created| format!("{} ago", fmt_lifetime(now.saturating_sub(created))),
+        );
+
+        list.push_str(&format!(
+            "\n    {}\n      {} | {} bytes | {}",
+            style(&gistit.hash).bold(),
+            gistit.names.join(", "),
+            gistit.size,
+            style(age).dim(),
+        ));
+        if gistit.once {
+            list.push_str(&format!(" | {}", style("once").yellow()));
+        }
+        if let Some(remaining) = gistit
+            .expires_at
+            .as_ref()
+            .and_then(|expires_at| expires_at.parse::<u128>().ok())
+            .map(|expires_at| expires_at.saturating_sub(now))
+        {
+            list.push_str(&format!(
+                " | {}",
+                style(format!("expires in {}", fmt_lifetime(remaining))).dim()
+            ));
+        }
+        list.push('\n');
+    }
+    finish!(format!("{}\n", list));
+}
+
 fn attach_to_log(runtime_path: &Path, linked: bool) -> Result<()> {
     let log_path = runtime_path.join("gistit.log");
 
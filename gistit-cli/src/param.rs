@@ -41,15 +41,28 @@ lazy_static! {
 pub mod check {
     use super::{FUZZY_MATCH, SUPPORTED_COLORSCHEMES};
 
-    use std::ffi::OsStr;
     use std::fs;
+    use std::iter;
     use std::net::Ipv4Addr;
     use std::ops::RangeInclusive;
+    use std::time::Duration;
 
-    use crate::file::EXTENSION_TO_LANG_MAPPING;
+    use libp2p_core::PeerId;
+
+    use crate::backend;
+    use crate::fetch;
+    use crate::history;
+    use crate::lang;
     use crate::{Error, Result};
 
-    const ALLOWED_FILE_SIZE_RANGE: RangeInclusive<u64> = 20..=50_000;
+    const MIN_FILE_SIZE: u64 = 20;
+
+    /// File sizes are carried as 32 bits
+    const MAX_FILE_SIZE: u64 = 0xffff_ffff;
+
+    /// How many times bigger than what the backends take files may be before they're read, betting
+    /// on compression
+    const COMPRESSION_MAX_RATIO: u64 = 8;
 
     const ALLOWED_DESCRIPTION_CHAR_LENGHT_RANGE: RangeInclusive<usize> = 10..=100;
 
@@ -57,6 +70,8 @@ pub mod check {
 
     const GISTIT_HASH_CHAR_LENGTH: usize = 64;
 
+    const ALLOWED_EXPIRY_SECS_RANGE: RangeInclusive<u64> = 60..=30 * 24 * 60 * 60;
+
     pub fn description(description: &str) -> Result<&str> {
         if ALLOWED_DESCRIPTION_CHAR_LENGHT_RANGE.contains(&description.len()) {
             Ok(description)
@@ -79,8 +94,10 @@ pub mod check {
         }
     }
 
-    pub fn metadata(attr: &fs::Metadata) -> Result<()> {
-        let size_allowed = ALLOWED_FILE_SIZE_RANGE.contains(&attr.len());
+    /// Files are checked before being read against `max_size`, the limit of the picked backend
+    /// taking the largest gistits: too big if they would have to compress better than we bet on
+    pub fn metadata(attr: &fs::Metadata, max_size: u64) -> Result<()> {
+        let size_allowed = attr.len() >= MIN_FILE_SIZE && attr.len() <= uncompressed_size(max_size);
 
         if size_allowed {
             Ok(())
@@ -89,18 +106,38 @@ pub mod check {
         }
     }
 
-    pub fn extension(ext: Option<&OsStr>) -> Result<()> {
-        let ext = ext
-            .and_then(OsStr::to_str)
-            .ok_or(Error::Argument("file doesn't have an extension", "[FILE]"))?;
+    /// The most worth reading to send `max_size` bytes once compressed, files still tell their
+    /// size in 32 bits
+    pub fn uncompressed_size(max_size: u64) -> u64 {
+        max_size
+            .saturating_mul(COMPRESSION_MAX_RATIO)
+            .min(MAX_FILE_SIZE)
+    }
 
-        if EXTENSION_TO_LANG_MAPPING.contains_key(ext) {
+    /// And once compressed, `carried` bytes
+    pub const fn carried_size(carried: u64, max_size: u64) -> Result<()> {
+        if carried <= max_size {
             Ok(())
         } else {
-            Err(Error::Argument("file extension not supported", "[FILE]"))
+            Err(Error::Argument("file size not allowed", "[FILE]"))
         }
     }
 
+    pub const fn total_size(size: u64, max_size: u64) -> Result<()> {
+        if size <= max_size {
+            Ok(())
+        } else {
+            Err(Error::Argument(
+                "total size of files not allowed",
+                "[FILE]...",
+            ))
+        }
+    }
+
+    pub fn lang(lang: &str) -> Result<&'static str> {
+        lang::parse(lang).ok_or(Error::Argument("unknown language", "--lang"))
+    }
+
     pub fn colorscheme(colorscheme: &str) -> Result<&str> {
         if SUPPORTED_COLORSCHEMES.contains(&colorscheme) {
             Ok(colorscheme)
@@ -123,13 +160,160 @@ pub mod check {
         }
     }
 
-    pub fn host_port<'a, 'b>(host: &'a str, port: &'b str) -> Result<(&'a str, &'b str)> {
+    pub fn host(host: &str) -> Result<&str> {
         let _host: Ipv4Addr = host
             .parse()
             .map_err(|_| Error::Argument("invalid host", "--host"))?;
+        Ok(host)
+    }
+
+    pub fn port(port: &str) -> Result<&str> {
         let _port: u16 = port
             .parse()
             .map_err(|_| Error::Argument("invalid port", "--port"))?;
-        Ok((host, port))
+        Ok(port)
+    }
+
+    /// Parses a lifetime like `30m`, `1h`, `1d` or `7d`
+    pub fn expires(expires: &str) -> Result<Duration> {
+        match lifetime_secs(expires) {
+            Some(secs) if ALLOWED_EXPIRY_SECS_RANGE.contains(&secs) => {
+                Ok(Duration::from_secs(secs))
+            }
+            _ => Err(Error::Argument(
+                "invalid expiry, expected a lifetime between 1m and 30d like '1h', '1d' or '7d'",
+                "--expires",
+            )),
+        }
+    }
+
+    /// Parses a `YYYY-MM-DD` date or a lifetime ago like `12h` or `7d`, relative to `now`
+    pub fn since(since: &str, now: u64) -> Result<u64> {
+        history::parse_date(since)
+            .or_else(|| {
+                lifetime_secs(since).map(|secs| now.saturating_sub(secs.saturating_mul(1000)))
+            })
+            .ok_or(Error::Argument(
+                "invalid date, expected 'YYYY-MM-DD' or a lifetime like '12h' or '7d'",
+                "--since",
+            ))
+    }
+
+    /// Where `gistit fetch` looks, in order. Repeated sources are only tried once
+    pub fn sources(values: &[&str]) -> Result<Vec<&'static str>> {
+        let known: Vec<_> = iter::once(fetch::CACHE).chain(backend::names()).collect();
+        unique(values, &known).ok_or(Error::Argument("unknown source", "--from"))
+    }
+
+    /// Where `gistit` sends to. Repeated backends are only sent to once
+    pub fn via(values: &[&str]) -> Result<Vec<&'static str>> {
+        unique(values, &backend::names()).ok_or(Error::Argument("unknown backend", "--via"))
+    }
+
+    /// Matches each value to a known name, `None` if any is unknown
+    fn unique(values: &[&str], known: &[&'static str]) -> Option<Vec<&'static str>> {
+        let mut names = Vec::with_capacity(values.len());
+        for value in values {
+            let name = *known.iter().find(|name| *name == value)?;
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        Some(names)
+    }
+
+    /// How long to wait on the node, like `30s` or `2m`
+    pub fn timeout(timeout: &str) -> Result<Duration> {
+        match lifetime_secs(timeout) {
+            Some(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+            _ => Err(Error::Argument(
+                "invalid timeout, expected a duration like '30s' or '2m'",
+                "--timeout",
+            )),
+        }
+    }
+
+    fn lifetime_secs(lifetime: &str) -> Option<u64> {
+        if lifetime.len() < 2 || !lifetime.is_char_boundary(lifetime.len() - 1) {
+            return None;
+        }
+
+        let (amount, unit) = lifetime.split_at(lifetime.len() - 1);
+        let amount: u64 = amount.parse().ok()?;
+        match unit {
+            "s" => Some(amount),
+            "m" => amount.checked_mul(60),
+            "h" => amount.checked_mul(60 * 60),
+            "d" => amount.checked_mul(24 * 60 * 60),
+            _ => None,
+        }
+    }
+
+    pub fn peer_id(peer_id: &str) -> Result<PeerId> {
+        peer_id
+            .parse()
+            .map_err(|_| Error::Argument("invalid peer id", "--to"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn param_check_expires() {
+        assert_eq!(check::expires("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(check::expires("1h").unwrap(), Duration::from_secs(60 * 60));
+        assert_eq!(
+            check::expires("7d").unwrap(),
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+
+        for invalid in [
+            "",
+            "h",
+            "1",
+            "0m",
+            "1w",
+            "-1h",
+            "59s",
+            "31d",
+            "1.5h",
+            "99999999999999999999d",
+        ] {
+            assert!(check::expires(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn param_check_timeout() {
+        assert_eq!(check::timeout("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(check::timeout("2m").unwrap(), Duration::from_secs(2 * 60));
+
+        for invalid in ["", "s", "30", "0s", "-1s", "1.5m"] {
+            assert!(check::timeout(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn param_check_sources() {
+        assert_eq!(
+            check::sources(&["server", "cache", "server"]).unwrap(),
+            vec!["server", "cache"]
+        );
+        assert!(check::sources(&["server", "ipfs"]).is_err());
+    }
+
+    #[test]
+    fn param_check_since() {
+        let now = 1_646_092_800_000;
+        assert_eq!(check::since("2022-03-01", now).unwrap(), 1_646_092_800_000);
+        assert_eq!(check::since("1h", now).unwrap(), now - 60 * 60 * 1000);
+        assert_eq!(check::since("99999d", now).unwrap(), 0);
+
+        for invalid in ["", "1w", "yesterday", "2022-02-30"] {
+            assert!(check::since(invalid, now).is_err(), "{}", invalid);
+        }
     }
 }
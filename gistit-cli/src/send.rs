@@ -1,137 +1,355 @@
 use std::ffi::OsStr;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use clap::ArgMatches;
 use console::style;
-use reqwest::StatusCode;
+use libp2p_core::identity::ed25519;
 
-use gistit_proto::payload::{hash, Gistit};
-use gistit_proto::prost::Message;
-use gistit_proto::{ipc, Instruction};
+use gistit_proto::payload::Gistit;
+use gistit_proto::Inner;
 
 use gistit_project::path;
+use gistit_project::settings::Settings;
 
+use crate::backend::{self, Backend, Context, Published};
 use crate::clipboard::Clipboard;
+use crate::crypt::{self, Secret, SecretKey};
 use crate::dispatch::Dispatch;
-use crate::file::File;
-use crate::github::{self, CreateResponse, GITHUB_GISTS_API_URL};
+use crate::file;
+use crate::github;
+use crate::history::{self, Direction, Entry};
+use crate::lang;
 use crate::param::check;
-use crate::server::SERVER_URL_LOAD;
-use crate::{errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
+use crate::server::Profile;
+use crate::sign;
+use crate::stdin;
+use crate::whoami;
+use crate::{cleanln, errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
 
+/// How long to wait on the node to start hosting by default
+const PROVIDE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone)]
 pub struct Action {
-    pub file_path: Option<&'static OsStr>,
-    pub maybe_stdin: Option<String>,
+    pub file_paths: Option<Vec<&'static OsStr>>,
     pub description: Option<&'static str>,
-    pub author: &'static str,
+    pub author: Option<&'static str>,
     pub clipboard: bool,
     pub github: bool,
+    pub encrypt: bool,
+    pub password: bool,
+    pub recipients: Option<Vec<&'static str>>,
+    pub expires: Option<&'static str>,
+    pub once: bool,
+    pub sign: bool,
+    pub via: Option<Vec<&'static str>>,
+    pub server: Option<&'static str>,
+    pub lang: Option<&'static str>,
+    pub timeout: Option<&'static str>,
 }
 
 impl Action {
     pub fn from_args(
         args: &'static ArgMatches,
-        maybe_stdin: Option<String>,
     ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
         Ok(Box::new(Self {
-            file_path: args.value_of_os("FILE"),
-            maybe_stdin,
+            file_paths: args.values_of_os("FILE").map(Iterator::collect),
             description: args.value_of("description"),
-            author: args
-                .value_of("author")
-                .ok_or(Error::Argument("missing argument", "--author"))?,
+            author: args.value_of("author"),
             clipboard: args.is_present("clipboard"),
             github: args.is_present("github"),
+            encrypt: args.is_present("encrypt"),
+            password: args.is_present("password"),
+            recipients: args.values_of("to").map(Iterator::collect),
+            expires: args.value_of("expires"),
+            once: args.is_present("once"),
+            timeout: args.value_of("timeout"),
+            via: args.values_of("via").map(Iterator::collect),
+            server: args.value_of("server"),
+            lang: args.value_of("lang"),
+            sign: args.is_present("sign"),
         }))
     }
 }
 
 #[derive(Debug)]
 pub struct Config {
-    file: File,
-    author: &'static str,
+    /// Already compressed
+    files: Vec<Inner>,
+    author: String,
     description: Option<&'static str>,
     clipboard: bool,
-    github_token: Option<github::Token>,
-    runtime_path: PathBuf,
+    /// Whether the history keeps the key, see `history.keys`
+    history_keys: bool,
+    secret: Option<Secret>,
+    expires: Option<Duration>,
+    signing_key: Option<ed25519::Keypair>,
+    via: Vec<&'static str>,
+    /// Largest gistit every backend picked takes
+    max_size: u64,
+    context: Context,
 }
 
 impl TryFrom<Config> for Gistit {
     type Error = Error;
 
-    #[allow(clippy::cast_possible_truncation)]
     fn try_from(value: Config) -> std::result::Result<Self, Self::Error> {
-        let data = value.file.read()?;
-        let hash = hash(value.author, value.description, &data);
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Check your system time")
-            .as_millis()
-            .to_string();
-
-        let inner = Self::new_inner(
-            value.file.name(),
-            value.file.lang().to_owned(),
-            value.file.size() as u32,
-            data,
-        );
+        // Compressed before encrypting, ciphertext doesn't compress
+        let mut inner = value.files;
+
+        // Encrypt before hashing so the hash never reveals anything about the plaintext
+        let encryption = if let Some(ref secret) = value.secret {
+            let encryption = crypt::encrypt_inner(&mut inner, secret)?;
+            let encrypted_size: usize = inner.iter().map(|file| file.contents().len()).sum();
+
+            check::total_size(encrypted_size as u64, value.max_size).map_err(|_| {
+                Error::Argument(
+                    "total size of files not allowed once encrypted",
+                    "[FILE]...",
+                )
+            })?;
+            Some(encryption)
+        } else {
+            None
+        };
+
+        let now = u128::from(history::now());
 
-        let gistit = Self::new(
-            hash,
-            value.author.to_owned(),
+        let mut gistit = Self::new(
+            String::new(),
+            value.author.clone(),
             value.description.map(ToOwned::to_owned),
-            now,
-            vec![inner],
+            now.to_string(),
+            inner,
         );
+        gistit.encryption = encryption;
+        // Compressed files are hashed inflated, a gist or another compressor gives the same hash
+        gistit.hash = gistit.compute_hash();
+        gistit.expires_at = value
+            .expires
+            .map(|expires| (now + expires.as_millis()).to_string());
+        gistit.signature = value
+            .signing_key
+            .map(|keypair| sign::sign(&gistit, &keypair));
 
         Ok(gistit)
     }
 }
 
+impl Action {
+    /// The backends asked for with `--via` or `backends.send`, `None` to pick among all of them
+    fn requested(&self, settings: &Settings) -> Result<Option<Vec<&'static str>>> {
+        if let Some(ref values) = self.via {
+            Ok(Some(check::via(values)?))
+        } else if let Some(values) = settings.list("backends.send") {
+            Ok(Some(check::via(
+                &values.iter().map(String::as_str).collect::<Vec<_>>(),
+            )?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The backends requested, or every available one taking `size` bytes
+    fn via(
+        &self,
+        candidates: &[&dyn Backend],
+        requested: Option<Vec<&'static str>>,
+        binary: bool,
+        size: u64,
+    ) -> Result<Vec<&'static str>> {
+        let encrypted = self.encrypt || self.password || self.recipients.is_some();
+
+        let via = requested.unwrap_or_else(|| {
+            candidates
+                .iter()
+                .filter(|backend| {
+                    (backend.available()
+                        || (self.github && backend.name() == backend::github::NAME))
+                        && (!self.once || backend.supports_once())
+                        && (!encrypted || backend.supports_encryption())
+                        && (!binary || backend.supports_binary())
+                        && size <= backend.max_size()
+                })
+                .map(|backend| backend.name())
+                .collect()
+        });
+        let selected: Vec<_> = candidates
+            .iter()
+            .filter(|backend| via.contains(&backend.name()))
+            .collect();
+
+        if self.once && !selected.iter().any(|backend| backend.supports_once()) {
+            return Err(Error::Argument(
+                "serving once requires a running gistit node",
+                "--once",
+            ));
+        }
+        if self.once && !selected.iter().all(|backend| backend.supports_once()) {
+            return Err(Error::Argument("serving once only works over p2p", "--via"));
+        }
+        if let Some(backend) = selected
+            .iter()
+            .find(|backend| encrypted && !backend.supports_encryption())
+        {
+            return Err(Error::Unsupported(backend.name(), "be encrypted"));
+        }
+        if let Some(backend) = selected
+            .iter()
+            .find(|backend| binary && !backend.supports_binary())
+        {
+            return Err(Error::Unsupported(backend.name(), "hold binary files"));
+        }
+        if !selected.iter().all(|backend| size <= backend.max_size()) {
+            return Err(Error::Argument(
+                "total size of files not allowed by the backends picked",
+                "--via",
+            ));
+        }
+        Ok(via)
+    }
+}
+
 #[async_trait]
 impl Dispatch for Action {
     type InnerData = Config;
 
+    #[allow(clippy::too_many_lines)]
     async fn prepare(&self) -> Result<Self::InnerData> {
+        let mut settings = Settings::load(&path::config()?)?;
+        let server = Profile::from_settings(&settings, self.server)?;
+        let lang = if let Some(value) = self.lang {
+            Some(check::lang(value)?)
+        } else {
+            None
+        };
+        let timeout = if let Some(value) = self.timeout {
+            check::timeout(value)?
+        } else {
+            PROVIDE_TIMEOUT
+        };
+        let mut oauth = github::Oauth::new(&server)?;
+        let mut context = Context {
+            runtime_path: path::runtime()?,
+            data_path: path::data()?,
+            timeout,
+            once: self.once,
+            github_token: oauth.token.clone(),
+            server,
+            node_max_size: settings.node_max_size()?,
+        };
+        let backends = backend::registry(&context);
+        // Only the backends that may be picked are asked what they support
+        let requested = self.requested(&settings)?;
+        let candidates = requested.as_ref().map_or_else(
+            || backends.iter().map(AsRef::as_ref).collect(),
+            |requested| backend::select(&backends, requested),
+        );
+        backend::probe_all(&candidates).await;
+        // Which backends take the files is only known once their total size is
+        let max_size = candidates
+            .iter()
+            .map(|backend| backend.max_size())
+            .max()
+            .unwrap_or_default();
+
+        // Read before anything shows progress, it may be typed in
+        let stdin = if self.file_paths.is_none() {
+            let limit = usize::try_from(check::uncompressed_size(max_size)).unwrap_or(usize::MAX);
+            Some(stdin::read_to_end(limit)?)
+        } else {
+            None
+        };
         progress!("Preparing");
-        let file = if let Some(file_ostr) = self.file_path {
-            let path = Path::new(file_ostr);
-            let attr = fs::metadata(&path)?;
-            let maybe_extension = path.extension();
 
-            check::metadata(&attr)?;
-            check::extension(maybe_extension)?;
+        let mut total_size = 0;
+        let files = if let Some(ref file_paths) = self.file_paths {
+            let mut files = Vec::new();
+            let mut read_size = 0;
+
+            for file_ostr in file_paths {
+                let root = Path::new(file_ostr);
+
+                for path in file::walk(root)? {
+                    let attr = fs::metadata(&path)?;
+                    check::metadata(&attr, max_size)?;
+                    read_size += attr.len();
+                    check::total_size(read_size, check::uncompressed_size(max_size))?;
+
+                    let name = file::walked_name(root, &path);
+                    let file = file::to_inner(name, fs::read(&path)?, lang)?;
+                    let carried = file.contents().len() as u64;
+                    check::carried_size(carried, max_size)?;
+                    total_size += carried;
+                    files.push(file);
+                }
+            }
+            check::total_size(total_size, max_size)?;
 
-            File::from_path(path)?
-        } else if let Some(ref stdin) = self.maybe_stdin {
-            File::from_data(stdin, "stdin")?
+            if files.is_empty() {
+                return Err(Error::Argument("no files found", "[FILE]..."));
+            }
+
+            files
+        } else if let Some(stdin) = stdin {
+            // Nothing but the contents to go by
+            let guessed = str::from_utf8(&stdin).ok().and_then(lang::guess);
+            let mut file = file::to_inner("stdin".to_owned(), stdin, lang)?;
+            total_size = file.contents().len() as u64;
+            if file.lang == lang::DEFAULT_LANG {
+                if let Some(guessed) = guessed {
+                    file.lang = guessed.to_owned();
+                }
+            }
+            vec![file]
         } else {
             return Err(Error::Argument("missing file input", "[FILE]/[STDIN]"));
         };
 
-        let author = check::author(self.author)?;
+        let author = match self.author {
+            Some(author) => author.to_owned(),
+            None => whoami::author(&mut settings)?,
+        };
+        check::author(&author)?;
         let description = if let Some(value) = self.description {
             Some(check::description(value)?)
+        } else if settings.text("description").as_deref() == Some("required") {
+            return Err(Error::Argument(
+                "a description is required by your settings",
+                "--description",
+            ));
+        } else {
+            None
+        };
+        let expires = if let Some(value) = self.expires {
+            Some(check::expires(value)?)
         } else {
             None
         };
+        let binary = files.iter().any(Inner::is_binary);
+        let via = self.via(&candidates, requested, binary, total_size)?;
+        let max_size = backend::select(&backends, &via)
+            .iter()
+            .map(|backend| backend.max_size())
+            .min()
+            .unwrap_or(max_size);
         updateln!("Prepared");
 
-        let github_token = if self.github {
+        if via.contains(&backend::github::NAME) {
             progress!("Authorizing");
-            let mut oauth = github::Oauth::new()?;
-
             if oauth.token().is_none() {
-                if let Err(url) = oauth.authorize() {
-                    warnln!(
+                match oauth.authorize() {
+                    Err(Error::OAuth(url)) => warnln!(
                         "failed to open your web browser. \n\nAuthorize manually: '{}'",
                         style(url).cyan()
-                    );
+                    ),
+                    Err(err) => return Err(err),
+                    Ok(()) => (),
                 }
                 oauth.poll_token().await?;
                 warnln!(
@@ -140,155 +358,164 @@ impl Dispatch for Action {
                 );
             }
             updateln!("Authorized");
-            oauth.token
+            context.github_token = oauth.token;
+        }
+
+        let signing_key = if self.sign {
+            Some(sign::signing_keypair(&path::config()?)?)
+        } else {
+            None
+        };
+
+        let secret = if let Some(ref recipients) = self.recipients {
+            let peers = recipients
+                .iter()
+                .map(|peer_id| check::peer_id(peer_id))
+                .collect::<Result<Vec<_>>>()?;
+            Some(Secret::for_recipients(&peers)?)
+        } else if self.password {
+            Some(Secret::from_passphrase(&crypt::passphrase(true)?)?)
+        } else if self.encrypt {
+            Some(Secret::Key(SecretKey::generate()))
         } else {
             None
         };
 
         Ok(Config {
-            file,
+            files,
             description,
             author,
-            clipboard: self.clipboard,
-            github_token,
-            runtime_path: path::runtime()?,
+            clipboard: self.clipboard || settings.flag("clipboard")?.unwrap_or(false),
+            history_keys: settings.flag("history.keys")?.unwrap_or(false),
+            secret,
+            expires,
+            signing_key,
+            via,
+            max_size,
+            context,
         })
     }
 
-    #[allow(clippy::too_many_lines)]
     async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
         let clipboard = config.clipboard;
+        let history_keys = config.history_keys;
+        let secret = config.secret.clone();
+        let via = config.via.clone();
+        let context = config.context.clone();
+
+        progress!("Sending");
+        let gistit: Gistit = config.try_into()?;
+
+        let backends = backend::registry(&context);
+        let selected = backend::select(&backends, &via);
+        let results = backend::publish_all(&selected, &gistit).await;
+        let sent: Vec<_> = selected
+            .iter()
+            .map(|backend| backend.name())
+            .zip(results)
+            .collect();
+
+        for (_, result) in &sent {
+            if let Err(Error::Interrupted(phase)) = result {
+                return Err(Error::Interrupted(phase));
+            }
+        }
+
+        let published: Vec<&Published> = sent
+            .iter()
+            .filter_map(|(_, result)| result.as_ref().ok())
+            .collect();
+        if published.is_empty() {
+            cleanln!(fmt_summary(&sent, secret.as_ref()));
+            interruptln!();
+            errorln!("failed to send gistit");
+            std::process::exit(1);
+        }
+
+        // A single history entry
+        if let Some(origin) = published
+            .iter()
+            .map(|published| &published.origin)
+            .max_by_key(|origin| origin.precedence())
+        {
+            let kept = secret.as_ref().filter(|_| history_keys);
+            record(&context, &gistit, origin.clone(), kept);
+        }
+
+        // The hash is what gets shared, backends that don't have one hand out an url
+        let copied = published
+            .iter()
+            .find_map(|published| published.hash.as_deref())
+            .map(|hash| share_token(hash, secret.as_ref()))
+            .or_else(|| published.iter().find_map(|published| published.url.clone()))
+            .unwrap_or_default();
+        if clipboard {
+            Clipboard::new(&copied)
+                .try_into_selected()?
+                .into_provider()
+                .set_contents()?;
+        }
+
+        updateln!("Sent");
+        let mut summary = fmt_summary(&sent, secret.as_ref());
+        if clipboard {
+            summary.push_str(&format!(
+                "\n\n    {}",
+                style(format!("'{}' copied to clipboard", copied))
+                    .italic()
+                    .dim()
+            ));
+        }
+        finish!(format!("{}\n\n", summary));
+        Ok(())
+    }
+}
 
-        let mut bridge = gistit_ipc::client(&config.runtime_path)?;
-        if bridge.alive() {
-            // Daemon is running, hosting with p2p
-            progress!("Hosting");
-            let gistit: Gistit = config.try_into()?;
-
-            bridge.connect_blocking()?;
-            bridge.send(Instruction::request_provide(gistit)).await?;
-
-            if let ipc::instruction::Kind::ProvideResponse(ipc::instruction::ProvideResponse {
-                hash: Some(hash),
-            }) = bridge.recv().await?.expect_response()?
-            {
-                if clipboard {
-                    Clipboard::new(&hash)
-                        .try_into_selected()?
-                        .into_provider()
-                        .set_contents()?;
-                }
+/// One line per backend, in the order they were asked for
+fn fmt_summary(sent: &[(&str, Result<Published>)], secret: Option<&Secret>) -> String {
+    let mut summary = String::new();
 
-                let clipboard_msg = if self.clipboard {
-                    style("(copied to clipboard)").italic().dim().to_string()
-                } else {
-                    "".to_string()
-                };
-
-                updateln!("Hosted");
-                finish!(format!(
-                    "\n    hash: '{}' {}\n\n",
-                    style(hash).bold(),
-                    style(clipboard_msg).italic().dim()
-                ));
-            } else {
-                interruptln!();
-                errorln!("failed to provide gistit, check gistit-daemon logs");
-            }
-        } else {
-            progress!("Sending");
-            let maybe_github_token = config.github_token.as_ref().map(Clone::clone);
-            let gistit: Gistit = config.try_into()?;
-
-            let maybe_gist = if let Some(token) = maybe_github_token {
-                // Github flag was provided, sending to Github Gists
-                // NOTE: Currently we only support one file
-                let inner = gistit.inner.first().expect("to have at least one file");
-                let name = &inner.name;
-                let description = gistit.description.as_deref().unwrap_or("");
-
-                let response = reqwest::Client::new()
-                    .post(GITHUB_GISTS_API_URL)
-                    .header("user-agent", "gistit")
-                    .header("authorization", format!("token {}", token.access_token))
-                    .header("accept", "application/vnd.github.v3+json")
-                    .json(&serde_json::json!({
-                        "description": description,
-                        "public": true,
-                        "files": {
-                            name: {
-                                "content": inner.data
-                            }
-                        }
-                    }))
-                    .send()
-                    .await?;
-
-                match response.status() {
-                    StatusCode::CREATED => {
-                        let data: CreateResponse = response.json().await?;
-                        Some(data.url)
-                    }
-                    StatusCode::FORBIDDEN | StatusCode::UNPROCESSABLE_ENTITY => {
-                        warnln!(
-                            "your github token is expired, nothing was posted. status {}",
-                            response.status()
-                        );
-                        None
-                    }
-                    _ => {
-                        warnln!("got a invalid response from github, nothing was posted");
-                        None
-                    }
-                }
-            } else {
-                None
-            };
-
-            let response = reqwest::Client::new()
-                .post(SERVER_URL_LOAD.to_string())
-                .header("content-type", "application/x-protobuf")
-                .body(gistit.encode_to_vec())
-                .send()
-                .await?;
-
-            match response.status() {
-                StatusCode::OK => {
-                    let server_hash = Gistit::from_bytes(response.bytes().await?)?.hash;
-
-                    if clipboard {
-                        Clipboard::new(&server_hash)
-                            .try_into_selected()?
-                            .into_provider()
-                            .set_contents()?;
-                    }
-                    updateln!("Sent");
-
-                    let clipboard_msg = if self.clipboard {
-                        style("(copied to clipboard)").italic().dim().to_string()
-                    } else {
-                        "".to_string()
-                    };
-
-                    let gist = maybe_gist.map_or_else(
-                        || "".to_string(),
-                        |gist_url| format!("github gist: '{}'\n", gist_url),
-                    );
-
-                    finish!(format!(
-                        "\n    hash: '{}' {} \n    url: 'https://gistit.vercel.app/h/{}' \n    {}\n\n",
-                        style(&server_hash).bold(),
-                        clipboard_msg,
-                        style(&server_hash).bold(),
-                        gist
-                    ));
+    for (name, result) in sent {
+        let line = match result {
+            Ok(published) => {
+                let mut line = Vec::new();
+                if let Some(ref hash) = published.hash {
+                    line.push(format!("hash: '{}'", share_token(hash, secret)));
                 }
-                StatusCode::UNPROCESSABLE_ENTITY | StatusCode::BAD_REQUEST => {
-                    return Err(Error::Server("invalid gistit payload"));
+                if let Some(ref url) = published.url {
+                    line.push(format!("url: '{}'", url));
                 }
-                _ => return Err(Error::Server("invalid server response")),
+                style(line.join(" ")).bold().to_string()
             }
+            Err(err) => style(format!("failed, {}", err)).red().to_string(),
         };
-        Ok(())
+        summary.push_str(&format!("\n    {:<8} {}", name, line));
+    }
+    summary
+}
+
+/// The hash to share, carrying the key if the gistit is encrypted with a random one
+fn share_token(hash: &str, secret: Option<&Secret>) -> String {
+    match secret {
+        Some(Secret::Key(key)) => crypt::share_token(hash, key),
+        _ => hash.to_owned(),
+    }
+}
+
+/// Keeps a history entry, failing to do so never fails the send
+fn record(context: &Context, gistit: &Gistit, origin: history::Backend, secret: Option<&Secret>) {
+    let key = match secret {
+        Some(Secret::Key(key)) => Some(key),
+        _ => None,
+    };
+    let mut entry = Entry::new(gistit, Direction::Sent, origin, key);
+    if entry.backend == history::Backend::Server {
+        entry = entry.with_server(&context.server.name);
+    }
+
+    // Served once, it's not kept either
+    let payload = (!context.once).then(|| gistit);
+    if let Err(err) = history::record(&context.data_path, payload, &entry) {
+        warnln!("failed to record history: {}", err);
     }
 }
@@ -1,36 +1,75 @@
+//! The gistit server profile in use, picked with `--server`
 use url::Url;
 
-use gistit_project::{env, var};
-use lazy_static::lazy_static;
+use gistit_project::settings::{self, Settings};
 
-lazy_static! {
-    static ref SERVER_URL_BASE: Url = Url::parse(var::GISTIT_SERVER_URL_BASE).unwrap();
-}
+use crate::Result;
 
 const SERVER_SUBPATH_GET: &str = "get";
 const SERVER_SUBPATH_LOAD: &str = "load";
 const SERVER_SUBPATH_TOKEN: &str = "token";
 
-lazy_static! {
-    pub static ref SERVER_URL_GET: Url = Url::parse(
-        &std::env::var(env::GISTIT_SERVER_URL)
-            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
-    )
-    .expect("invalid `GISTIT_SERVER_URL` variable")
-    .join(SERVER_SUBPATH_GET)
-    .unwrap();
-    pub static ref SERVER_URL_LOAD: Url = Url::parse(
-        &std::env::var(env::GISTIT_SERVER_URL)
-            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
-    )
-    .expect("invalid `GISTIT_SERVER_URL` variable")
-    .join(SERVER_SUBPATH_LOAD)
-    .unwrap();
-    pub static ref SERVER_URL_TOKEN: Url = Url::parse(
-        &std::env::var(env::GISTIT_SERVER_URL)
-            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
-    )
-    .expect("invalid `GISTIT_SERVER_URL` variable")
-    .join(SERVER_SUBPATH_TOKEN)
-    .unwrap();
+/// A gistit server profile, see `servers.<name>` in the settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    get: Url,
+    load: Url,
+    token: Url,
+    /// Where the web app shows a gistit, followed by its hash
+    web: Option<Url>,
+    pub github_client_id: Option<String>,
+    /// Largest gistit in bytes the server accepts
+    pub max_size: u64,
+}
+
+impl Profile {
+    /// The profile `name`, else the one in the settings, else the hosted server
+    ///
+    /// # Errors
+    ///
+    /// Fails if the profile doesn't exist or one of its urls is invalid
+    pub fn from_settings(settings: &Settings, name: Option<&str>) -> Result<Self> {
+        let server = settings.server(name)?;
+        let base = Url::parse(&server.url)?;
+
+        Ok(Self {
+            get: base.join(SERVER_SUBPATH_GET)?,
+            load: base.join(SERVER_SUBPATH_LOAD)?,
+            token: base.join(SERVER_SUBPATH_TOKEN)?,
+            web: server.web_url.as_deref().map(Url::parse).transpose()?,
+            name: server.name,
+            github_client_id: server.github_client_id,
+            max_size: server.max_size,
+        })
+    }
+
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        self.name == settings::DEFAULT_SERVER
+    }
+
+    #[must_use]
+    pub fn get_url(&self) -> Url {
+        self.get.clone()
+    }
+
+    #[must_use]
+    pub fn load_url(&self) -> Url {
+        self.load.clone()
+    }
+
+    #[must_use]
+    pub fn token_url(&self) -> Url {
+        self.token.clone()
+    }
+
+    /// Where to see the gistit in a browser, if the profile has a web app
+    #[must_use]
+    pub fn share_url(&self, hash: &str) -> Option<String> {
+        self.web
+            .as_ref()
+            .and_then(|web| web.join(hash).ok())
+            .map(String::from)
+    }
 }
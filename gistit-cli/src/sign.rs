@@ -0,0 +1,239 @@
+//! The signature module
+//!
+//! Gistits are signed over a digest of every field, see [`Gistit::signed_digest`]. The contents
+//! are covered through the hash, checked against them before the signature.
+//! The node's ed25519 key is used when `gistit-daemon` was set up, otherwise a key is generated
+//! and kept in the config directory. Signers can be given local petnames in `petnames.json`.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use libp2p_core::identity::{self, ed25519};
+use libp2p_core::PeerId;
+
+use gistit_proto::payload::gistit::Signature;
+use gistit_proto::Gistit;
+
+use crate::crypt;
+use crate::Result;
+
+/// Signing key used when no node key is available
+const SIGNING_KEY_FILE: &str = "signing-key";
+
+/// Maps peer ids to local names
+const PETNAMES_FILE: &str = "petnames.json";
+
+/// The outcome of checking a gistit signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Signature is valid and was made by this peer
+    Verified(PeerId),
+    /// Signature or contents were tampered with
+    Invalid(&'static str),
+    Unsigned,
+}
+
+impl Verification {
+    /// The signer's petname if one is set, otherwise its peer id
+    #[must_use]
+    pub fn signer(&self, config_path: &Path) -> Option<String> {
+        if let Self::Verified(peer_id) = self {
+            let peer_id = peer_id.to_base58();
+            Some(petname(config_path, &peer_id).unwrap_or(peer_id))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Verified(peer_id) => write!(f, "verified: {}", peer_id),
+            Self::Invalid(reason) => write!(f, "invalid signature: {}", reason),
+            Self::Unsigned => f.write_str("unsigned"),
+        }
+    }
+}
+
+/// Loads the key to sign gistits with, generating one if needed
+///
+/// # Errors
+///
+/// Fails if the config directory can't be read or written
+pub fn signing_keypair(config_path: &Path) -> Result<ed25519::Keypair> {
+    if let Ok(keypair) = crypt::node_keypair(config_path) {
+        return Ok(keypair);
+    }
+
+    let key_path = config_path.join(SIGNING_KEY_FILE);
+    if let Ok(encoded) = fs::read_to_string(&key_path) {
+        let mut bytes = base64::decode(encoded.trim())?;
+        return ed25519::Keypair::decode(&mut bytes)
+            .map_err(|_| crate::Error::Argument("corrupted signing key", SIGNING_KEY_FILE));
+    }
+
+    let keypair = ed25519::Keypair::generate();
+    crypt::private_file()
+        .write(true)
+        .create_new(true)
+        .open(&key_path)?
+        .write_all(base64::encode(keypair.encode()).as_bytes())?;
+    Ok(keypair)
+}
+
+/// Signs every field of `gistit`, which must already be final
+#[must_use]
+pub fn sign(gistit: &Gistit, keypair: &ed25519::Keypair) -> Signature {
+    Signature {
+        public_key: identity::PublicKey::Ed25519(keypair.public()).to_protobuf_encoding(),
+        signature: keypair.sign(&gistit.signed_digest()),
+    }
+}
+
+/// Checks both the hash against the contents and the signature against every field
+#[must_use]
+pub fn verify(gistit: &Gistit) -> Verification {
+    let signature = match gistit.signature {
+        Some(ref signature) => signature,
+        None => return Verification::Unsigned,
+    };
+
+    if gistit.compute_hash() != gistit.hash {
+        return Verification::Invalid("contents don't match the hash");
+    }
+
+    let public_key = match identity::PublicKey::from_protobuf_encoding(&signature.public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return Verification::Invalid("malformed public key"),
+    };
+
+    if public_key.verify(&gistit.signed_digest(), &signature.signature) {
+        Verification::Verified(public_key.to_peer_id())
+    } else {
+        Verification::Invalid("signature doesn't match")
+    }
+}
+
+fn petname(config_path: &Path, peer_id: &str) -> Option<String> {
+    let contents = fs::read_to_string(config_path.join(PETNAMES_FILE)).ok()?;
+    let mut petnames: HashMap<String, String> = serde_json::from_str(&contents).ok()?;
+    petnames.remove(peer_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gistit_proto::payload::hash;
+
+    fn test_gistit() -> Gistit {
+        let data = "fn main() { println!(\"hello\"); }".to_owned();
+        Gistit::new(
+            hash("Matthew McConaughey", None, [("main.rs", data.as_bytes())]),
+            "Matthew McConaughey".to_owned(),
+            None,
+            "0".to_owned(),
+            vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                data.len() as u32,
+                data,
+            )],
+        )
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let keypair = ed25519::Keypair::generate();
+        let peer_id = identity::PublicKey::Ed25519(keypair.public()).to_peer_id();
+
+        let mut gistit = test_gistit();
+        assert_eq!(verify(&gistit), Verification::Unsigned);
+
+        gistit.signature = Some(sign(&gistit, &keypair));
+        assert_eq!(verify(&gistit), Verification::Verified(peer_id));
+    }
+
+    #[test]
+    fn sign_verify_tampered() {
+        let keypair = ed25519::Keypair::generate();
+        let mut gistit = test_gistit();
+        gistit.signature = Some(sign(&gistit, &keypair));
+
+        let mut tampered_data = gistit.clone();
+        tampered_data.inner[0].data.push_str("// pwned");
+        assert!(matches!(verify(&tampered_data), Verification::Invalid(_)));
+
+        let mut tampered_author = gistit.clone();
+        tampered_author.author = "Woody Harrelson".to_owned();
+        assert!(matches!(verify(&tampered_author), Verification::Invalid(_)));
+
+        let mut tampered_name = gistit.clone();
+        tampered_name.inner[0].name = "build.rs".to_owned();
+        assert!(matches!(verify(&tampered_name), Verification::Invalid(_)));
+
+        let mut tampered_lang = gistit.clone();
+        tampered_lang.inner[0].lang = "text".to_owned();
+        assert_eq!(
+            verify(&tampered_lang),
+            Verification::Invalid("signature doesn't match")
+        );
+
+        let mut tampered_expiry = gistit.clone();
+        tampered_expiry.expires_at = Some("0".to_owned());
+        assert_eq!(
+            verify(&tampered_expiry),
+            Verification::Invalid("signature doesn't match")
+        );
+
+        let mut stripped_expiry = gistit.clone();
+        stripped_expiry.expires_at = Some(u64::MAX.to_string());
+        stripped_expiry.signature = Some(sign(&stripped_expiry, &keypair));
+        stripped_expiry.expires_at = None;
+        assert_eq!(
+            verify(&stripped_expiry),
+            Verification::Invalid("signature doesn't match")
+        );
+
+        let mut resigned = gistit.clone();
+        resigned.signature.as_mut().unwrap().public_key =
+            identity::PublicKey::Ed25519(ed25519::Keypair::generate().public())
+                .to_protobuf_encoding();
+        assert!(matches!(verify(&resigned), Verification::Invalid(_)));
+    }
+
+    #[test]
+    fn sign_signing_keypair_is_persisted() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+
+        let first = signing_keypair(tmp.path()).unwrap();
+        let second = signing_keypair(tmp.path()).unwrap();
+        assert_eq!(first.encode(), second.encode());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(tmp.path().join(SIGNING_KEY_FILE)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn sign_verification_signer_petname() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let peer_id = PeerId::random();
+        let verification = Verification::Verified(peer_id);
+
+        assert_eq!(verification.signer(tmp.path()), Some(peer_id.to_base58()));
+
+        fs::write(
+            tmp.path().join(PETNAMES_FILE),
+            serde_json::json!({ peer_id.to_base58(): "rust" }).to_string(),
+        )
+        .unwrap();
+        assert_eq!(verification.signer(tmp.path()), Some("rust".to_owned()));
+        assert_eq!(Verification::Unsigned.signer(tmp.path()), None);
+    }
+}
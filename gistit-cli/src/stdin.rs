@@ -1,25 +1,29 @@
 use console::{style, Emoji};
-use std::io::{stdin, BufRead};
+use std::io::{stdin, Read};
 
-const READ_LIMIT_BYTES: usize = 50_000;
+use crate::{Error, Result};
 
-pub fn read_to_end() -> String {
-    let mut buf = String::new();
-    let mut threshold = READ_LIMIT_BYTES;
-    let stdin = stdin();
-    let mut handle = stdin.lock();
+/// Reads stdin as bytes, it may not be text. Up to `limit` bytes, the most worth sending
+///
+/// # Errors
+///
+/// Fails if stdin can't be read or holds more than `limit` bytes
+pub fn read_to_end(limit: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
     println!(
         "{} Reading stdin {}",
         Emoji("📝", ">"),
         style("(Ctrl+D to end)").dim().italic()
     );
 
-    while let Ok(read) = handle.read_line(&mut buf) {
-        if threshold == 0 || read == 0 {
-            break;
-        }
-        threshold -= read;
+    // One byte past the limit tells a full stdin from a bigger one
+    stdin()
+        .lock()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut buf)?;
+    if buf.len() > limit {
+        return Err(Error::Argument("stdin is too big to be sent", "[STDIN]"));
     }
 
-    buf
+    Ok(buf)
 }
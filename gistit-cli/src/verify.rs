@@ -0,0 +1,71 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+use gistit_proto::Gistit;
+
+use crate::dispatch::Dispatch;
+use crate::sign::{self, Verification};
+use crate::{finish, progress, updateln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub file: &'static OsStr,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            file: args
+                .value_of_os("FILE")
+                .ok_or(Error::Argument("missing argument", "[FILE]"))?,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    payload: Gistit,
+    config_path: PathBuf,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Reading");
+        let payload = Gistit::from_bytes(fs::read(self.file)?)?;
+        updateln!("Read");
+
+        Ok(Config {
+            payload,
+            config_path: path::config()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Verifying");
+
+        match sign::verify(&config.payload) {
+            verification @ Verification::Verified(_) => {
+                updateln!("Verified");
+                finish!(format!(
+                    "\n    hash: '{}'\n    signer: '{}'\n\n",
+                    style(&config.payload.hash).bold(),
+                    style(verification.signer(&config.config_path).unwrap_or_default()).green()
+                ));
+                Ok(())
+            }
+            Verification::Invalid(reason) => Err(Error::Signature(reason)),
+            Verification::Unsigned => Err(Error::Unsigned),
+        }
+    }
+}
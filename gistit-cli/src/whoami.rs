@@ -0,0 +1,97 @@
+//! `gistit whoami`, the author of the gistits you send
+//!
+//! The author is the `author` setting. It's created on first run from `git config user.name`,
+//! or a generated name when git has none, so the same person always sends as the same author.
+use std::process::Command;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+
+use gistit_project::path;
+use gistit_project::settings::Settings;
+
+use crate::dispatch::Dispatch;
+use crate::param::check;
+use crate::{finish, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub name: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            name: args.value_of("NAME"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    name: Option<&'static str>,
+    settings: Settings,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        let name = if let Some(value) = self.name {
+            Some(check::author(value)?)
+        } else {
+            None
+        };
+
+        Ok(Config {
+            name,
+            settings: Settings::load(&path::config()?)?,
+        })
+    }
+
+    async fn dispatch(&self, mut config: Self::InnerData) -> Result<()> {
+        if let Some(name) = config.name {
+            config.settings.set("author", name)?;
+            config.settings.save()?;
+            finish!(format!("👤  You are now '{}'", name));
+        } else {
+            println!("{}", author(&mut config.settings)?);
+        }
+        Ok(())
+    }
+}
+
+/// The author of sent gistits, saved to the settings the first time it's needed
+///
+/// # Errors
+///
+/// Fails if the settings can't be written
+pub fn author(settings: &mut Settings) -> Result<String> {
+    if let Some(author) = settings.text("author") {
+        return Ok(author);
+    }
+
+    let author = git_user_name()
+        .filter(|name| check::author(name).is_ok())
+        .unwrap_or_else(|| {
+            names::Generator::default()
+                .next()
+                .expect("to generate a name")
+        });
+    settings.set("author", &author)?;
+    settings.save()?;
+    Ok(author)
+}
+
+fn git_user_name() -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()?;
+    let name = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+
+    (output.status.success() && !name.is_empty()).then(|| name)
+}
@@ -0,0 +1,23 @@
+//! Shared by the end to end tests
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+use gistit_project::{env, settings};
+
+/// `gistit` keeping its files in `tmp`, blind to the settings and git config of whoever runs the
+/// tests
+pub fn gistit(tmp: &assert_fs::TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("gistit").unwrap();
+    // Server profiles are keyed by name, only the ones that are always there are cleared
+    for key in settings::KEYS.iter().filter(|key| !key.name.contains('*')) {
+        cmd.env_remove(settings::env_var(key.name));
+    }
+    cmd.env_remove(env::GISTIT_SERVER_URL)
+        .env_remove(env::GISTIT_PASSWORD_VAR)
+        .env(env::GISTIT_RUNTIME_VAR, tmp.child("runtime").path())
+        .env(env::GISTIT_CONFIG_VAR, tmp.child("config").path())
+        .env(env::GISTIT_DATA_VAR, tmp.child("data").path())
+        .env("GIT_CONFIG_GLOBAL", tmp.child("gitconfig").path())
+        .env("GIT_CONFIG_NOSYSTEM", "1");
+    cmd
+}
@@ -0,0 +1,63 @@
+//! End to end tests of `gistit config`
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+
+use common::gistit;
+
+#[test]
+fn config_set_then_get() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+
+    gistit(&tmp)
+        .args(["config", "set", "daemon.port", "4001"])
+        .assert()
+        .success();
+    gistit(&tmp)
+        .args(["config", "get", "daemon.port"])
+        .assert()
+        .success()
+        .stdout("4001\n");
+
+    tmp.child("config")
+        .child("config.toml")
+        .assert(predicate::str::contains("[daemon]\nport = 4001"));
+}
+
+#[test]
+fn config_list_shows_origins() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+
+    gistit(&tmp)
+        .args(["config", "set", "author", "Matthew McConaughey"])
+        .assert()
+        .success();
+    gistit(&tmp)
+        .env("GISTIT_DAEMON_PORT", "4002")
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("author = Matthew McConaughey  file")
+                .and(predicate::str::contains("daemon.port = 4002  env"))
+                .and(predicate::str::contains("clipboard = false  default")),
+        );
+}
+
+#[test]
+fn config_rejects_unknown_keys_and_bad_values() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+
+    gistit(&tmp)
+        .args(["config", "set", "nope", "1"])
+        .assert()
+        .stderr(predicate::str::contains("nope"));
+    gistit(&tmp)
+        .args(["config", "set", "daemon.port", "not a port"])
+        .assert()
+        .stderr(predicate::str::contains("invalid value for `daemon.port`"));
+    tmp.child("config")
+        .child("config.toml")
+        .assert(predicate::path::missing());
+}
@@ -0,0 +1,75 @@
+//! End to end tests of the cli talking to a `gistit-daemon` over ipc
+use std::sync::mpsc;
+use std::time::Duration;
+
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+use gistit_proto::ipc::instruction::{CancelRequest, Kind};
+use gistit_proto::Instruction;
+
+mod common;
+
+use common::gistit;
+
+/// A node that takes requests and never responds, forwarding them to the test
+fn spawn_unresponsive_node(tmp: &assert_fs::TempDir) -> mpsc::Receiver<Instruction> {
+    let runtime = tmp.child("runtime");
+    runtime.create_dir_all().unwrap();
+    let runtime_path = runtime.path().to_path_buf();
+
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut bridge = gistit_ipc::server(&runtime_path).unwrap();
+            ready_tx.send(()).unwrap();
+
+            while let Ok((_, instruction)) = bridge.recv().await {
+                if tx.send(instruction).is_err() {
+                    break;
+                }
+            }
+        });
+    });
+    ready_rx.recv().unwrap();
+
+    rx
+}
+
+#[test]
+fn fetch_times_out_and_cancels_the_request() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let node = spawn_unresponsive_node(&tmp);
+
+    gistit(&tmp)
+        .args(["fetch", &"a".repeat(64), "--from", "p2p", "--timeout", "1s"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "timed out while looking for the gistit in the DHT",
+        ));
+
+    let fetch = node.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(fetch.kind, Some(Kind::FetchRequest(_))));
+
+    let cancel = node.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        cancel.kind,
+        Some(Kind::CancelRequest(CancelRequest { id: fetch.id }))
+    );
+}
+
+#[test]
+fn node_status_times_out() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let _node = spawn_unresponsive_node(&tmp);
+
+    gistit(&tmp)
+        .args(["node", "--status", "--timeout", "1s"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "timed out while requesting status",
+        ));
+}
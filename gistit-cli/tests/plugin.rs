@@ -0,0 +1,202 @@
+//! End to end tests of the cli talking to `gistit-backend-<name>` plugins
+#![cfg(unix)]
+use std::fmt::Write as _;
+use std::os::unix::fs::PermissionsExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+use gistit_proto::payload::hash;
+use gistit_proto::plugin::{request, Request, Response};
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+mod common;
+
+const AUTHOR: &str = "Matthew McConaughey";
+
+const DATA: &str = "fn main() {\n    println!(\"alright alright alright\");\n}\n";
+
+/// A plugin that keeps the request it got and always answers with `response`
+fn install_plugin(tmp: &assert_fs::TempDir, name: &str, response: &Response) {
+    let mut escaped = String::new();
+    for byte in response.encode_length_delimited_to_vec() {
+        write!(escaped, "\\{:03o}", byte).unwrap();
+    }
+
+    let plugin = tmp.child("bin").child(format!("gistit-backend-{}", name));
+    plugin
+        .write_str(&format!(
+            "#!/bin/sh\ncat > '{}'\nprintf '{}'\n",
+            tmp.child("request").path().display(),
+            escaped
+        ))
+        .unwrap();
+    std::fs::set_permissions(plugin.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn gistit(tmp: &assert_fs::TempDir) -> Command {
+    let path = format!(
+        "{}:{}",
+        tmp.child("bin").path().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let mut cmd = common::gistit(tmp);
+    cmd.env("PATH", path);
+    cmd
+}
+
+fn last_request(tmp: &assert_fs::TempDir) -> Request {
+    let bytes = std::fs::read(tmp.child("request").path()).unwrap();
+    Request::decode_length_delimited(&*bytes).unwrap()
+}
+
+#[test]
+fn send_via_plugin() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    install_plugin(
+        &tmp,
+        "vault",
+        &Response::published(Some(hash.clone()), Some("vault://main.rs".to_owned())),
+    );
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "vault"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "hash: '{}' url: 'vault://main.rs'",
+            hash
+        )));
+
+    match last_request(&tmp).kind {
+        Some(request::Kind::Publish(request::Publish {
+            gistit: Some(gistit),
+            once: false,
+        })) => assert_eq!(gistit.hash, hash),
+        other => panic!("unexpected request {:?}", other),
+    }
+}
+
+#[test]
+fn fetch_from_plugin() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    let payload = Gistit::new(
+        hash.clone(),
+        AUTHOR.to_owned(),
+        None,
+        "0".to_owned(),
+        vec![Gistit::new_inner(
+            "main.rs".to_owned(),
+            "rust".to_owned(),
+            DATA.len() as u32,
+            DATA.to_owned(),
+        )],
+    );
+    install_plugin(&tmp, "vault", &Response::retrieved(Some(payload)));
+
+    gistit(&tmp)
+        .args(["fetch", &hash, "--save", "--from", "vault"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Fetched from vault"));
+    tmp.child("data/saved/main.rs").assert(DATA);
+
+    assert_eq!(last_request(&tmp), Request::retrieve(hash));
+}
+
+#[test]
+fn plugin_failures_are_reported() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    install_plugin(
+        &tmp,
+        "vault",
+        &Response::failed("vault is sealed".to_owned()),
+    );
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "vault"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed, vault is sealed"));
+
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "nope"])
+        .assert()
+        .stderr(predicate::str::contains("unknown backend"));
+}
+
+#[test]
+fn only_picked_plugins_are_asked() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    install_plugin(&tmp, "vault", &Response::published(Some(hash), None));
+
+    // Never answers, and says so if asked
+    let stuck = tmp.child("bin/gistit-backend-stuck");
+    stuck
+        .write_str(&format!(
+            "#!/bin/sh\ntouch '{}'\nsleep 60\n",
+            tmp.child("asked").path().display()
+        ))
+        .unwrap();
+    std::fs::set_permissions(stuck.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "vault"])
+        .assert()
+        .success();
+    tmp.child("asked").assert(predicate::path::missing());
+
+    // Picking among all of them, a plugin that doesn't answer the probe in time is left out,
+    // without waiting on the whole `--timeout`
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR])
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .stderr(predicate::str::contains("stuck").not());
+    tmp.child("asked").assert(predicate::path::exists());
+}
+
+#[test]
+fn plugin_without_binary_is_not_sent_binary_files() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    install_plugin(
+        &tmp,
+        "vault",
+        &Response::capabilities(true, true, false, 0, false),
+    );
+
+    let input = tmp.child("logo.png");
+    input
+        .write_binary(&[0x89, b'P', b'N', b'G', 0xff, 0x00].repeat(8))
+        .unwrap();
+
+    gistit(&tmp)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "vault"])
+        .assert()
+        .stderr(predicate::str::contains("vault can't hold binary files"));
+    assert!(matches!(
+        last_request(&tmp).kind,
+        Some(request::Kind::Capabilities(_))
+    ));
+}
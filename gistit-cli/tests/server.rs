@@ -0,0 +1,800 @@
+//! End to end tests of the send and fetch paths against a local `gistit-server`
+use std::net::TcpListener;
+use std::path::Path;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+use gistit_project::{env, var};
+use gistit_proto::payload::hash;
+use gistit_server::{serve, State, Store};
+
+mod common;
+
+const AUTHOR: &str = "Matthew McConaughey";
+
+const DATA: &str = "fn main() {\n    println!(\"alright alright alright\");\n}\n";
+
+fn spawn_server(data_path: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = State {
+        store: Store::new(data_path).unwrap(),
+        github: None,
+        max_size: var::GISTIT_MAX_SIZE,
+    };
+
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(serve(listener, state))
+            .unwrap();
+    });
+
+    format!("http://{}/", addr)
+}
+
+fn gistit(tmp: &assert_fs::TempDir, server_url: &str) -> Command {
+    let mut cmd = common::gistit(tmp);
+    cmd.env(env::GISTIT_SERVER_URL, server_url);
+    cmd
+}
+
+#[test]
+fn send_and_fetch_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR])
+        .assert()
+        .success();
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    tmp.child("server/gistits")
+        .child(&hash)
+        .assert(predicate::path::exists());
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+
+    tmp.child("data/saved/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_and_fetch_directory_tree_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("input");
+    input
+        .child("Cargo.toml")
+        .write_str("[package]\nname = \"repro\"\n")
+        .unwrap();
+    input.child("src/main.rs").write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .current_dir(input.path())
+        .args(["Cargo.toml", "src"])
+        .args(["--author", AUTHOR])
+        .assert()
+        .success();
+
+    let hash = hash(
+        AUTHOR,
+        None,
+        [
+            ("Cargo.toml", &b"[package]\nname = \"repro\"\n"[..]),
+            ("src/main.rs", DATA.as_bytes()),
+        ],
+    );
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+
+    tmp.child("data/saved/Cargo.toml")
+        .assert("[package]\nname = \"repro\"\n");
+    tmp.child("data/saved/src/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_and_fetch_encrypted_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    let output = gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--encrypt"])
+        .output()
+        .unwrap();
+    let output = String::from_utf8_lossy(&output.stderr);
+    let token = output
+        .split("hash: '")
+        .nth(1)
+        .and_then(|rest| rest.split('\'').next())
+        .unwrap();
+    let (hash, _key) = token.split_once('#').unwrap();
+
+    // The server only ever sees ciphertext
+    let stored = std::fs::read(tmp.child("server/gistits").child(hash).path()).unwrap();
+    assert!(!String::from_utf8_lossy(&stored).contains("alright alright alright"));
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", hash, "--save", "--from", "server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("missing key"));
+    tmp.child("data/saved/main.rs")
+        .assert(predicate::path::missing());
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", token, "--save", "--from", "server"])
+        .assert()
+        .success();
+    tmp.child("data/saved/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_and_fetch_passphrase_protected_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--password"])
+        .env(env::GISTIT_PASSWORD_VAR, "just keep livin")
+        .assert()
+        .success();
+
+    let stored = std::fs::read_dir(tmp.child("server/gistits").path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let hash = stored.file_name().into_string().unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .env(env::GISTIT_PASSWORD_VAR, "just keep living")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("wrong passphrase"));
+    tmp.child("data/saved/main.rs")
+        .assert(predicate::path::missing());
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .env(env::GISTIT_PASSWORD_VAR, "just keep livin")
+        .assert()
+        .success();
+    tmp.child("data/saved/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_and_fetch_for_recipient_through_local_server() {
+    use libp2p_core::identity::{self, ed25519};
+    use libp2p_core::PeerId;
+
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    // Recipient node key, as written by `gistit-daemon`
+    let keypair = identity::Keypair::Ed25519(ed25519::Keypair::generate());
+    let peer_id = PeerId::from(keypair.public());
+    tmp.child("config/node-config")
+        .write_str(
+            &serde_json::json!({
+                "Identity": {
+                    "PeerID": peer_id.to_base58(),
+                    "PrivKey": base64::encode(keypair.to_protobuf_encoding().unwrap()),
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--to", &peer_id.to_base58()])
+        .assert()
+        .success();
+
+    let stored = std::fs::read_dir(tmp.child("server/gistits").path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let hash = stored.file_name().into_string().unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+    tmp.child("data/saved/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_with_expiry_through_local_server() {
+    use gistit_proto::Gistit;
+
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--expires", "2w"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("invalid expiry"));
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--expires", "1h"])
+        .assert()
+        .success();
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    let stored = std::fs::read(tmp.child("server/gistits").child(&hash).path()).unwrap();
+    let payload = Gistit::from_bytes(stored).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let remaining = payload.remaining_millis(now).unwrap();
+    assert!(remaining > 59 * 60 * 1000 && remaining <= 60 * 60 * 1000);
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+    tmp.child("data/saved/main.rs").assert(DATA);
+}
+
+#[test]
+fn send_signed_and_verify_saved_payload() {
+    use gistit_proto::prost::Message;
+    use gistit_proto::Gistit;
+
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--sign"])
+        .assert()
+        .success();
+    tmp.child("config/signing-key")
+        .assert(predicate::path::exists());
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("verified: "));
+
+    let saved = tmp.child("data/saved").child(format!("{}.gistit", hash));
+    gistit(&tmp, &server_url)
+        .args(["verify"])
+        .arg(saved.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("signer: "));
+
+    let mut tampered = Gistit::from_bytes(std::fs::read(saved.path()).unwrap()).unwrap();
+    tampered.inner[0].data.push_str("// alright");
+    saved.write_binary(&tampered.encode_to_vec()).unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["verify"])
+        .arg(saved.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("invalid signature"));
+
+    tampered.signature = None;
+    saved.write_binary(&tampered.encode_to_vec()).unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["verify"])
+        .arg(saved.path())
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("gistit is not signed")
+                .and(predicate::str::contains("invalid signature").not()),
+        );
+}
+
+#[test]
+fn fetch_rejects_tampered_contents() {
+    use gistit_proto::prost::Message;
+    use gistit_proto::Gistit;
+
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR])
+        .assert()
+        .success();
+
+    // A malicious server swaps the contents
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    let stored = tmp.child("server/gistits").child(&hash);
+    let mut tampered = Gistit::from_bytes(std::fs::read(stored.path()).unwrap()).unwrap();
+    tampered.inner[0].data = "fn main() { std::process::exit(1); }".to_owned();
+    stored.write_binary(&tampered.encode_to_vec()).unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--save", "--from", "server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("don't match the requested hash"));
+    tmp.child("data/saved/main.rs")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn send_and_fetch_are_kept_in_history() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR])
+        .assert()
+        .success();
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash, "--from", "server"])
+        .assert()
+        .success();
+
+    gistit(&tmp, &server_url)
+        .arg("log")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(&hash[..12]).count(2))
+        .stdout(predicate::str::contains("main.rs"))
+        .stdout(predicate::str::contains(AUTHOR));
+
+    gistit(&tmp, &server_url)
+        .args(["log", "--direction", "fetched", "--lang", "rust"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fetched").count(1))
+        .stdout(predicate::str::contains("sent").not());
+
+    gistit(&tmp, &server_url)
+        .args(["log", "--lang", "python"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    gistit(&tmp, &server_url)
+        .args(["log", "--since", "2999-01-01"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    // Previewed from the local copy, with the server gone
+    std::fs::remove_dir_all(tmp.child("server/gistits").path()).unwrap();
+    gistit(&tmp, &server_url)
+        .args(["log", "--show", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("alright alright alright"));
+}
+
+#[test]
+fn fetch_tries_each_source_in_order() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR])
+        .assert()
+        .success();
+
+    // Sent gistits are kept in the history, so no need to hit the network
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    gistit(&tmp, &server_url)
+        .args(["fetch", &hash])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Fetched from local cache"));
+
+    // Somebody else's cache is empty and there's no node running
+    gistit(&tmp, &server_url)
+        .env(env::GISTIT_DATA_VAR, tmp.child("other").path())
+        .args(["fetch", &hash])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Fetched from server"));
+
+    gistit(&tmp, &server_url)
+        .env(env::GISTIT_DATA_VAR, tmp.child("other").path())
+        .args(["fetch", &"a".repeat(64), "--from", "cache,server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("gistit hash not found"));
+}
+
+#[test]
+fn send_via_every_backend_reports_each_one() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    // No node running, only the server gets it
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "p2p,server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("gistit node is not running"))
+        .stderr(predicate::str::contains(format!("hash: '{}'", hash)));
+    tmp.child("server/gistits")
+        .child(&hash)
+        .assert(predicate::path::exists());
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "p2p"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed to send gistit"));
+}
+
+#[test]
+fn send_and_fetch_through_server_profiles() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let staging_url = spawn_server(&tmp.child("staging"));
+    let production_url = spawn_server(&tmp.child("production"));
+    tmp.child("config/config.toml")
+        .write_str(&format!(
+            "server = \"production\"\n\n\
+             [servers.staging]\nurl = \"{}\"\nweb_url = \"https://staging.gistit.dev/h/\"\n\n\
+             [servers.production]\nurl = \"{}\"\nmax_size = 30\n",
+            staging_url, production_url
+        ))
+        .unwrap();
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    // No `GISTIT_SERVER_URL`, the profiles decide
+    let gistit = || common::gistit(&tmp);
+
+    // Too large for production
+    gistit()
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("file size not allowed"));
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    gistit()
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "server", "--server", "staging"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "url: 'https://staging.gistit.dev/h/{}'",
+            hash
+        )));
+    tmp.child("staging/gistits")
+        .child(&hash)
+        .assert(predicate::path::exists());
+    tmp.child("production/gistits")
+        .child(&hash)
+        .assert(predicate::path::missing());
+
+    gistit()
+        .args(["fetch", &hash, "--from", "server"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("gistit hash not found"));
+    gistit()
+        .args(["fetch", &hash, "--from", "server", "--server", "staging"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Fetched from server"));
+
+    gistit()
+        .arg("log")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("server staging").count(2));
+
+    gistit()
+        .args(["fetch", &hash, "--server", "nope"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("unknown server profile 'nope'"));
+}
+
+#[test]
+fn send_defaults_to_your_author() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .args(["whoami", AUTHOR])
+        .assert()
+        .success();
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--via", "server"])
+        .assert()
+        .success();
+
+    // Same author, same hash
+    tmp.child("server/gistits")
+        .child(hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]))
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn send_detects_or_overrides_the_language() {
+    use gistit_proto::Gistit;
+
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+    let stored = |hash: &str| {
+        Gistit::from_bytes(std::fs::read(tmp.child("server/gistits").child(hash).path()).unwrap())
+            .unwrap()
+    };
+
+    let dockerfile = tmp.child("Dockerfile");
+    dockerfile
+        .write_str("FROM rust:latest\nRUN cargo build\n")
+        .unwrap();
+    let script = tmp.child("deploy");
+    script
+        .write_str("#!/usr/bin/env bash\necho alright\n")
+        .unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(dockerfile.path())
+        .arg(script.path())
+        .args(["--author", AUTHOR, "--via", "server"])
+        .assert()
+        .success();
+    let payload = stored(&hash(
+        AUTHOR,
+        None,
+        [
+            ("Dockerfile", &b"FROM rust:latest\nRUN cargo build\n"[..]),
+            ("deploy", &b"#!/usr/bin/env bash\necho alright\n"[..]),
+        ],
+    ));
+    assert_eq!(payload.inner[0].lang, "docker");
+    assert_eq!(payload.inner[1].lang, "bash");
+
+    gistit(&tmp, &server_url)
+        .args(["--author", AUTHOR, "--via", "server", "--lang", "rs"])
+        .write_stdin(DATA)
+        .assert()
+        .success();
+    assert_eq!(
+        stored(&hash(AUTHOR, None, [("stdin", DATA.as_bytes())])).inner[0].lang,
+        "rust"
+    );
+
+    gistit(&tmp, &server_url)
+        .args(["--author", AUTHOR, "--via", "server", "--lang", "klingon"])
+        .write_stdin(DATA)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("unknown language"));
+}
+
+#[test]
+fn send_and_fetch_binary_files_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    png.extend((0..=255).rev());
+    let image = tmp.child("shot.png");
+    image.write_binary(&png).unwrap();
+    // "café" in Latin-1
+    let latin1 = b"/* caf\xe9 au lait */\nint main(void) { return 0; }\n".to_vec();
+    let legacy = tmp.child("legacy.c");
+    legacy.write_binary(&latin1).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(image.path())
+        .arg(legacy.path())
+        .args(["--author", AUTHOR, "--via", "server"])
+        .assert()
+        .success();
+
+    let files_hash = hash(AUTHOR, None, [("shot.png", &*png), ("legacy.c", &*latin1)]);
+    let stored = gistit_proto::Gistit::from_bytes(
+        std::fs::read(tmp.child("server/gistits").child(&files_hash).path()).unwrap(),
+    )
+    .unwrap();
+    assert!(stored.inner.iter().all(gistit_proto::Inner::is_binary));
+    assert_eq!(stored.inner[1].lang, "c");
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &files_hash, "--from", "server"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "00000000  89 50 4e 47 0d 0a 1a 0a",
+        ))
+        .stdout(predicate::str::contains("|.PNG............|"));
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &files_hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read(tmp.child("data/saved/shot.png")).unwrap(),
+        png
+    );
+    assert_eq!(
+        std::fs::read(tmp.child("data/saved/legacy.c")).unwrap(),
+        latin1
+    );
+
+    gistit(&tmp, &server_url)
+        .args(["--author", AUTHOR, "--via", "server"])
+        .write_stdin(png.clone())
+        .assert()
+        .success();
+    tmp.child("server/gistits")
+        .child(hash(AUTHOR, None, [("stdin", &*png)]))
+        .assert(predicate::path::exists());
+
+    gistit(&tmp, &server_url)
+        .arg(image.path())
+        .args(["--author", AUTHOR, "--via", "github"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("github can't hold binary files"));
+
+    gistit(&tmp, &server_url)
+        .arg(image.path())
+        .args(["--author", AUTHOR, "--via", "server,github", "--encrypt"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("github can't be encrypted"));
+}
+
+#[test]
+fn send_and_fetch_compressed_logs_through_local_server() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+
+    // Four times the size limit, a fraction of it once compressed
+    let log: String = (0..5000)
+        .map(|line| {
+            format!(
+                "2022-03-01T12:00:{:02}Z INFO request served in 3ms\n",
+                line % 60
+            )
+        })
+        .collect();
+    assert!(log.len() > 4 * gistit_project::var::GISTIT_MAX_SIZE);
+    let input = tmp.child("app.log");
+    input.write_str(&log).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "server"])
+        .assert()
+        .success();
+
+    let stored = std::fs::read_dir(tmp.child("server/gistits").path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let stored = gistit_proto::Gistit::from_bytes(std::fs::read(stored.path()).unwrap()).unwrap();
+    assert!(stored.inner[0].is_compressed());
+    assert!(stored.inner[0].contents().len() < gistit_project::var::GISTIT_MAX_SIZE);
+    // Hashed as sent, not as carried
+    assert_eq!(
+        stored.hash,
+        hash(AUTHOR, None, [("app.log", log.as_bytes())])
+    );
+
+    gistit(&tmp, &server_url)
+        .args(["fetch", &stored.hash, "--save", "--from", "server"])
+        .assert()
+        .success();
+    tmp.child("data/saved/app.log").assert(log);
+}
+
+#[test]
+fn send_refuses_stdin_bigger_than_the_picked_backends_take() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+    tmp.child("config/config.toml")
+        .write_str("[servers.default]\nmax_size = 30\n")
+        .unwrap();
+
+    // Past what is worth reading to send 30 bytes, whatever the node would take
+    gistit(&tmp, &server_url)
+        .args(["--author", AUTHOR, "--via", "server"])
+        .write_stdin("a".repeat(40_000))
+        .assert()
+        .stderr(predicate::str::contains("stdin is too big to be sent"));
+    let mut stored = std::fs::read_dir(tmp.child("server/gistits").path()).unwrap();
+    assert!(stored.next().is_none());
+}
+
+#[test]
+fn send_to_picked_profile_over_server_url() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    let server_url = spawn_server(&tmp.child("server"));
+    let staging_url = spawn_server(&tmp.child("staging"));
+    tmp.child("config/config.toml")
+        .write_str(&format!("[servers.staging]\nurl = \"{}\"\n", staging_url))
+        .unwrap();
+
+    let input = tmp.child("main.rs");
+    input.write_str(DATA).unwrap();
+
+    gistit(&tmp, &server_url)
+        .arg(input.path())
+        .args(["--author", AUTHOR, "--via", "server", "--server", "staging"])
+        .assert()
+        .success();
+
+    let hash = hash(AUTHOR, None, [("main.rs", DATA.as_bytes())]);
+    tmp.child("staging/gistits")
+        .child(&hash)
+        .assert(predicate::path::exists());
+    tmp.child("server/gistits")
+        .child(&hash)
+        .assert(predicate::path::missing());
+}
@@ -0,0 +1,62 @@
+//! End to end tests of the persistent author
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+mod common;
+
+fn gistit(tmp: &assert_fs::TempDir) -> Command {
+    let mut cmd = common::gistit(tmp);
+    cmd.current_dir(tmp.path());
+    cmd
+}
+
+fn whoami(tmp: &assert_fs::TempDir) -> String {
+    let output = gistit(tmp).arg("whoami").output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+#[test]
+fn whoami_defaults_to_git_user_name() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+    tmp.child("gitconfig")
+        .write_str("[user]\n\tname = Matthew McConaughey\n")
+        .unwrap();
+
+    assert_eq!(whoami(&tmp), "Matthew McConaughey");
+
+    // Kept from now on, whatever git says
+    tmp.child("gitconfig")
+        .write_str("[user]\n\tname = Woody Harrelson\n")
+        .unwrap();
+    assert_eq!(whoami(&tmp), "Matthew McConaughey");
+    tmp.child("config/config.toml")
+        .assert(predicate::str::contains("author = \"Matthew McConaughey\""));
+}
+
+#[test]
+fn whoami_generates_a_name_once() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+
+    let author = whoami(&tmp);
+    assert!(!author.is_empty());
+    assert_eq!(whoami(&tmp), author);
+}
+
+#[test]
+fn whoami_changes_the_author() {
+    let tmp = assert_fs::TempDir::new().unwrap();
+
+    gistit(&tmp)
+        .args(["whoami", "Woody Harrelson"])
+        .assert()
+        .success();
+    assert_eq!(whoami(&tmp), "Woody Harrelson");
+
+    gistit(&tmp)
+        .args(["whoami", "W"])
+        .assert()
+        .stderr(predicate::str::contains("invalid author character length"));
+    assert_eq!(whoami(&tmp), "Woody Harrelson");
+}
@@ -1,20 +1,17 @@
 use std::io;
-use std::iter::once;
-use std::str::{self, FromStr};
+use std::str;
 use std::time::Duration;
 
 use gistit_project::var;
-use gistit_proto::bytes::BytesMut;
 
 use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
 use libp2p::core::ProtocolName;
 use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use libp2p::{autonat, Multiaddr, NetworkBehaviour};
+use libp2p::{autonat, NetworkBehaviour};
 
 use libp2p::autonat::{Behaviour as Autonat, Event as AutonatEvent};
 use libp2p::core::PeerId;
 use libp2p::identify::{Identify, IdentifyConfig, IdentifyEvent};
-use libp2p::kad::record::store::MemoryStore;
 use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent};
 use libp2p::ping::{Behaviour as PingBehaviour, Config as PingConfig, Event as PingEvent, Ping};
 use libp2p::relay::v2::client::{self, Client, Event as ClientEvent};
@@ -26,11 +23,13 @@ use libp2p::request_response::{
 
 use async_trait::async_trait;
 
+use gistit_proto::exchange::response::{self, Manifest};
+use gistit_proto::exchange::{self, request};
 use gistit_proto::prost::Message;
 use gistit_proto::Gistit;
 
 use crate::config::Config;
-use crate::Result;
+use crate::store::PersistentStore;
 
 pub const BOOTNODES: [&str; 4] = [
     "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
@@ -45,7 +44,7 @@ pub const BOOTADDR: &str = "/dnsaddr/bootstrap.libp2p.io";
 #[behaviour(out_event = "Event", event_process = false)]
 pub struct Behaviour {
     pub request_response: RequestResponse<ExchangeCodec>,
-    pub kademlia: Kademlia<MemoryStore>,
+    pub kademlia: Kademlia<PersistentStore>,
     pub identify: Identify,
     pub relay: Relay,
     pub autonat: Autonat,
@@ -56,26 +55,26 @@ pub struct Behaviour {
 impl Behaviour {
     pub fn new_behaviour_and_transport(
         config: &Config,
-    ) -> Result<(Self, client::transport::ClientTransport)> {
+    ) -> (Self, client::transport::ClientTransport) {
+        // Preferred first
         let request_response = RequestResponse::new(
-            ExchangeCodec,
-            once((ExchangeProtocol, ProtocolSupport::Full)),
+            ExchangeCodec::new(config.max_size),
+            [
+                (ExchangeProtocol::V2, ProtocolSupport::Full),
+                (ExchangeProtocol::V1, ProtocolSupport::Full),
+            ],
             RequestResponseConfig::default(),
         );
 
         let kademlia = {
             let mut cfg = KademliaConfig::default();
             cfg.set_query_timeout(Duration::from_secs(5 * 60));
-            let store = MemoryStore::new(config.peer_id);
+            let store = PersistentStore::open(config.peer_id, &config.data_path);
             let mut behaviour = Kademlia::with_config(config.peer_id, store, cfg);
 
-            let bootaddr = Multiaddr::from_str(BOOTADDR)?;
             if config.bootstrap {
-                for peer in BOOTNODES {
-                    behaviour.add_address(
-                        &PeerId::from_str(peer).expect("peer id to be valid"),
-                        bootaddr.clone(),
-                    );
+                for (peer, address) in &config.bootnodes {
+                    behaviour.add_address(peer, address.clone());
                 }
 
                 behaviour.bootstrap().expect("to bootstrap");
@@ -102,12 +101,8 @@ impl Behaviour {
                 autonat::Config::default(),
             );
             if config.bootstrap {
-                for peer in BOOTNODES {
-                    let bootaddr = Multiaddr::from_str(BOOTADDR)?;
-                    behaviour.add_server(
-                        PeerId::from_str(peer).expect("peer id to be valid"),
-                        Some(bootaddr),
-                    );
+                for (peer, address) in &config.bootnodes {
+                    behaviour.add_server(*peer, Some(address.clone()));
                 }
             }
 
@@ -116,7 +111,7 @@ impl Behaviour {
 
         let ping = PingBehaviour::new(PingConfig::new().with_keep_alive(true));
 
-        Ok((
+        (
             Self {
                 request_response,
                 kademlia,
@@ -127,7 +122,7 @@ impl Behaviour {
                 client,
             },
             client_transport,
-        ))
+        )
     }
 }
 
@@ -184,27 +179,80 @@ impl From<ClientEvent> for Event {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct ExchangeProtocol;
+/// How nodes exchange gistits. `/gistit/2` sends a manifest and then content addressed chunks,
+/// `/gistit/1` the whole gistit at once. Both are offered, nodes speaking only the first one
+/// still get and serve small gistits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeProtocol {
+    V1,
+    V2,
+}
 
 impl ProtocolName for ExchangeProtocol {
     fn protocol_name(&self) -> &[u8] {
-        b"/gistit/1"
+        match self {
+            Self::V1 => b"/gistit/1",
+            Self::V2 => b"/gistit/2",
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct ExchangeCodec;
+/// Biggest `/gistit/2` request, a hash and a chunk digest
+const REQUEST_MAX_SIZE: usize = 1024;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Request(pub Vec<u8>);
+#[derive(Debug, Clone)]
+pub struct ExchangeCodec {
+    /// Largest gistit accepted, see `daemon.max_size`
+    max_size: usize,
+}
+
+impl ExchangeCodec {
+    pub const fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// The whole gistit, asked for by `/gistit/1` nodes
+    Gistit(String),
+    /// The manifest of a gistit, written as a `/gistit/1` request to nodes speaking only that
+    Manifest(String),
+    Chunk {
+        hash: String,
+        index: u32,
+        digest: Vec<u8>,
+    },
+}
 
+impl Request {
+    pub fn hash(&self) -> &str {
+        match self {
+            Self::Gistit(hash) | Self::Manifest(hash) | Self::Chunk { hash, .. } => hash,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq)]
-pub struct Response(pub Gistit);
+pub enum Response {
+    Gistit(Gistit),
+    Manifest(Manifest),
+    Chunk { index: u32, data: Vec<u8> },
+}
 
 impl std::fmt::Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0)
+        match self {
+            Self::Gistit(gistit) => write!(f, "gistit {}", gistit.hash),
+            Self::Manifest(manifest) => write!(
+                f,
+                "manifest of {}, {} chunk(s)",
+                manifest.hash,
+                manifest.chunks.len()
+            ),
+            Self::Chunk { index, data } => write!(f, "chunk {} of {} bytes", index, data.len()),
+        }
     }
 }
 
@@ -214,6 +262,26 @@ impl std::error::Error for Response {
     }
 }
 
+/// Decodes a gistit sent whole, its files must carry their contents where their encoding says,
+/// `raw` for binary ones
+fn decode_gistit(bytes: &[u8]) -> io::Result<Gistit> {
+    let gistit = Gistit::decode(bytes).map_err(|_| io::ErrorKind::InvalidInput)?;
+    gistit
+        .check_encoding()
+        .map_err(|_| io::ErrorKind::InvalidData)?;
+    log::debug!(
+        "Read gistit: {} with {} file(s), {} binary",
+        gistit.hash,
+        gistit.inner.len(),
+        gistit
+            .inner
+            .iter()
+            .filter(|inner| inner.is_binary())
+            .count()
+    );
+    Ok(gistit)
+}
+
 #[async_trait]
 impl RequestResponseCodec for ExchangeCodec {
     type Protocol = ExchangeProtocol;
@@ -222,63 +290,134 @@ impl RequestResponseCodec for ExchangeCodec {
 
     async fn read_request<T: Send + Unpin + AsyncRead>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Request> {
-        let hash = read_length_prefixed(io, var::GISTIT_HASH_LENGTH).await?;
-        log::debug!("Read request {:?}", std::str::from_utf8(&hash).unwrap());
+        let request = match protocol {
+            ExchangeProtocol::V1 => {
+                let hash = read_length_prefixed(io, var::GISTIT_HASH_LENGTH).await?;
+                if hash.is_empty() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Request::Gistit(String::from_utf8(hash).map_err(|_| io::ErrorKind::InvalidData)?)
+            }
+            ExchangeProtocol::V2 => {
+                let bytes = read_length_prefixed(io, REQUEST_MAX_SIZE).await?;
+                match exchange::Request::decode(&*bytes)
+                    .map_err(|_| io::ErrorKind::InvalidInput)?
+                    .kind
+                {
+                    Some(request::Kind::Manifest(request::Manifest { hash })) => {
+                        Request::Manifest(hash)
+                    }
+                    Some(request::Kind::Chunk(request::Chunk {
+                        hash,
+                        index,
+                        digest,
+                    })) => Request::Chunk {
+                        hash,
+                        index,
+                        digest,
+                    },
+                    None => return Err(io::ErrorKind::InvalidInput.into()),
+                }
+            }
+        };
+        log::debug!("Read request {:?}", request);
 
-        if hash.is_empty() {
-            Err(io::ErrorKind::UnexpectedEof.into())
-        } else {
-            Ok(Request(hash))
-        }
+        Ok(request)
     }
 
     async fn read_response<T: Send + Unpin + AsyncRead>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response> {
-        let bytes = read_length_prefixed(io, var::GISTIT_MAX_SIZE).await?;
-        let gistit = Gistit::decode(&*bytes).map_err(|_| io::ErrorKind::InvalidInput)?;
-        log::debug!("Read response: {:?}", gistit);
-
+        let bytes = read_length_prefixed(io, var::max_payload_size(self.max_size)).await?;
         if bytes.is_empty() {
-            Err(io::ErrorKind::UnexpectedEof.into())
-        } else {
-            Ok(Response(gistit))
+            return Err(io::ErrorKind::UnexpectedEof.into());
         }
+
+        let response = match protocol {
+            ExchangeProtocol::V1 => Response::Gistit(decode_gistit(&bytes)?),
+            ExchangeProtocol::V2 => match exchange::Response::decode(&*bytes)
+                .map_err(|_| io::ErrorKind::InvalidInput)?
+                .kind
+            {
+                Some(response::Kind::Gistit(gistit)) => {
+                    // Checked the same way as a gistit sent over `/gistit/1`
+                    Response::Gistit(decode_gistit(&gistit.encode_to_vec())?)
+                }
+                Some(response::Kind::Manifest(manifest)) => Response::Manifest(manifest),
+                Some(response::Kind::Chunk(response::Chunk { index, data })) => {
+                    Response::Chunk { index, data }
+                }
+                None => return Err(io::ErrorKind::InvalidInput.into()),
+            },
+        };
+        log::debug!("Read response: {}", response);
+
+        Ok(response)
     }
 
     async fn write_request<T: Send + Unpin + AsyncWrite>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
-        Request(req): Self::Request,
+        request: Self::Request,
     ) -> io::Result<()> {
-        log::debug!("Write request {:?}", std::str::from_utf8(&req).unwrap());
-        write_length_prefixed(io, req).await?;
+        log::debug!("Write request {:?}", request);
+        let bytes = match (protocol, request) {
+            (ExchangeProtocol::V1, Request::Gistit(hash) | Request::Manifest(hash)) => {
+                hash.into_bytes()
+            }
+            // Chunks are only asked for after a `/gistit/2` manifest
+            (ExchangeProtocol::V1, Request::Chunk { .. }) => {
+                return Err(io::ErrorKind::Unsupported.into())
+            }
+            (ExchangeProtocol::V2, Request::Gistit(hash) | Request::Manifest(hash)) => {
+                exchange::Request::manifest(hash).encode_to_vec()
+            }
+            (
+                ExchangeProtocol::V2,
+                Request::Chunk {
+                    hash,
+                    index,
+                    digest,
+                },
+            ) => exchange::Request::chunk(hash, index, digest).encode_to_vec(),
+        };
+
+        write_length_prefixed(io, bytes).await?;
         io.close().await?;
         Ok(())
     }
 
     async fn write_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
-        Response(gistit): Self::Response,
+        response: Self::Response,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let mut buf = BytesMut::with_capacity(var::GISTIT_MAX_SIZE);
-        gistit
-            .encode(&mut buf)
-            .map_err(|_| io::ErrorKind::InvalidInput)?;
-        log::debug!("Write response {:?} bytes", buf.len());
+        log::debug!("Write response {}", response);
+        let bytes = match (protocol, response) {
+            (ExchangeProtocol::V1, Response::Gistit(gistit)) => gistit.encode_to_vec(),
+            (ExchangeProtocol::V1, _) => return Err(io::ErrorKind::Unsupported.into()),
+            (ExchangeProtocol::V2, Response::Gistit(gistit)) => {
+                exchange::Response::gistit(gistit).encode_to_vec()
+            }
+            (ExchangeProtocol::V2, Response::Manifest(manifest)) => {
+                exchange::Response::manifest(manifest).encode_to_vec()
+            }
+            (ExchangeProtocol::V2, Response::Chunk { index, data }) => {
+                exchange::Response::chunk(index, data).encode_to_vec()
+            }
+        };
 
-        write_length_prefixed(io, buf).await?;
+        write_length_prefixed(io, bytes).await?;
         io.close().await?;
 
         Ok(())
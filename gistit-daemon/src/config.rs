@@ -6,12 +6,15 @@ use std::str::FromStr;
 
 use libp2p::core::{Multiaddr, PeerId};
 use libp2p::identity::{self, ed25519, Keypair};
-use libp2p::multiaddr::multiaddr;
+use libp2p::multiaddr::{multiaddr, Protocol};
 
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use zeroize::{Zeroize, Zeroizing};
 
+use gistit_project::settings::Settings;
+
+use crate::behaviour::{BOOTADDR, BOOTNODES};
 use crate::{Error, Result};
 
 pub struct Config {
@@ -19,37 +22,81 @@ pub struct Config {
     pub keypair: Keypair,
     pub runtime_path: PathBuf,
     pub config_path: PathBuf,
+    pub data_path: PathBuf,
     pub multiaddr: Multiaddr,
+    /// More addresses to listen on
+    pub listen: Vec<String>,
     pub bootstrap: bool,
+    pub bootnodes: Vec<(PeerId, Multiaddr)>,
+    /// Largest gistit sent and fetched, see `daemon.max_size`
+    pub max_size: usize,
 }
 
 impl Debug for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{:?} {:?} {:?} {:?}",
-            self.peer_id, self.runtime_path, self.config_path, self.multiaddr,
+            "{:?} {:?} {:?} {:?} {:?}",
+            self.peer_id, self.runtime_path, self.config_path, self.data_path, self.multiaddr,
         )
     }
 }
 
 impl Config {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_args(
         runtime_path: Option<PathBuf>,
         config_path: Option<PathBuf>,
+        data_path: Option<PathBuf>,
         config_file: Option<PathBuf>,
         host: Option<Ipv4Addr>,
         port: Option<u16>,
+        listen: Vec<String>,
         bootstrap: bool,
     ) -> Result<Self> {
         gistit_project::path::init()?;
 
-        let host = host.unwrap_or_else(|| Ipv4Addr::new(0, 0, 0, 0));
-        let port = port.unwrap_or(0_u16);
-        let multiaddr = multiaddr!(Ip4(host), Tcp(port));
-
         let runtime_path = runtime_path.unwrap_or(gistit_project::path::runtime()?);
         let config_path = config_path.unwrap_or(gistit_project::path::config()?);
+        let data_path = data_path.unwrap_or(gistit_project::path::data()?);
+        let settings = Settings::load(&config_path)?;
+
+        let host = match host {
+            Some(host) => host,
+            None => settings
+                .text("daemon.host")
+                .unwrap_or_default()
+                .parse()
+                .map_err(|_| Error::Parse("invalid `daemon.host` setting"))?,
+        };
+        let port = match port {
+            Some(port) => port,
+            None => settings.port("daemon.port")?.unwrap_or(0_u16),
+        };
+        let multiaddr = multiaddr!(Ip4(host), Tcp(port));
+
+        let listen = if listen.is_empty() {
+            settings.list("daemon.listen").unwrap_or_default()
+        } else {
+            listen
+        };
+        let bootnodes = match settings.list("daemon.bootnodes") {
+            Some(addresses) => addresses
+                .iter()
+                .map(|address| bootnode(address))
+                .collect::<Result<Vec<_>>>()?,
+            None => BOOTNODES
+                .iter()
+                .map(|peer| {
+                    Ok((
+                        PeerId::from_str(peer).expect("peer id to be valid"),
+                        Multiaddr::from_str(BOOTADDR)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let max_size = usize::try_from(settings.node_max_size()?)
+            .map_err(|_| Error::Parse("invalid `daemon.max_size` setting"))?;
         let node_config = config_file.unwrap_or_else(|| config_path.join("node-config"));
 
         let (peer_id, keypair) = if fs::metadata(&node_config).is_ok() {
@@ -86,12 +133,28 @@ impl Config {
             keypair,
             runtime_path,
             config_path,
+            data_path,
             multiaddr,
+            listen,
             bootstrap,
+            bootnodes,
+            max_size,
         })
     }
 }
 
+/// Splits a bootnode multiaddr ending in `/p2p/<peer id>` into the peer and its address
+fn bootnode(address: &str) -> Result<(PeerId, Multiaddr)> {
+    let mut address = Multiaddr::from_str(address)?;
+    match address.pop() {
+        Some(Protocol::P2p(hash)) => Ok((
+            PeerId::from_multihash(hash).map_err(|_| Error::Parse("invalid bootnode peer id"))?,
+            address,
+        )),
+        _ => Err(Error::Parse("bootnodes must end in `/p2p/<peer id>`")),
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct NodeKey {
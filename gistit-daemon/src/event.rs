@@ -4,70 +4,192 @@ use libp2p::identify::{IdentifyEvent, IdentifyInfo};
 use libp2p::kad::record::Key;
 use libp2p::kad::{GetProvidersError, GetProvidersOk, KademliaEvent, QueryResult};
 use libp2p::multiaddr::Protocol;
-use libp2p::request_response::{RequestResponseEvent, RequestResponseMessage};
+use libp2p::request_response::{RequestId, RequestResponseEvent, RequestResponseMessage};
+use libp2p::PeerId;
 
+use gistit_project::var;
+use gistit_proto::exchange::response::Manifest;
+use gistit_proto::prost::Message;
 use gistit_proto::Instruction;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use crate::behaviour::{Request, Response};
 use crate::node::Node;
 use crate::Result;
 
+#[allow(clippy::too_many_lines)]
 pub async fn handle_request_response(
     node: &mut Node,
     event: RequestResponseEvent<Request, Response>,
 ) -> Result<()> {
     match event {
-        RequestResponseEvent::Message { message, .. } => match message {
+        RequestResponseEvent::Message { peer, message } => match message {
             RequestResponseMessage::Request {
-                request, channel, ..
+                request_id,
+                request,
+                channel,
             } => {
-                let key = Key::new(&request.0);
-                info!("Request response 'Message::Request' for {:?}", key);
-                let file = node
-                    .to_provide
-                    .get(&key)
-                    .expect("to be providing {key}")
-                    .clone();
+                let response = if let Some(response) = respond_to(node, request_id, request) {
+                    response
+                } else {
+                    return Ok(());
+                };
 
-                node.swarm
+                let sent = node
+                    .swarm
                     .behaviour_mut()
                     .request_response
-                    .send_response(channel, Response(file))?;
+                    .send_response(channel, response);
+
+                if sent.is_err() {
+                    node.restore_once(request_id);
+                }
+                sent?;
             }
             RequestResponseMessage::Response {
                 request_id,
                 response,
             } => {
                 info!("Request response 'Message::Response'");
-                let gistit = response.0;
+                let gistit = match response {
+                    Response::Gistit(gistit) => gistit,
+                    Response::Manifest(manifest) => {
+                        return handle_manifest(node, request_id, peer, manifest).await;
+                    }
+                    Response::Chunk { index, data } => {
+                        return node.receive_chunk(request_id, index, data).await;
+                    }
+                };
                 let key = Key::new(&gistit.hash.as_bytes());
 
-                if node.pending_receive_file.remove(&key) {
-                    node.bridge.connect_blocking()?;
-                    node.bridge
-                        .send(Instruction::respond_fetch(Some(gistit)))
-                        .await?;
+                if !node.pending_request_file.contains_key(&request_id) {
+                    debug!("Dropping response to a cancelled or answered request");
+                    return Ok(());
+                }
+
+                // Providers can send anything, make sure it's what we asked for
+                if node.pending_request_file.get(&request_id) != Some(&key)
+                    || gistit.compute_hash() != gistit.hash
+                {
+                    warn!("Provider sent a gistit not matching the requested hash");
+                    if let Some(key) = node.fail_request(request_id) {
+                        node.respond_fetch(&key, None).await?;
+                    }
+                    return Ok(());
                 }
-                node.pending_request_file.remove(&request_id);
+
+                node.respond_fetch(&key, Some(gistit)).await?;
             }
         },
         RequestResponseEvent::OutboundFailure {
             request_id, error, ..
         } => {
             error!("Request response outbound failure {:?}", error);
-            node.pending_request_file.remove(&request_id);
-            node.bridge.connect_blocking()?;
-            node.bridge.send(Instruction::respond_fetch(None)).await?;
+            if let Some((key, _)) = node.pending_request_chunk.remove(&request_id) {
+                node.fail_transfer(&key).await?;
+            } else if let Some(key) = node.fail_request(request_id) {
+                node.respond_fetch(&key, None).await?;
+            }
         }
-        RequestResponseEvent::InboundFailure { error, .. } => {
+        RequestResponseEvent::InboundFailure {
+            request_id, error, ..
+        } => {
             error!("Request response inbound failure {:?}", error);
+            node.restore_once(request_id);
         }
-        RequestResponseEvent::ResponseSent { .. } => (),
+        RequestResponseEvent::ResponseSent { request_id, .. } => node.finish_once(request_id),
     }
     Ok(())
 }
 
+/// What to answer a provider request with, `None` to drop the channel, which fails the request on
+/// the other end. One-shot gistits are taken out of `to_provide` until the response is sent
+pub fn respond_to(node: &mut Node, request_id: RequestId, request: Request) -> Option<Response> {
+    let key = Key::new(&request.hash());
+    info!("Request response 'Message::Request' for {:?}", key);
+
+    let gistit = if let Some(gistit) = node.providing(&key) {
+        gistit.clone()
+    } else {
+        warn!("Not providing {:?}, it may have expired", key);
+        return None;
+    };
+
+    let response = match request {
+        // One-shot gistits are never split, they're gone once sent
+        Request::Manifest(_)
+            if !node.to_provide_once.contains(&key)
+                && gistit.encoded_len() > var::GISTIT_CHUNK_SIZE =>
+        {
+            let manifest = node.manifests.entry(key).or_insert_with(|| {
+                Manifest::new(
+                    gistit.hash.clone(),
+                    &gistit.encode_to_vec(),
+                    var::GISTIT_CHUNK_SIZE,
+                )
+            });
+            Response::Manifest(manifest.clone())
+        }
+        Request::Gistit(_) | Request::Manifest(_) => {
+            node.take_once(request_id, &key);
+            Response::Gistit(gistit)
+        }
+        Request::Chunk { index, digest, .. } => {
+            let encoded = gistit.encode_to_vec();
+            let data = if let Some(data) = node
+                .manifests
+                .get(&key)
+                .and_then(|manifest| manifest.chunk(&encoded, index, &digest))
+            {
+                data.to_vec()
+            } else {
+                warn!("No chunk {} of {:?} to send", index, key);
+                return None;
+            };
+            Response::Chunk { index, data }
+        }
+    };
+    Some(response)
+}
+
+/// Starts a transfer from the manifest a provider sent, if it's the gistit we asked for and not
+/// bigger than we accept
+async fn handle_manifest(
+    node: &mut Node,
+    request_id: RequestId,
+    peer: PeerId,
+    manifest: Manifest,
+) -> Result<()> {
+    let key = Key::new(&manifest.hash);
+    match node.pending_request_file.get(&request_id) {
+        Some(requested) if *requested == key => {}
+        Some(_) => {
+            warn!("Provider sent a manifest not matching the requested hash");
+            if let Some(key) = node.fail_request(request_id) {
+                node.respond_fetch(&key, None).await?;
+            }
+            return Ok(());
+        }
+        None => {
+            debug!("Dropping manifest of a cancelled or answered request");
+            return Ok(());
+        }
+    }
+
+    if !manifest.is_consistent() || manifest.size > var::max_payload_size(node.max_size) as u64 {
+        warn!(
+            "Refusing manifest of {} bytes from {:?}",
+            manifest.size, peer
+        );
+        if let Some(key) = node.fail_request(request_id) {
+            node.respond_fetch(&key, None).await?;
+        }
+        return Ok(());
+    }
+
+    node.start_transfer(request_id, peer, manifest).await
+}
+
 pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()> {
     match event {
         KademliaEvent::OutboundQueryCompleted {
@@ -75,8 +197,15 @@ pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()
             result: QueryResult::StartProviding(maybe_provided),
             ..
         } => {
-            node.pending_start_providing.remove(&id);
-            node.bridge.connect_blocking()?;
+            let reply = if let Some((reply, _)) = node.pending_start_providing.remove(&id) {
+                reply
+            } else {
+                // Keep hosting anyway, kademlia publishes provider records again periodically
+                if let Err(provider) = maybe_provided {
+                    warn!("Kademlia republishing failed: {:?}", provider);
+                }
+                return Ok(());
+            };
 
             match maybe_provided {
                 Ok(provider) => {
@@ -85,13 +214,15 @@ pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()
                         .expect("hash format to be valid utf8")
                         .to_owned();
                     node.bridge
-                        .send(Instruction::respond_provide(Some(hash)))
+                        .send(reply, Instruction::respond_provide(Some(hash)))
                         .await?;
                 }
                 Err(provider) => {
                     error!("Kademlia start providing failed: {:?}", provider);
-                    node.to_provide.remove(provider.key());
-                    node.bridge.send(Instruction::respond_provide(None)).await?;
+                    node.unhost(provider.key());
+                    node.bridge
+                        .send(reply, Instruction::respond_provide(None))
+                        .await?;
                 }
             }
             Ok(())
@@ -102,29 +233,25 @@ pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()
             ..
         } => {
             info!("Kademlia get providers: {:?}", maybe_providers);
-            node.pending_get_providers.remove(&id);
-            let mut failed = false;
+            // Cancelled lookups are finished early, nobody waits on them anymore
+            if node.pending_get_providers.remove(&id).is_none() {
+                return Ok(());
+            }
 
             match maybe_providers {
+                // Finding zero providers is also an error
+                Ok(GetProvidersOk { key, providers, .. }) if providers.is_empty() => {
+                    node.respond_fetch(&key, None).await?;
+                }
                 Ok(GetProvidersOk { key, providers, .. }) => {
-                    // Finding zero providers is also an error
-                    if providers.is_empty() {
-                        failed = true;
-                    } else {
-                        node.to_request.push((key, providers));
-                    }
+                    node.to_request.push((key, providers));
                 }
                 Err(GetProvidersError::Timeout { key, .. }) => {
-                    failed = true;
                     error!("No providers for {:?}", key);
+                    node.respond_fetch(&key, None).await?;
                 }
             }
 
-            if failed {
-                node.bridge.connect_blocking()?;
-                node.bridge.send(Instruction::respond_fetch(None)).await?;
-            }
-
             Ok(())
         }
         _ => Ok(()),
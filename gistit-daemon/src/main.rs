@@ -25,6 +25,8 @@ mod config;
 mod error;
 mod event;
 mod node;
+mod store;
+mod transfer;
 
 pub type Error = crate::error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -49,6 +51,10 @@ struct Args {
     /// Override config directory
     config_path: Option<PathBuf>,
 
+    #[clap(long)]
+    /// Override data directory, where hosted gistits are kept
+    data_path: Option<PathBuf>,
+
     #[clap(long)]
     /// IPFS config file to extract key material
     config_file: Option<PathBuf>,
@@ -66,7 +72,7 @@ struct Args {
     dial: Vec<String>,
 
     #[clap(long)]
-    /// Listen to these addresses, useful for relays
+    /// Listen to these addresses, useful for relays. Defaults to the `daemon.listen` setting
     listen: Vec<String>,
 
     #[clap(long)]
@@ -78,6 +84,7 @@ async fn run() -> Result<()> {
     let Args {
         runtime_path,
         config_path,
+        data_path,
         config_file,
         host,
         port,
@@ -89,12 +96,15 @@ async fn run() -> Result<()> {
     let config = Config::from_args(
         runtime_path,
         config_path,
+        data_path,
         config_file,
         host,
         port,
+        listen,
         bootstrap,
     )?;
     log::debug!("Running config: {:?}", config);
+    let listen = config.listen.clone();
 
     let mut node = Node::new(config).await?;
 
@@ -5,11 +5,16 @@ use std::collections::{HashMap, HashSet};
 use std::io;
 use std::string::ToString;
 use std::task::Poll;
+use std::time::Duration;
 
 use either::Either;
 use log::{debug, error, info, warn};
+use tokio::time::{interval, Interval};
 
-use gistit_ipc::{Bridge, Server};
+use gistit_ipc::{Bridge, Reply, Server};
+use gistit_project::var;
+use gistit_proto::exchange::response::Manifest;
+use gistit_proto::prost::Message;
 use gistit_proto::{ipc, Gistit, Instruction};
 
 use libp2p::core::either::EitherError;
@@ -27,8 +32,13 @@ use libp2p::request_response::RequestId;
 use crate::behaviour::{Behaviour, Event, Request};
 use crate::config::Config;
 use crate::event::{handle_identify, handle_kademlia, handle_request_response};
+use crate::store::{self, Chunks, Hosting};
+use crate::transfer::Transfer;
 use crate::Result;
 
+/// How often hosted gistits are checked for expiry and kademlia records are written to disk
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
 /// The main event loop
 pub struct Node {
     pub swarm: Swarm<Behaviour>,
@@ -36,25 +46,57 @@ pub struct Node {
 
     pub pending_dial: HashSet<PeerId>,
 
-    /// Pending kademlia queries to get providers
-    pub pending_get_providers: HashSet<QueryId>,
+    /// Pending kademlia queries to get providers, by the key looked up
+    pub pending_get_providers: HashMap<QueryId, Key>,
 
-    pub pending_start_providing: HashSet<QueryId>,
+    /// Start providing queries and who to respond to. Queries started after a restart or a
+    /// republish are not in here, nobody waits on them
+    pub pending_start_providing: HashMap<QueryId, (Reply, Key)>,
     pub to_provide: HashMap<Key, Gistit>,
 
-    pub pending_request_file: HashSet<RequestId>,
+    /// Hosted gistits on disk, mirrors `to_provide`
+    pub hosting: Hosting,
+
+    /// Keys in `to_provide` to be served a single time
+    pub to_provide_once: HashSet<Key>,
+
+    /// One-shot gistits handed to a requester, waiting for the response to be sent
+    pub pending_send_once: HashMap<RequestId, (Key, Gistit)>,
+
+    /// Requests sent to providers and the key each one asked for
+    pub pending_request_file: HashMap<RequestId, Key>,
+
+    /// Gistits being fetched chunk by chunk over `/gistit/2`
+    pub pending_transfers: HashMap<Key, Transfer>,
+
+    /// Chunk requests in flight, with the key and chunk index each one asked for
+    pub pending_request_chunk: HashMap<RequestId, (Key, u32)>,
+
+    /// Chunks of unfinished transfers, kept on disk to resume them
+    pub chunks: Chunks,
+
+    /// Manifests of hosted gistits already sent, so chunk requests don't split them again
+    pub manifests: HashMap<Key, Manifest>,
+
+    /// Largest gistit sent and fetched, see `daemon.max_size`
+    pub max_size: usize,
 
     /// Stack of request file (`key`) events
     pub to_request: Vec<(Key, HashSet<PeerId>)>,
-    pub pending_receive_file: HashSet<Key>,
+
+    /// Keys being fetched and every request waiting on each
+    pub pending_receive_file: HashMap<Key, Vec<Reply>>,
 
     /// Addresses that can be used as relay
     pub relays: HashSet<Multiaddr>,
+
+    /// Ticks to stop providing expired gistits
+    pub expiry_sweep: Interval,
 }
 
 impl Node {
     pub async fn new(config: Config) -> Result<Self> {
-        let (behaviour, client_transport) = Behaviour::new_behaviour_and_transport(&config)?;
+        let (behaviour, client_transport) = Behaviour::new_behaviour_and_transport(&config);
 
         let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
             .into_authentic(&config.keypair)
@@ -85,22 +127,127 @@ impl Node {
             .build();
         swarm.listen_on(config.multiaddr)?;
 
-        let bridge = gistit_ipc::server(&config.runtime_path)?;
+        let bridge = gistit_ipc::server(&config.runtime_path)?
+            .max_frame_size(var::max_payload_size(config.max_size));
+        let hosting = Hosting::new(&config.data_path)?;
+        let chunks = Chunks::new(&config.data_path)?;
 
-        Ok(Self {
+        let mut node = Self {
             swarm,
             bridge,
             pending_dial: HashSet::default(),
-            pending_start_providing: HashSet::default(),
-            pending_get_providers: HashSet::default(),
-            pending_request_file: HashSet::default(),
-            pending_receive_file: HashSet::default(),
+            pending_start_providing: HashMap::default(),
+            pending_get_providers: HashMap::default(),
+            pending_request_file: HashMap::default(),
+            pending_receive_file: HashMap::default(),
+            pending_transfers: HashMap::default(),
+            pending_request_chunk: HashMap::default(),
+            chunks,
+            manifests: HashMap::default(),
+            max_size: config.max_size,
 
             to_provide: HashMap::default(),
+            hosting,
+            to_provide_once: HashSet::default(),
+            pending_send_once: HashMap::default(),
             to_request: Vec::default(),
 
             relays: HashSet::default(),
-        })
+
+            expiry_sweep: interval(EXPIRY_SWEEP_INTERVAL),
+        };
+        node.rehost()?;
+
+        Ok(node)
+    }
+
+    /// Starts providing the gistits we were hosting before a restart
+    fn rehost(&mut self) -> Result<()> {
+        let now = u128::from(store::now());
+
+        for (gistit, once) in self.hosting.load()? {
+            let key = Key::new(&gistit.hash);
+            if gistit.is_expired(now) {
+                self.hosting.remove(&key);
+                continue;
+            }
+
+            if let Err(err) = self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .start_providing(key.clone())
+            {
+                warn!("Failed to host {} again: {:?}", gistit.hash, err);
+                continue;
+            }
+            info!("Hosting {} again", gistit.hash);
+
+            if once {
+                self.to_provide_once.insert(key.clone());
+            }
+            self.to_provide.insert(key, gistit);
+        }
+        Ok(())
+    }
+
+    /// Announces every hosted gistit again, refreshing its provider records
+    #[allow(clippy::cast_possible_truncation)]
+    fn republish(&mut self) -> u32 {
+        let keys: Vec<Key> = self.to_provide.keys().cloned().collect();
+
+        for key in &keys {
+            if let Err(err) = self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .start_providing(key.clone())
+            {
+                error!("Failed to republish {:?}: {:?}", key, err);
+            }
+        }
+        keys.len() as u32
+    }
+
+    /// Lists hosted gistits, newest first
+    fn hosted(&self) -> Vec<ipc::instruction::Hosted> {
+        let mut hosted: Vec<ipc::instruction::Hosted> = self
+            .to_provide
+            .iter()
+            .map(|(key, gistit)| ipc::instruction::Hosted {
+                hash: gistit.hash.clone(),
+                names: gistit
+                    .inner
+                    .iter()
+                    .map(|inner| inner.name.clone())
+                    .collect(),
+                size: gistit.inner.iter().map(|inner| inner.size).sum(),
+                timestamp: gistit.timestamp.clone(),
+                once: self.to_provide_once.contains(key),
+                expires_at: gistit.expires_at.clone(),
+            })
+            .collect();
+
+        hosted.sort_by_key(|hosted| {
+            std::cmp::Reverse(hosted.timestamp.parse::<u128>().unwrap_or_default())
+        });
+        hosted
+    }
+
+    /// Stops hosting a gistit, both in memory and on disk. Returns whether it was hosted
+    pub fn unhost(&mut self, key: &Key) -> bool {
+        self.swarm.behaviour_mut().kademlia.stop_providing(key);
+        self.to_provide_once.remove(key);
+        self.manifests.remove(key);
+        self.hosting.remove(key);
+        self.to_provide.remove(key).is_some()
+    }
+
+    /// Writes kademlia records to disk, so they survive a restart
+    fn flush_records(&mut self) {
+        if let Err(err) = self.swarm.behaviour_mut().kademlia.store_mut().flush() {
+            error!("Failed to write kademlia records: {}", err);
+        }
     }
 
     pub fn dial_on_init(&mut self, address: &str) -> Result<()> {
@@ -118,19 +265,290 @@ impl Node {
                 swarm_event = self.swarm.next() => self.handle_swarm_event(
                     swarm_event.expect("stream not to end")).await?,
 
-                bridge_event = self.bridge.recv() => self.handle_bridge_event(bridge_event?).await?,
+                bridge_event = self.bridge.recv() => {
+                    let (reply, instruction) = bridge_event?;
+                    self.handle_bridge_event(reply, instruction).await?;
+                }
 
                 request_event = poll_fn(|_| {
                     self.to_request.pop().map_or(Poll::Pending, Poll::Ready)
                 }) => self.handle_request_event(request_event).await?,
+
+                _ = self.expiry_sweep.tick() => {
+                    self.remove_expired();
+                    self.flush_records();
+                }
+            }
+        }
+    }
+
+    /// Returns the gistit hosted under `key`, unless it has expired
+    pub fn providing(&self, key: &Key) -> Option<&Gistit> {
+        self.to_provide
+            .get(key)
+            .filter(|gistit| !gistit.is_expired(u128::from(store::now())))
+    }
+
+    /// Stops providing every expired gistit
+    fn remove_expired(&mut self) {
+        let now = u128::from(store::now());
+        let expired: Vec<Key> = self
+            .to_provide
+            .iter()
+            .filter(|(_, gistit)| gistit.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            info!("Gistit expired, stop providing {:?}", key);
+            self.unhost(&key);
+        }
+    }
+
+    /// Drops a failed request to a provider. Returns the key if it was the last provider being
+    /// asked for it, meaning the fetch has failed
+    pub fn fail_request(&mut self, request_id: RequestId) -> Option<Key> {
+        let key = self.pending_request_file.remove(&request_id)?;
+        self.is_fetch_failed(key)
+    }
+
+    /// Returns the key if nothing is left asking for it, neither a provider nor a transfer
+    fn is_fetch_failed(&self, key: Key) -> Option<Key> {
+        let other_pending = self.pending_transfers.contains_key(&key)
+            || self
+                .pending_request_file
+                .values()
+                .any(|other| *other == key);
+
+        if other_pending {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    /// Starts fetching a gistit chunk by chunk from the provider that sent its manifest, unless
+    /// another provider is already being asked for the chunks
+    pub async fn start_transfer(
+        &mut self,
+        request_id: RequestId,
+        peer: PeerId,
+        manifest: Manifest,
+    ) -> Result<()> {
+        let key = Key::new(&manifest.hash);
+        self.pending_request_file.remove(&request_id);
+        if self.pending_transfers.contains_key(&key) {
+            return Ok(());
+        }
+
+        info!(
+            "Fetching {} in {} chunk(s) from {:?}",
+            manifest.hash,
+            manifest.chunks.len(),
+            peer
+        );
+        let transfer = Transfer::new(peer, manifest, &self.chunks);
+        self.pending_transfers.insert(key.clone(), transfer);
+        self.request_chunks(&key).await
+    }
+
+    /// Asks for the next chunks of a transfer, or finishes it once every chunk is in
+    async fn request_chunks(&mut self, key: &Key) -> Result<()> {
+        let transfer = match self.pending_transfers.get_mut(key) {
+            Some(transfer) => transfer,
+            None => return Ok(()),
+        };
+        if transfer.is_complete() {
+            return self.finish_transfer(key).await;
+        }
+
+        let peer = transfer.peer;
+        let hash = transfer.manifest.hash.clone();
+        for (index, digest) in transfer.next_chunks() {
+            let request_id = self.swarm.behaviour_mut().request_response.send_request(
+                &peer,
+                Request::Chunk {
+                    hash: hash.clone(),
+                    index,
+                    digest,
+                },
+            );
+            self.pending_request_chunk
+                .insert(request_id, (key.clone(), index));
+        }
+        Ok(())
+    }
+
+    /// Keeps a chunk sent by a provider, a chunk not matching the manifest fails the transfer
+    pub async fn receive_chunk(
+        &mut self,
+        request_id: RequestId,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let key = match self.pending_request_chunk.remove(&request_id) {
+            Some((key, asked)) if asked == index => key,
+            Some((key, _)) => return self.fail_transfer(&key).await,
+            None => {
+                debug!("Dropping chunk of a cancelled or failed transfer");
+                return Ok(());
+            }
+        };
+
+        let received = match self.pending_transfers.get_mut(&key) {
+            Some(transfer) => transfer.receive(index, data, &self.chunks)?,
+            None => return Ok(()),
+        };
+        if received {
+            self.request_chunks(&key).await
+        } else {
+            warn!("Provider sent a chunk not matching the manifest");
+            self.fail_transfer(&key).await
+        }
+    }
+
+    /// Drops a transfer and its chunk requests. The chunks received stay on disk, fetching the
+    /// gistit again only asks for the missing ones
+    pub async fn fail_transfer(&mut self, key: &Key) -> Result<()> {
+        if self.pending_transfers.remove(key).is_none() {
+            return Ok(());
+        }
+        self.pending_request_chunk
+            .retain(|_, (other, _)| other != key);
+
+        warn!("Transfer of {:?} failed", key);
+        if let Some(key) = self.is_fetch_failed(key.clone()) {
+            self.respond_fetch(&key, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Puts a complete transfer back together, the gistit must match the hash it was asked by
+    async fn finish_transfer(&mut self, key: &Key) -> Result<()> {
+        let transfer = match self.pending_transfers.remove(key) {
+            Some(transfer) => transfer,
+            None => return Ok(()),
+        };
+        let hash = transfer.manifest.hash.clone();
+        let encoded = transfer.assemble(&self.chunks);
+
+        let gistit = Gistit::decode(&*encoded)
+            .ok()
+            .filter(|gistit| gistit.hash == hash && gistit.compute_hash() == hash)
+            .filter(|gistit| gistit.check_encoding().is_ok());
+        if gistit.is_none() {
+            warn!("Chunks of {} don't add up to the gistit", hash);
+        }
+
+        self.respond_fetch(key, gistit).await
+    }
+
+    /// Gives up on a request, the client timed out or was interrupted. No response is sent
+    fn cancel(&mut self, reply: Reply) {
+        let provide = self
+            .pending_start_providing
+            .iter()
+            .find(|(_, (other, _))| *other == reply)
+            .map(|(query_id, _)| *query_id);
+
+        if let Some(query_id) = provide {
+            if let Some((_, key)) = self.pending_start_providing.remove(&query_id) {
+                info!("Cancelled providing {:?}", key);
+                self.finish_query(query_id);
+                self.unhost(&key);
+            }
+            return;
+        }
+
+        let fetch = self
+            .pending_receive_file
+            .iter_mut()
+            .find_map(|(key, replies)| {
+                let waiting = replies.len();
+                replies.retain(|other| *other != reply);
+                (replies.len() != waiting).then(|| key.clone())
+            });
+
+        // Drop the lookup only if no other request waits on the same gistit
+        if let Some(key) = fetch {
+            if self
+                .pending_receive_file
+                .get(&key)
+                .map_or(true, Vec::is_empty)
+            {
+                info!("Cancelled fetching {:?}", key);
+                self.pending_receive_file.remove(&key);
+
+                let queries: Vec<QueryId> = self
+                    .pending_get_providers
+                    .iter()
+                    .filter(|(_, other)| **other == key)
+                    .map(|(query_id, _)| *query_id)
+                    .collect();
+                for query_id in queries {
+                    self.pending_get_providers.remove(&query_id);
+                    self.finish_query(query_id);
+                }
+
+                self.pending_request_file.retain(|_, other| *other != key);
+                self.pending_transfers.remove(&key);
+                self.pending_request_chunk
+                    .retain(|_, (other, _)| *other != key);
+                self.to_request.retain(|(other, _)| *other != key);
+            }
+        }
+    }
+
+    fn finish_query(&mut self, query_id: QueryId) {
+        if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+            query.finish();
+        }
+    }
+
+    /// Responds to every request waiting on `key` to be fetched. Providers still being asked for
+    /// it are forgotten, their responses are dropped
+    pub async fn respond_fetch(&mut self, key: &Key, gistit: Option<Gistit>) -> Result<()> {
+        self.pending_request_file.retain(|_, other| other != key);
+        for reply in self.pending_receive_file.remove(key).unwrap_or_default() {
+            self.bridge
+                .send(reply, Instruction::respond_fetch(gistit.clone()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Takes a gistit out of `to_provide` if it's meant to be served once, so no other
+    /// requester gets it while the response is in flight
+    pub fn take_once(&mut self, request_id: RequestId, key: &Key) {
+        if self.to_provide_once.remove(key) {
+            if let Some(gistit) = self.to_provide.remove(key) {
+                self.pending_send_once
+                    .insert(request_id, (key.clone(), gistit));
             }
         }
     }
 
+    /// Stops providing a one-shot gistit after it was successfully sent
+    pub fn finish_once(&mut self, request_id: RequestId) {
+        if let Some((key, _)) = self.pending_send_once.remove(&request_id) {
+            info!("One-shot gistit served, stop providing {:?}", key);
+            self.unhost(&key);
+        }
+    }
+
+    /// Puts a one-shot gistit back if sending it failed
+    pub fn restore_once(&mut self, request_id: RequestId) {
+        if let Some((key, gistit)) = self.pending_send_once.remove(&request_id) {
+            warn!("Failed to serve one-shot gistit, providing {:?} again", key);
+            self.to_provide_once.insert(key.clone());
+            self.to_provide.insert(key, gistit);
+        }
+    }
+
     async fn handle_request_event(&mut self, event: (Key, HashSet<PeerId>)) -> Result<()> {
         let (key, providers) = event;
+        let hash = String::from_utf8(key.to_vec()).expect("hash format to be valid utf8");
 
-        self.pending_receive_file.insert(key.clone());
         for peer in providers {
             for relay in &self.relays {
                 // Skip if we are trying to relay over the destination peer itself
@@ -153,10 +571,10 @@ impl Node {
                 .swarm
                 .behaviour_mut()
                 .request_response
-                .send_request(&peer, Request(key.to_vec()));
+                .send_request(&peer, Request::Manifest(hash.clone()));
             info!("Requesting gistit from {:?}", peer);
 
-            self.pending_request_file.insert(request_id);
+            self.pending_request_file.insert(request_id, key.clone());
         }
 
         Ok(())
@@ -239,13 +657,26 @@ impl Node {
 
     #[allow(clippy::match_wildcard_for_single_variants)]
     #[allow(clippy::cast_possible_truncation)]
-    async fn handle_bridge_event(&mut self, instruction: Instruction) -> Result<()> {
+    #[allow(clippy::too_many_lines)]
+    async fn handle_bridge_event(&mut self, reply: Reply, instruction: Instruction) -> Result<()> {
         match instruction.expect_request()? {
             ipc::instruction::Kind::ProvideRequest(ipc::instruction::ProvideRequest {
                 gistit: Some(gistit),
+                once,
             }) => {
                 warn!("Instruction: Provide gistit {}", &gistit.hash);
+                if gistit.is_expired(u128::from(store::now()))
+                    || gistit.encoded_len() > var::max_payload_size(self.max_size)
+                {
+                    self.bridge
+                        .send(reply, Instruction::respond_provide(None))
+                        .await?;
+                    return Ok(());
+                }
                 let key = Key::new(&gistit.hash);
+                if let Err(err) = self.hosting.save(&gistit, once) {
+                    error!("Failed to keep hosted gistit on disk: {}", err);
+                }
 
                 let query_id = self
                     .swarm
@@ -254,18 +685,31 @@ impl Node {
                     .start_providing(key.clone())
                     .expect("to start providing");
 
-                self.pending_start_providing.insert(query_id);
+                self.pending_start_providing
+                    .insert(query_id, (reply, key.clone()));
+                if once {
+                    self.to_provide_once.insert(key.clone());
+                }
                 self.to_provide.insert(key, gistit);
             }
 
             ipc::instruction::Kind::FetchRequest(ipc::instruction::FetchRequest { hash }) => {
                 warn!("Instruction: Get providers for {}", hash);
+                let key = Key::new(&hash);
+
+                // Requests for a gistit already being fetched wait on the same query
+                if let Some(replies) = self.pending_receive_file.get_mut(&key) {
+                    replies.push(reply);
+                    return Ok(());
+                }
+                self.pending_receive_file.insert(key.clone(), vec![reply]);
+
                 let query_id = self
                     .swarm
                     .behaviour_mut()
                     .kademlia
-                    .get_providers(Key::new(&hash));
-                self.pending_get_providers.insert(query_id);
+                    .get_providers(key.clone());
+                self.pending_get_providers.insert(query_id, key);
             }
 
             ipc::instruction::Kind::StatusRequest(ipc::instruction::StatusRequest {}) => {
@@ -277,18 +721,54 @@ impl Node {
                 let peer_count = network_info.num_peers() as u32;
                 let pending_connections = network_info.connection_counters().num_pending();
                 let hosting = self.to_provide.len() as u32;
+                let once = self.to_provide_once.len() as u32;
 
-                self.bridge.connect_blocking()?;
                 self.bridge
-                    .send(Instruction::respond_status(
-                        peer_id,
-                        peer_count,
-                        pending_connections,
-                        hosting,
-                    ))
+                    .send(
+                        reply,
+                        Instruction::respond_status(
+                            peer_id,
+                            peer_count,
+                            pending_connections,
+                            hosting,
+                            once,
+                        ),
+                    )
                     .await?;
             }
 
+            ipc::instruction::Kind::HostingRequest(ipc::instruction::HostingRequest {}) => {
+                warn!("Instruction: Hosting");
+                let hosted = self.hosted();
+
+                self.bridge
+                    .send(reply, Instruction::respond_hosting(hosted))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::UnhostRequest(ipc::instruction::UnhostRequest { hash }) => {
+                warn!("Instruction: Unhost {}", hash);
+                let unhosted = self.unhost(&Key::new(&hash));
+
+                self.bridge
+                    .send(reply, Instruction::respond_unhost(unhosted))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::RepublishRequest(ipc::instruction::RepublishRequest {}) => {
+                warn!("Instruction: Republish");
+                let count = self.republish();
+
+                self.bridge
+                    .send(reply, Instruction::respond_republish(count))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::CancelRequest(ipc::instruction::CancelRequest { id }) => {
+                warn!("Instruction: Cancel {}", id);
+                self.cancel(reply.to_request(id));
+            }
+
             ipc::instruction::Kind::DialRequest(ipc::instruction::DialRequest { address }) => {
                 warn!("Instruction: Dial");
                 let multiaddr: Multiaddr = address.parse()?;
@@ -297,6 +777,7 @@ impl Node {
 
             ipc::instruction::Kind::ShutdownRequest(ipc::instruction::ShutdownRequest {}) => {
                 warn!("Exiting...");
+                self.flush_records();
                 std::process::exit(0);
             }
 
@@ -305,3 +786,215 @@ impl Node {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::{ed25519, Keypair};
+    use libp2p::request_response::{InboundFailure, RequestResponseEvent};
+
+    use crate::behaviour::Response;
+    use crate::event::{handle_request_response, respond_to};
+
+    async fn test_node(tmp: &assert_fs::TempDir) -> Node {
+        let keypair = Keypair::Ed25519(ed25519::Keypair::generate());
+        Node::new(Config {
+            peer_id: keypair.public().into(),
+            keypair,
+            runtime_path: tmp.path().to_path_buf(),
+            config_path: tmp.path().to_path_buf(),
+            data_path: tmp.path().to_path_buf(),
+            multiaddr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            listen: Vec::new(),
+            bootstrap: false,
+            bootnodes: Vec::new(),
+            max_size: var::GISTIT_MAX_SIZE,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn test_gistit(hash: &str, data: String) -> Gistit {
+        Gistit::new(
+            hash.to_owned(),
+            "Matthew McConaughey".to_owned(),
+            None,
+            "0".to_owned(),
+            vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                data.len() as u32,
+                data,
+            )],
+        )
+    }
+
+    fn host(node: &mut Node, gistit: Gistit, once: bool) -> Key {
+        let key = Key::new(&gistit.hash);
+        node.hosting.save(&gistit, once).unwrap();
+        if once {
+            node.to_provide_once.insert(key.clone());
+        }
+        node.to_provide.insert(key.clone(), gistit);
+        key
+    }
+
+    /// A request id as the swarm hands them out
+    fn request_id(node: &mut Node) -> RequestId {
+        node.swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&PeerId::random(), Request::Gistit(String::new()))
+    }
+
+    #[tokio::test]
+    async fn node_once_is_served_once_then_gone() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        let gistit = test_gistit(&"a".repeat(64), "fn main() {}".to_owned());
+        let key = host(&mut node, gistit.clone(), true);
+
+        let first = request_id(&mut node);
+        assert_eq!(
+            respond_to(&mut node, first, Request::Gistit(gistit.hash.clone())),
+            Some(Response::Gistit(gistit.clone()))
+        );
+        // In flight, no one else gets it
+        let second = request_id(&mut node);
+        assert_eq!(
+            respond_to(&mut node, second, Request::Gistit(gistit.hash.clone())),
+            None
+        );
+
+        handle_request_response(
+            &mut node,
+            RequestResponseEvent::ResponseSent {
+                peer: PeerId::random(),
+                request_id: first,
+            },
+        )
+        .await
+        .unwrap();
+        assert!(node.providing(&key).is_none());
+        assert!(node.pending_send_once.is_empty());
+        assert!(!node.to_provide_once.contains(&key));
+        assert!(node.hosting.load().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn node_once_is_restored_after_inbound_failure() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        let gistit = test_gistit(&"b".repeat(64), "fn main() {}".to_owned());
+        let key = host(&mut node, gistit.clone(), true);
+
+        let request_id = request_id(&mut node);
+        assert!(respond_to(&mut node, request_id, Request::Gistit(gistit.hash.clone())).is_some());
+        assert!(node.providing(&key).is_none());
+
+        handle_request_response(
+            &mut node,
+            RequestResponseEvent::InboundFailure {
+                peer: PeerId::random(),
+                request_id,
+                error: InboundFailure::ConnectionClosed,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(node.providing(&key), Some(&gistit));
+        assert!(node.to_provide_once.contains(&key));
+        assert!(node.pending_send_once.is_empty());
+    }
+
+    #[tokio::test]
+    async fn node_once_is_never_split_into_chunks() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        let big = "x".repeat(var::GISTIT_CHUNK_SIZE * 2);
+        let shared = test_gistit(&"c".repeat(64), big.clone());
+        let once = test_gistit(&"d".repeat(64), big);
+        host(&mut node, shared.clone(), false);
+        host(&mut node, once.clone(), true);
+
+        let request = request_id(&mut node);
+        assert!(matches!(
+            respond_to(&mut node, request, Request::Manifest(shared.hash.clone())),
+            Some(Response::Manifest(_))
+        ));
+
+        let request = request_id(&mut node);
+        assert_eq!(
+            respond_to(&mut node, request, Request::Manifest(once.hash.clone())),
+            Some(Response::Gistit(once))
+        );
+        assert!(!node.manifests.contains_key(&Key::new(&"d".repeat(64))));
+    }
+
+    #[tokio::test]
+    async fn node_hosted_newest_first() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        let mut older = test_gistit(&"a".repeat(64), "fn main() {}".to_owned());
+        older.timestamp = "1000".to_owned();
+        let mut newer = test_gistit(&"b".repeat(64), "fn main() { loop {} }".to_owned());
+        newer.timestamp = "2000".to_owned();
+        newer.expires_at = Some("3000".to_owned());
+        host(&mut node, older, false);
+        host(&mut node, newer, true);
+
+        let hosted = node.hosted();
+        assert_eq!(
+            hosted
+                .iter()
+                .map(|hosted| hosted.hash.as_str())
+                .collect::<Vec<_>>(),
+            [&*"b".repeat(64), &*"a".repeat(64)]
+        );
+        assert_eq!(hosted[0].names, ["main.rs"]);
+        assert_eq!(hosted[0].size, 21);
+        assert!(hosted[0].once);
+        assert_eq!(hosted[0].expires_at.as_deref(), Some("3000"));
+        assert!(!hosted[1].once);
+        assert_eq!(hosted[1].expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn node_unhost() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        let gistit = test_gistit(&"a".repeat(64), "fn main() {}".to_owned());
+        let key = host(&mut node, gistit, true);
+
+        assert!(!node.unhost(&Key::new(&"b".repeat(64))));
+        assert!(node.providing(&key).is_some());
+
+        assert!(node.unhost(&key));
+        assert!(node.providing(&key).is_none());
+        assert!(!node.to_provide_once.contains(&key));
+        assert!(node.hosting.load().unwrap().is_empty());
+        assert!(node.hosted().is_empty());
+
+        assert!(!node.unhost(&key));
+    }
+
+    #[tokio::test]
+    async fn node_republish_counts_hosted() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut node = test_node(&tmp).await;
+        assert_eq!(node.republish(), 0);
+
+        host(
+            &mut node,
+            test_gistit(&"a".repeat(64), "fn main() {}".to_owned()),
+            false,
+        );
+        host(
+            &mut node,
+            test_gistit(&"b".repeat(64), "fn main() {}".to_owned()),
+            true,
+        );
+        assert_eq!(node.republish(), 2);
+    }
+}
@@ -0,0 +1,486 @@
+//! The storage module
+//!
+//! Hosted gistits are kept under the data directory, one `<hash>.gistit` payload each, so the
+//! node picks them up again after a restart. Kademlia records are kept in memory and
+//! snapshotted to disk by [`PersistentStore::flush`]. Chunks of gistits being fetched are kept
+//! by their digest until the fetch completes, so a failed one resumes where it stopped.
+#![allow(clippy::missing_errors_doc)]
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use libp2p::core::{Multiaddr, PeerId};
+use libp2p::kad::record::store::{self, MemoryStore, RecordStore};
+use libp2p::kad::record::{Key, ProviderRecord, Record};
+
+use gistit_proto::exchange;
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::{Error, Result};
+
+/// Hosted gistits, relative to the data directory
+const HOSTING_DIR: &str = "hosting";
+
+/// Kademlia records snapshot, relative to the data directory
+const RECORDS_FILE: &str = "kademlia.json";
+
+/// Chunks of gistits being fetched, relative to the data directory
+const CHUNKS_DIR: &str = "chunks";
+
+/// Marks a hosted gistit to be served once, next to its payload
+const ONCE_EXTENSION: &str = "once";
+
+const PAYLOAD_EXTENSION: &str = "gistit";
+
+/// Gistits hosted by this node
+#[derive(Debug, Clone)]
+pub struct Hosting {
+    path: PathBuf,
+}
+
+impl Hosting {
+    pub fn new(data_path: &Path) -> Result<Self> {
+        let path = data_path.join(HOSTING_DIR);
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Keeps a gistit until it's removed. Hosting it again replaces whether it's served once
+    pub fn save(&self, gistit: &Gistit, once: bool) -> Result<()> {
+        let hash = valid_hash(&gistit.hash).ok_or(Error::Parse("invalid gistit hash"))?;
+
+        fs::write(self.file(hash, PAYLOAD_EXTENSION), gistit.encode_to_vec())?;
+        if once {
+            fs::write(self.file(hash, ONCE_EXTENSION), [])?;
+        } else {
+            match fs::remove_file(self.file(hash, ONCE_EXTENSION)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets a hosted gistit, a missing one is not an error
+    pub fn remove(&self, key: &Key) {
+        if let Some(hash) = str::from_utf8(key.as_ref()).ok().and_then(valid_hash) {
+            for extension in [PAYLOAD_EXTENSION, ONCE_EXTENSION] {
+                let _ = fs::remove_file(self.file(hash, extension));
+            }
+        }
+    }
+
+    /// Reads every hosted gistit and whether it's meant to be served once. Unreadable payloads
+    /// are skipped
+    pub fn load(&self) -> Result<Vec<(Gistit, bool)>> {
+        let mut hosted = Vec::new();
+
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(PAYLOAD_EXTENSION) {
+                continue;
+            }
+
+            match fs::read(&path)
+                .map_err(Error::from)
+                .and_then(|bytes| Ok(Gistit::from_bytes(bytes)?))
+            {
+                Ok(gistit) if valid_hash(&gistit.hash).is_some() => {
+                    let once = self.file(&gistit.hash, ONCE_EXTENSION).exists();
+                    hosted.push((gistit, once));
+                }
+                _ => warn!("Skipping unreadable hosted gistit {:?}", path),
+            }
+        }
+        Ok(hosted)
+    }
+
+    fn file(&self, hash: &str, extension: &str) -> PathBuf {
+        self.path.join(format!("{}.{}", hash, extension))
+    }
+}
+
+/// Verified chunks of gistits being fetched, content addressed
+#[derive(Debug, Clone)]
+pub struct Chunks {
+    path: PathBuf,
+}
+
+impl Chunks {
+    pub fn new(data_path: &Path) -> Result<Self> {
+        let path = data_path.join(CHUNKS_DIR);
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// A chunk received earlier, `None` if missing or no longer matching its digest
+    pub fn get(&self, digest: &[u8]) -> Option<Vec<u8>> {
+        fs::read(self.file(digest))
+            .ok()
+            .filter(|data| exchange::digest(data) == digest)
+    }
+
+    pub fn put(&self, digest: &[u8], data: &[u8]) -> Result<()> {
+        Ok(fs::write(self.file(digest), data)?)
+    }
+
+    /// Forgets a chunk, a missing one is not an error
+    pub fn remove(&self, digest: &[u8]) {
+        let _ = fs::remove_file(self.file(digest));
+    }
+
+    fn file(&self, digest: &[u8]) -> PathBuf {
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.path.join(name)
+    }
+}
+
+/// Hashes are used as file names, never let them escape the hosting directory
+fn valid_hash(hash: &str) -> Option<&str> {
+    if !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hash)
+    } else {
+        None
+    }
+}
+
+/// A [`MemoryStore`] that can be written to disk and restored on startup
+pub struct PersistentStore {
+    inner: MemoryStore,
+    path: PathBuf,
+    /// `MemoryStore` doesn't list providers of other peers, keep track of their keys
+    provider_keys: HashSet<Key>,
+    dirty: bool,
+}
+
+impl PersistentStore {
+    /// Restores the last snapshot, starting empty if there is none or it can't be read
+    #[must_use]
+    pub fn open(local_id: PeerId, data_path: &Path) -> Self {
+        let mut store = Self {
+            inner: MemoryStore::new(local_id),
+            path: data_path.join(RECORDS_FILE),
+            provider_keys: HashSet::default(),
+            dirty: false,
+        };
+
+        match fs::read_to_string(&store.path)
+            .map_err(Error::from)
+            .and_then(|contents| Ok(serde_json::from_str::<Snapshot>(&contents)?))
+        {
+            Ok(snapshot) => {
+                let now = now();
+                for record in snapshot.records {
+                    if let Some(record) = record.into_record(now) {
+                        let _ = store.inner.put(record);
+                    }
+                }
+                for provider in snapshot.providers {
+                    if let Some(provider) = provider.into_record(now) {
+                        store.provider_keys.insert(provider.key.clone());
+                        let _ = store.inner.add_provider(provider);
+                    }
+                }
+                info!("Restored kademlia records from {:?}", store.path);
+            }
+            Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => warn!("Failed to restore kademlia records: {}", err),
+        }
+        store
+    }
+
+    /// Writes a snapshot if anything changed since the last one
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let snapshot = Snapshot {
+            records: self
+                .inner
+                .records()
+                .map(|record| StoredRecord::from(record.as_ref()))
+                .collect(),
+            providers: self
+                .provider_keys
+                .iter()
+                .flat_map(|key| self.inner.providers(key))
+                .map(|provider| StoredProvider::from(&provider))
+                .collect(),
+        };
+
+        // Write aside and rename so a crash never leaves a truncated snapshot
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&snapshot)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl<'a> RecordStore<'a> for PersistentStore {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'a, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> store::Result<()> {
+        self.dirty = true;
+        self.inner.put(r)
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        self.dirty = true;
+        self.inner.remove(k);
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.inner.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> store::Result<()> {
+        self.dirty = true;
+        self.provider_keys.insert(record.key.clone());
+        self.inner.add_provider(record)
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        self.dirty = true;
+        self.inner.remove_provider(k, p);
+        if self.inner.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    records: Vec<StoredRecord>,
+    providers: Vec<StoredProvider>,
+}
+
+/// A [`Record`] with base64 key and value, expiring at a unix timestamp in milliseconds
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRecord {
+    key: String,
+    value: String,
+    publisher: Option<String>,
+    expires_at: Option<u64>,
+}
+
+impl From<&Record> for StoredRecord {
+    fn from(record: &Record) -> Self {
+        Self {
+            key: base64::encode(&record.key),
+            value: base64::encode(&record.value),
+            publisher: record.publisher.map(|peer_id| peer_id.to_base58()),
+            expires_at: record.expires.map(instant_to_unix_millis),
+        }
+    }
+}
+
+impl StoredRecord {
+    /// `None` if expired or malformed
+    fn into_record(self, now: u64) -> Option<Record> {
+        Some(Record {
+            key: Key::from(base64::decode(self.key).ok()?),
+            value: base64::decode(self.value).ok()?,
+            publisher: match self.publisher {
+                Some(publisher) => Some(publisher.parse().ok()?),
+                None => None,
+            },
+            expires: restore_expiry(self.expires_at, now)?,
+        })
+    }
+}
+
+/// A [`ProviderRecord`] with base64 key, expiring at a unix timestamp in milliseconds
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredProvider {
+    key: String,
+    provider: String,
+    expires_at: Option<u64>,
+    addresses: Vec<String>,
+}
+
+impl From<&ProviderRecord> for StoredProvider {
+    fn from(record: &ProviderRecord) -> Self {
+        Self {
+            key: base64::encode(&record.key),
+            provider: record.provider.to_base58(),
+            expires_at: record.expires.map(instant_to_unix_millis),
+            addresses: record.addresses.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl StoredProvider {
+    /// `None` if expired or malformed
+    fn into_record(self, now: u64) -> Option<ProviderRecord> {
+        Some(ProviderRecord {
+            key: Key::from(base64::decode(self.key).ok()?),
+            provider: self.provider.parse().ok()?,
+            expires: restore_expiry(self.expires_at, now)?,
+            addresses: self
+                .addresses
+                .iter()
+                .filter_map(|address| address.parse::<Multiaddr>().ok())
+                .collect(),
+        })
+    }
+}
+
+/// Maps a stored expiry back to the monotonic clock, `None` if it already passed
+#[allow(clippy::option_option)]
+fn restore_expiry(expires_at: Option<u64>, now: u64) -> Option<Option<Instant>> {
+    match expires_at {
+        Some(expires_at) if expires_at <= now => None,
+        Some(expires_at) => Some(Some(
+            Instant::now() + Duration::from_millis(expires_at - now),
+        )),
+        None => Some(None),
+    }
+}
+
+fn instant_to_unix_millis(instant: Instant) -> u64 {
+    unix_millis(SystemTime::now() + instant.saturating_duration_since(Instant::now()))
+}
+
+/// Milliseconds since the unix epoch, what gistit timestamps count
+pub fn now() -> u64 {
+    unix_millis(SystemTime::now())
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("Check your system time")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_gistit() -> Gistit {
+        let data = "fn main() { println!(\"hello\"); }".to_owned();
+        Gistit::new(
+            "a".repeat(64),
+            "Matthew McConaughey".to_owned(),
+            None,
+            "0".to_owned(),
+            vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                data.len() as u32,
+                data,
+            )],
+        )
+    }
+
+    #[test]
+    fn store_hosting_save_load_remove() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let hosting = Hosting::new(tmp.path()).unwrap();
+        let gistit = test_gistit();
+
+        hosting.save(&gistit, true).unwrap();
+        assert_eq!(hosting.load().unwrap(), vec![(gistit.clone(), true)]);
+
+        // Hosted again for good, it's no longer served once
+        hosting.save(&gistit, false).unwrap();
+        assert_eq!(hosting.load().unwrap(), vec![(gistit.clone(), false)]);
+
+        hosting.remove(&Key::new(&gistit.hash));
+        assert!(hosting.load().unwrap().is_empty());
+
+        let mut escaping = gistit;
+        escaping.hash = "../../etc/passwd".to_owned();
+        assert!(hosting.save(&escaping, false).is_err());
+    }
+
+    #[test]
+    fn store_chunks_put_get_remove() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let chunks = Chunks::new(tmp.path()).unwrap();
+        let data = b"alright alright alright";
+        let digest = exchange::digest(data);
+
+        assert_eq!(chunks.get(&digest), None);
+        chunks.put(&digest, data).unwrap();
+        assert_eq!(chunks.get(&digest).as_deref(), Some(&data[..]));
+
+        // Corrupted on disk, it's fetched again
+        fs::write(chunks.file(&digest), b"alright").unwrap();
+        assert_eq!(chunks.get(&digest), None);
+
+        chunks.remove(&digest);
+        assert!(!chunks.file(&digest).exists());
+    }
+
+    #[test]
+    fn store_persistent_store_survives_reopen() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let local_id = PeerId::random();
+        let other = PeerId::random();
+        let key = Key::new(&"a".repeat(64));
+
+        let mut store = PersistentStore::open(local_id, tmp.path());
+        store
+            .add_provider(ProviderRecord::new(key.clone(), local_id, Vec::new()))
+            .unwrap();
+        store
+            .add_provider(ProviderRecord {
+                expires: Some(Instant::now() + Duration::from_secs(60)),
+                ..ProviderRecord::new(
+                    key.clone(),
+                    other,
+                    vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+                )
+            })
+            .unwrap();
+        store
+            .put(Record::new(key.clone(), b"value".to_vec()))
+            .unwrap();
+        store.flush().unwrap();
+
+        let store = PersistentStore::open(local_id, tmp.path());
+        let providers = store.providers(&key);
+        assert_eq!(providers.len(), 2);
+        assert!(providers
+            .iter()
+            .any(|p| p.provider == other && p.expires.is_some() && p.addresses.len() == 1));
+        assert_eq!(store.provided().count(), 1);
+        assert_eq!(store.get(&key).unwrap().value, b"value".to_vec());
+    }
+
+    #[test]
+    fn store_persistent_store_drops_expired() {
+        let now = now();
+        assert!(restore_expiry(Some(now - 1), now).is_none());
+        assert_eq!(restore_expiry(None, now), Some(None));
+        assert!(matches!(
+            restore_expiry(Some(now + 1000), now),
+            Some(Some(_))
+        ));
+    }
+}
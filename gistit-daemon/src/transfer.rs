@@ -0,0 +1,171 @@
+//! The transfer module
+//!
+//! Gistits bigger than a chunk are fetched over `/gistit/2` chunk by chunk, as listed by the
+//! manifest their provider sent. Every chunk is checked against its digest and kept on disk, a
+//! fetch that failed midway only asks for the chunks it's still missing the next time.
+#![allow(clippy::missing_errors_doc)]
+
+use std::collections::HashSet;
+
+use libp2p::core::PeerId;
+
+use gistit_proto::exchange::response::Manifest;
+
+use crate::store::Chunks;
+use crate::Result;
+
+/// Chunk requests in flight at once for a single gistit
+const CHUNKS_IN_FLIGHT: usize = 8;
+
+/// A gistit being fetched chunk by chunk
+#[derive(Debug)]
+pub struct Transfer {
+    /// The provider that sent the manifest, asked for every chunk
+    pub peer: PeerId,
+    pub manifest: Manifest,
+    /// Chunks received so far, in order
+    received: Vec<Option<Vec<u8>>>,
+    /// Chunks asked for and not received yet
+    requested: HashSet<u32>,
+}
+
+impl Transfer {
+    /// Starts from the chunks received by an earlier attempt
+    pub fn new(peer: PeerId, manifest: Manifest, chunks: &Chunks) -> Self {
+        let received = manifest
+            .chunks
+            .iter()
+            .map(|digest| chunks.get(digest))
+            .collect();
+
+        Self {
+            peer,
+            manifest,
+            received,
+            requested: HashSet::new(),
+        }
+    }
+
+    /// The chunks to ask for next with their digest, keeping at most [`CHUNKS_IN_FLIGHT`]
+    /// requested at once
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn next_chunks(&mut self) -> Vec<(u32, Vec<u8>)> {
+        let free = CHUNKS_IN_FLIGHT.saturating_sub(self.requested.len());
+        let next: Vec<u32> = (0..self.received.len() as u32)
+            .filter(|index| {
+                self.received[*index as usize].is_none() && !self.requested.contains(index)
+            })
+            .take(free)
+            .collect();
+
+        next.into_iter()
+            .map(|index| {
+                self.requested.insert(index);
+                (index, self.manifest.chunks[index as usize].clone())
+            })
+            .collect()
+    }
+
+    /// Keeps a chunk, returns false if it wasn't asked for or doesn't match its digest
+    pub fn receive(&mut self, index: u32, data: Vec<u8>, chunks: &Chunks) -> Result<bool> {
+        if !self.requested.remove(&index) || !self.manifest.verify(index, &data) {
+            return Ok(false);
+        }
+
+        chunks.put(&self.manifest.chunks[index as usize], &data)?;
+        self.received[index as usize] = Some(data);
+        Ok(true)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received.iter().all(Option::is_some)
+    }
+
+    /// The encoded gistit once complete, its chunks are removed from disk
+    pub fn assemble(self, chunks: &Chunks) -> Vec<u8> {
+        for digest in &self.manifest.chunks {
+            chunks.remove(digest);
+        }
+        self.received.into_iter().flatten().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded() -> Vec<u8> {
+        (0..=255).cycle().take(1000).collect()
+    }
+
+    #[test]
+    fn transfer_receive_and_assemble() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let chunks = Chunks::new(tmp.path()).unwrap();
+        let encoded = encoded();
+        let manifest = Manifest::new("hash".to_owned(), &encoded, 100);
+        let mut transfer = Transfer::new(PeerId::random(), manifest, &chunks);
+
+        let first = transfer.next_chunks();
+        assert_eq!(first.len(), CHUNKS_IN_FLIGHT);
+        assert!(transfer.next_chunks().is_empty());
+
+        // Unrequested or corrupted chunks are refused
+        assert!(!transfer
+            .receive(9, encoded[900..].to_vec(), &chunks)
+            .unwrap());
+        assert!(!transfer.receive(0, vec![0; 100], &chunks).unwrap());
+
+        for (index, _) in first.into_iter().skip(1) {
+            let start = index as usize * 100;
+            let data = encoded[start..start + 100].to_vec();
+            assert!(transfer.receive(index, data, &chunks).unwrap());
+        }
+        assert!(!transfer.is_complete());
+
+        // The corrupted chunk is asked for again
+        let rest = transfer.next_chunks();
+        assert_eq!(
+            rest.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 8, 9]
+        );
+        for (index, digest) in rest {
+            let start = index as usize * 100;
+            let data = encoded[start..(start + 100).min(encoded.len())].to_vec();
+            assert_eq!(gistit_proto::exchange::digest(&data), digest);
+            assert!(transfer.receive(index, data, &chunks).unwrap());
+        }
+
+        assert!(transfer.is_complete());
+        assert_eq!(transfer.assemble(&chunks), encoded);
+        assert_eq!(
+            std::fs::read_dir(tmp.path().join("chunks"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn transfer_resumes_from_disk() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let chunks = Chunks::new(tmp.path()).unwrap();
+        let encoded = encoded();
+        let manifest = Manifest::new("hash".to_owned(), &encoded, 300);
+
+        let mut interrupted = Transfer::new(PeerId::random(), manifest.clone(), &chunks);
+        interrupted.next_chunks();
+        assert!(interrupted
+            .receive(1, encoded[300..600].to_vec(), &chunks)
+            .unwrap());
+        drop(interrupted);
+
+        let mut resumed = Transfer::new(PeerId::random(), manifest, &chunks);
+        let missing: Vec<u32> = resumed
+            .next_chunks()
+            .iter()
+            .map(|(index, _)| *index)
+            .collect();
+        assert_eq!(missing, vec![0, 2, 3]);
+    }
+}
@@ -21,187 +21,382 @@
     )
 )]
 //! This is a simple crate to handle the inter process comms for gistit-daemon and gistit-cli
+//!
+//! The daemon listens on a named unix stream socket and any number of clients can connect to it
+//! at once. Instructions are protobuf encoded and prefixed with their length as a big endian
+//! `u32`. Every request carries an id, chosen by the client, that the response echoes back.
+//! Responses are queued to a writer task per connection, a client that stops reading them is
+//! dropped instead of holding up the others.
 //! TODO: Missing TCP socket implementation
 
+use std::collections::{HashMap, HashSet};
 use std::fs::{metadata, remove_file};
-use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
-use tokio::net::UnixDatagram;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Mutex};
 
-use gistit_proto::bytes::BytesMut;
 use gistit_proto::prost::{self, Message};
 use gistit_proto::Instruction;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-const NAMED_SOCKET_0: &str = "gistit-0";
-const NAMED_SOCKET_1: &str = "gistit-1";
+const NAMED_SOCKET: &str = "gistit.sock";
 
-const READBUF_SIZE: usize = 60_000; // A bit bigger than 50kb because encoding
+/// Biggest instruction accepted by default, way above a gistit since responses may list many of
+/// them. Raised with [`Bridge::max_frame_size`] to carry larger gistits
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
 const CONNECT_TIMEOUT_SECS: u64 = 3;
 
+/// Incoming requests buffered before connections stop being read
+const INCOMING_BUFFER: usize = 64;
+
+/// Responses buffered per connection, a client not reading them past that is dropped
+const OUTGOING_BUFFER: usize = 16;
+
+/// Encoded responses waiting to be written to each connection by its own task
+type Connections = Arc<Mutex<HashMap<u64, mpsc::Sender<Vec<u8>>>>>;
+
 pub trait SockEnd {}
 
+/// The daemon end, accepting connections
 #[derive(Debug)]
-pub struct Server;
+pub struct Server {
+    incoming: mpsc::Receiver<(Reply, Instruction)>,
+    connections: Connections,
+}
 impl SockEnd for Server {}
 
+/// The cli end, a single connection
 #[derive(Debug)]
-pub struct Client;
+pub struct Client {
+    stream: Option<UnixStream>,
+    next_id: u64,
+    /// Ids of requests sent and not yet answered
+    pending: HashSet<u64>,
+}
 impl SockEnd for Client {}
 
 #[derive(Debug)]
 pub struct Bridge<T: SockEnd> {
-    pub sock_0: UnixDatagram,
-    pub sock_1: UnixDatagram,
+    end: T,
     base: PathBuf,
-    __marker_t: PhantomData<T>,
+    /// Shared with the connections being read, so it can be raised after they're accepted
+    max_frame_size: Arc<AtomicUsize>,
+}
+
+/// Where to send the response to a request: the connection it came from and its id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reply {
+    connection: u64,
+    id: u64,
+}
+
+impl Reply {
+    /// Where to send the response to another request over the same connection
+    #[must_use]
+    pub const fn to_request(self, id: u64) -> Self {
+        Self { id, ..self }
+    }
 }
 
-/// Recv from [`NAMED_SOCKET_0`] and send to [`NAMED_SOCKET_1`]
-/// The owner of `sock_0`
+/// Binds [`NAMED_SOCKET`] and starts accepting connections in the background
 ///
 /// # Errors
 ///
 /// Fails if can't spawn a named socket
 pub fn server(base: &Path) -> Result<Bridge<Server>> {
-    let sockpath_0 = &base.join(NAMED_SOCKET_0);
+    let sockpath = &base.join(NAMED_SOCKET);
 
-    if metadata(sockpath_0).is_ok() {
-        remove_file(sockpath_0)?;
+    if metadata(sockpath).is_ok() {
+        remove_file(sockpath)?;
     }
 
-    log::trace!("Bind sock_0 (server) at {:?}", sockpath_0);
-    let sock_0 = UnixDatagram::bind(sockpath_0)?;
+    log::trace!("Bind server socket at {:?}", sockpath);
+    let listener = UnixListener::bind(sockpath)?;
+    let (tx, incoming) = mpsc::channel(INCOMING_BUFFER);
+    let connections = Arc::new(Mutex::new(HashMap::new()));
+    let max_frame_size = Arc::new(AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE));
+
+    tokio::spawn(accept(
+        listener,
+        tx,
+        connections.clone(),
+        max_frame_size.clone(),
+    ));
 
     Ok(Bridge {
-        sock_0,
-        sock_1: UnixDatagram::unbound()?,
+        end: Server {
+            incoming,
+            connections,
+        },
         base: base.to_path_buf(),
-        __marker_t: PhantomData,
+        max_frame_size,
     })
 }
 
-/// Recv from [`NAMED_SOCKET_1`] and send to [`NAMED_SOCKET_0`]
-/// The owner of `sock_1`
+/// A client to the daemon listening under `base`, connecting lazily
 ///
 /// # Errors
 ///
-/// Fails if can't spawn a named socket
+/// Never fails currently, kept fallible for the TCP implementation
 pub fn client(base: &Path) -> Result<Bridge<Client>> {
-    let sockpath_1 = &base.join(NAMED_SOCKET_1);
-
-    if metadata(sockpath_1).is_ok() {
-        remove_file(sockpath_1)?;
-    }
-
-    log::trace!("Bind sock_1 (client) at {:?}", sockpath_1);
-    let sock_1 = UnixDatagram::bind(sockpath_1)?;
-
     Ok(Bridge {
-        sock_0: UnixDatagram::unbound()?,
-        sock_1,
+        end: Client {
+            stream: None,
+            next_id: 1,
+            pending: HashSet::new(),
+        },
         base: base.to_path_buf(),
-        __marker_t: PhantomData,
+        max_frame_size: Arc::new(AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE)),
     })
 }
 
-fn __alive(base: &Path, dgram: &UnixDatagram, sock_name: &str) -> bool {
-    !matches!(dgram.connect(base.join(sock_name)), Err(_))
+async fn accept(
+    listener: UnixListener,
+    tx: mpsc::Sender<(Reply, Instruction)>,
+    connections: Connections,
+    max_frame_size: Arc<AtomicUsize>,
+) {
+    let mut next_connection = 0_u64;
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::error!("Failed to accept connection: {:?}", err);
+                continue;
+            }
+        };
+
+        let connection = next_connection;
+        next_connection += 1;
+        log::trace!("Accepted connection {}", connection);
+
+        let (mut reader, writer) = stream.into_split();
+        let (outgoing, frames) = mpsc::channel(OUTGOING_BUFFER);
+        connections.lock().await.insert(connection, outgoing);
+        tokio::spawn(write_frames(writer, frames, connection));
+
+        let tx = tx.clone();
+        let connections = connections.clone();
+        let max_frame_size = max_frame_size.clone();
+        tokio::spawn(async move {
+            loop {
+                match read_frame(&mut reader, &max_frame_size).await {
+                    Ok(instruction) => {
+                        let reply = Reply {
+                            connection,
+                            id: instruction.id,
+                        };
+                        if tx.send((reply, instruction)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(err) => {
+                        log::warn!("Dropping connection {}: {:?}", connection, err);
+                        break;
+                    }
+                }
+            }
+            log::trace!("Closed connection {}", connection);
+            connections.lock().await.remove(&connection);
+        });
+    }
 }
 
-fn __connect_blocking(base: &Path, dgram: &UnixDatagram, sock_name: &str) -> Result<()> {
-    let earlier = Instant::now();
-    while let Err(err) = dgram.connect(base.join(sock_name)) {
-        if Instant::now().duration_since(earlier).as_secs() > CONNECT_TIMEOUT_SECS {
-            return Err(err.into());
-        }
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_frame_size: &AtomicUsize,
+) -> Result<Instruction> {
+    let len = reader.read_u32().await? as usize;
+    // Loaded once the frame comes in, it may have been raised while waiting
+    if len > max_frame_size.load(Ordering::Relaxed) {
+        return Err(Error::FrameSize(len));
     }
 
-    log::trace!("Connecting to {:?}", sock_name);
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).await?;
+    Ok(Instruction::decode(&*buf)?)
+}
+
+/// The length prefixed encoding of `instruction`
+fn encode_frame(instruction: &Instruction, max_frame_size: usize) -> Result<Vec<u8>> {
+    let len = instruction.encoded_len();
+    if len > max_frame_size {
+        return Err(Error::FrameSize(len));
+    }
+
+    let mut frame = Vec::with_capacity(4 + len);
+    #[allow(clippy::cast_possible_truncation)]
+    frame.extend((len as u32).to_be_bytes());
+    instruction.encode(&mut frame)?;
+    Ok(frame)
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    instruction: &Instruction,
+    max_frame_size: usize,
+) -> Result<()> {
+    writer
+        .write_all(&encode_frame(instruction, max_frame_size)?)
+        .await?;
+    writer.flush().await?;
     Ok(())
 }
 
-impl Bridge<Server> {
-    pub fn alive(&self) -> bool {
-        __alive(&self.base, &self.sock_1, NAMED_SOCKET_1)
+/// Writes the responses to a connection in order, a slow client only holds up its own
+async fn write_frames(
+    mut writer: OwnedWriteHalf,
+    mut frames: mpsc::Receiver<Vec<u8>>,
+    connection: u64,
+) {
+    while let Some(frame) = frames.recv().await {
+        let written = match writer.write_all(&frame).await {
+            Ok(()) => writer.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            log::warn!("Failed to respond to connection {}: {:?}", connection, err);
+            break;
+        }
     }
+}
 
-    /// Connect to the other end
-    ///
-    /// # Errors
-    ///
-    /// Inherits errors of [`__connect_blocking`]
-    pub fn connect_blocking(&mut self) -> Result<()> {
-        __connect_blocking(&self.base, &self.sock_1, NAMED_SOCKET_1)
+impl<T: SockEnd> Bridge<T> {
+    /// Accepts instructions of up to `size` bytes both ways, never less than
+    /// [`DEFAULT_MAX_FRAME_SIZE`]. Both ends need it to exchange large gistits
+    #[must_use]
+    pub fn max_frame_size(self, size: usize) -> Self {
+        self.max_frame_size
+            .store(size.max(DEFAULT_MAX_FRAME_SIZE), Ordering::Relaxed);
+        self
     }
 
-    /// Send bincode serialized data through the pipe
+    fn frame_size(&self) -> usize {
+        self.max_frame_size.load(Ordering::Relaxed)
+    }
+}
+
+impl Bridge<Server> {
+    /// Sends the response to a request. Responses to clients that went away are dropped
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
-    pub async fn send(&self, instruction: Instruction) -> Result<()> {
-        let mut buf = BytesMut::with_capacity(READBUF_SIZE);
-        instruction.encode(&mut buf)?;
-        log::trace!("Sending to client {} bytes", buf.len());
-        self.sock_1.send(&buf).await?;
+    /// Fails if the instruction can't be encoded
+    pub async fn send(&self, reply: Reply, mut instruction: Instruction) -> Result<()> {
+        instruction.id = reply.id;
+
+        let mut connections = self.end.connections.lock().await;
+        let outgoing = match connections.get(&reply.connection) {
+            Some(outgoing) => outgoing,
+            None => {
+                log::debug!("Connection {} is gone, dropping response", reply.connection);
+                return Ok(());
+            }
+        };
+
+        log::trace!(
+            "Sending response {} to connection {}",
+            reply.id,
+            reply.connection
+        );
+        // Queued for the connection's own writer, never waiting on the client
+        let queued = encode_frame(&instruction, self.frame_size())
+            .and_then(|frame| outgoing.try_send(frame).map_err(|_| Error::Closed));
+        if let Err(err) = queued {
+            log::warn!(
+                "Failed to respond to connection {}: {:?}",
+                reply.connection,
+                err
+            );
+            connections.remove(&reply.connection);
+        }
         Ok(())
     }
 
-    /// Attempts to receive serialized data from the pipe
+    /// Waits for the next request of any client
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
-    pub async fn recv(&self) -> Result<Instruction> {
-        let mut buf = vec![0u8; READBUF_SIZE];
-        let read = self.sock_0.recv(&mut buf).await?;
-        buf.truncate(read);
-        let target = Instruction::decode(&*buf)?;
-        Ok(target)
+    /// Fails if the socket stopped accepting connections
+    pub async fn recv(&mut self) -> Result<(Reply, Instruction)> {
+        self.end.incoming.recv().await.ok_or(Error::Closed)
     }
 }
 
 impl Bridge<Client> {
-    pub fn alive(&self) -> bool {
-        __alive(&self.base, &self.sock_0, NAMED_SOCKET_0)
+    /// Whether the daemon is listening, connects if so
+    pub fn alive(&mut self) -> bool {
+        self.try_connect().is_ok()
     }
 
-    /// Connect to the other end
+    /// Connect to the other end, waiting a few seconds for it to come up
     ///
     /// # Errors
     ///
-    /// Inherits errors of [`__connect_blocking`]
+    /// Fails if nobody is listening after [`CONNECT_TIMEOUT_SECS`]
     pub fn connect_blocking(&mut self) -> Result<()> {
-        __connect_blocking(&self.base, &self.sock_0, NAMED_SOCKET_0)
+        let earlier = Instant::now();
+        while let Err(err) = self.try_connect() {
+            if Instant::now().duration_since(earlier).as_secs() > CONNECT_TIMEOUT_SECS {
+                return Err(err);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        log::trace!("Connected to {:?}", NAMED_SOCKET);
+        Ok(())
+    }
+
+    fn try_connect(&mut self) -> Result<()> {
+        if self.end.stream.is_none() {
+            let stream = std::os::unix::net::UnixStream::connect(self.base.join(NAMED_SOCKET))?;
+            stream.set_nonblocking(true)?;
+            self.end.stream = Some(UnixStream::from_std(stream)?);
+        }
+        Ok(())
     }
 
-    /// Send bincode serialized data through the pipe
+    /// Sends a request, returning the id its response will carry
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
-    pub async fn send(&self, instruction: Instruction) -> Result<()> {
-        let mut buf = BytesMut::with_capacity(READBUF_SIZE);
-        instruction.encode(&mut buf)?;
-        log::trace!("Sending to server {} bytes", buf.len());
-        self.sock_0.send(&*buf).await?;
-        Ok(())
+    /// Fails if not connected or the socket is closed
+    pub async fn send(&mut self, mut instruction: Instruction) -> Result<u64> {
+        let id = self.end.next_id;
+        self.end.next_id += 1;
+        instruction.id = id;
+
+        let max_frame_size = self.frame_size();
+        let stream = self.end.stream.as_mut().ok_or(Error::Closed)?;
+        log::trace!("Sending request {} to server", id);
+        write_frame(stream, &instruction, max_frame_size).await?;
+        self.end.pending.insert(id);
+        Ok(id)
     }
 
-    /// Attempts to receive serialized data from the pipe
+    /// Waits for the response to a request sent with [`Self::send`]
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
-    pub async fn recv(&self) -> Result<Instruction> {
-        let mut buf = vec![0u8; READBUF_SIZE];
-        let read = self.sock_1.recv(&mut buf).await?;
-        buf.truncate(read);
-        let target = Instruction::decode(&*buf)?;
-        Ok(target)
+    /// Fails if not connected or the socket is closed
+    pub async fn recv(&mut self) -> Result<Instruction> {
+        let stream = self.end.stream.as_mut().ok_or(Error::Closed)?;
+        loop {
+            let instruction = read_frame(stream, &self.max_frame_size).await?;
+            if self.end.pending.remove(&instruction.id) {
+                return Ok(instruction);
+            }
+            log::debug!("Skipping unexpected response {}", instruction.id);
+        }
     }
 }
 
@@ -215,13 +410,18 @@ pub enum Error {
 
     #[error("encode error {0}")]
     Encode(#[from] prost::EncodeError),
+
+    #[error("instruction of {0} bytes is too big")]
+    FrameSize(usize),
+
+    #[error("socket is closed")]
+    Closed,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_fs::prelude::*;
-    use std::sync::Arc;
 
     pub fn test_instruction_1() -> Instruction {
         Instruction::request_status()
@@ -234,136 +434,185 @@ mod tests {
     #[tokio::test]
     async fn ipc_named_socket_spawn() {
         let tmp = assert_fs::TempDir::new().unwrap();
-        let _ = server(&tmp).unwrap();
-        let _ = client(&tmp).unwrap();
+        let _server = server(&tmp).unwrap();
 
-        assert!(tmp.child("gistit-0").exists());
-        assert!(tmp.child("gistit-1").exists());
+        assert!(tmp.child("gistit.sock").exists());
     }
 
     #[tokio::test]
     async fn ipc_socket_spawn_is_alive() {
         let tmp = assert_fs::TempDir::new().unwrap();
-        let server = server(&tmp).unwrap();
-        let client = client(&tmp).unwrap();
+        let mut client = client(&tmp).unwrap();
+        assert!(!client.alive());
 
-        assert!(server.alive());
+        let _server = server(&tmp).unwrap();
         assert!(client.alive());
     }
 
     #[tokio::test]
     async fn ipc_socket_server_recv_traffic() {
         let tmp = assert_fs::TempDir::new().unwrap();
-        let server = server(&tmp).unwrap();
+        let mut server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
 
         client.connect_blocking().unwrap();
 
-        client.send(test_instruction_1()).await.unwrap();
-        client.send(test_instruction_2()).await.unwrap();
+        let id_1 = client.send(test_instruction_1()).await.unwrap();
+        let id_2 = client.send(test_instruction_2()).await.unwrap();
+        assert_ne!(id_1, id_2);
+
+        let (reply, instruction) = server.recv().await.unwrap();
+        assert_eq!(instruction.kind, test_instruction_1().kind);
+        assert_eq!(reply.id, id_1);
 
-        assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_2());
+        let (reply, instruction) = server.recv().await.unwrap();
+        assert_eq!(instruction.kind, test_instruction_2().kind);
+        assert_eq!(reply.id, id_2);
     }
 
     #[tokio::test]
-    async fn ipc_socket_client_recv_traffic() {
+    async fn ipc_socket_client_recv_response() {
         let tmp = assert_fs::TempDir::new().unwrap();
         let mut server = server(&tmp).unwrap();
-        let client = client(&tmp).unwrap();
-
-        server.connect_blocking().unwrap();
-
-        server.send(test_instruction_1()).await.unwrap();
-        server.send(test_instruction_2()).await.unwrap();
+        let mut client = client(&tmp).unwrap();
 
-        assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(client.recv().await.unwrap(), test_instruction_2());
+        client.connect_blocking().unwrap();
+        let id = client.send(test_instruction_1()).await.unwrap();
+
+        let (reply, _) = server.recv().await.unwrap();
+        // Responses nobody asked for are skipped
+        server
+            .send(
+                Reply {
+                    id: id + 1,
+                    ..reply
+                },
+                Instruction::respond_provide(None),
+            )
+            .await
+            .unwrap();
+        server
+            .send(reply, Instruction::respond_fetch(None))
+            .await
+            .unwrap();
+
+        let response = client.recv().await.unwrap();
+        assert_eq!(response.id, id);
+        assert_eq!(response.kind, Instruction::respond_fetch(None).kind);
     }
 
     #[tokio::test]
-    async fn ipc_socket_alternate_traffic() {
+    async fn ipc_socket_concurrent_clients() {
         let tmp = assert_fs::TempDir::new().unwrap();
         let mut server = server(&tmp).unwrap();
-        let mut client = client(&tmp).unwrap();
-
-        client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
 
-        client.send(test_instruction_1()).await.unwrap();
-        client.send(test_instruction_2()).await.unwrap();
+        let mut clients = Vec::new();
+        for n in 0..8 {
+            let mut client = client(&tmp).unwrap();
+            client.connect_blocking().unwrap();
+            client
+                .send(Instruction::request_fetch(n.to_string()))
+                .await
+                .unwrap();
+            clients.push(client);
+        }
 
-        server.send(test_instruction_1()).await.unwrap();
-        server.send(test_instruction_2()).await.unwrap();
+        // Answer in reverse order, every client still gets its own response
+        let mut requests = Vec::new();
+        for _ in 0..8 {
+            requests.push(server.recv().await.unwrap());
+        }
+        for (reply, instruction) in requests.into_iter().rev() {
+            if let Some(gistit_proto::ipc::instruction::Kind::FetchRequest(request)) =
+                instruction.kind
+            {
+                server
+                    .send(reply, Instruction::respond_provide(Some(request.hash)))
+                    .await
+                    .unwrap();
+            }
+        }
 
-        assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(client.recv().await.unwrap(), test_instruction_2());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_2());
+        for (n, mut client) in clients.into_iter().enumerate() {
+            let response = client.recv().await.unwrap();
+            assert_eq!(
+                response.kind,
+                Instruction::respond_provide(Some(n.to_string())).kind
+            );
+        }
     }
 
     #[tokio::test]
-    async fn ipc_socket_alternate_traffic_rerun() {
+    async fn ipc_socket_large_frames() {
         let tmp = assert_fs::TempDir::new().unwrap();
+        let size = 2 * DEFAULT_MAX_FRAME_SIZE;
         let mut server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
-
         client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
-
-        client.send(test_instruction_1()).await.unwrap();
-        client.send(test_instruction_2()).await.unwrap();
 
-        server.send(test_instruction_1()).await.unwrap();
-        server.send(test_instruction_2()).await.unwrap();
+        let large = Instruction::request_fetch("x".repeat(DEFAULT_MAX_FRAME_SIZE + 1));
+        assert!(matches!(
+            client.send(large.clone()).await,
+            Err(Error::FrameSize(_))
+        ));
 
-        assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(client.recv().await.unwrap(), test_instruction_2());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_2());
+        let mut server = server.max_frame_size(size);
+        let mut client = client.max_frame_size(size);
+        client.send(large.clone()).await.unwrap();
 
-        client.send(test_instruction_1()).await.unwrap();
-        client.send(test_instruction_2()).await.unwrap();
+        let (reply, instruction) = server.recv().await.unwrap();
+        assert_eq!(instruction.kind, large.kind);
 
-        server.send(test_instruction_1()).await.unwrap();
-        server.send(test_instruction_2()).await.unwrap();
-
-        assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-        assert_eq!(client.recv().await.unwrap(), test_instruction_2());
-        assert_eq!(server.recv().await.unwrap(), test_instruction_2());
+        // Written while being read, it doesn't fit the socket buffer
+        let (sent, received) = tokio::join!(server.send(reply, large.clone()), client.recv());
+        sent.unwrap();
+        assert_eq!(received.unwrap().kind, large.kind);
     }
 
     #[tokio::test]
-    async fn ipc_socket_traffic_under_load() {
+    async fn ipc_socket_stalled_client_holds_up_no_one() {
         let tmp = assert_fs::TempDir::new().unwrap();
         let mut server = server(&tmp).unwrap();
-        let mut client = client(&tmp).unwrap();
 
-        client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
+        let mut stalled = client(&tmp).unwrap();
+        stalled.connect_blocking().unwrap();
+        stalled.send(test_instruction_1()).await.unwrap();
+        let (stalled_reply, _) = server.recv().await.unwrap();
 
-        let server = Arc::new(server);
-        let client = Arc::new(client);
+        let mut other = client(&tmp).unwrap();
+        other.connect_blocking().unwrap();
+        let id = other.send(test_instruction_2()).await.unwrap();
+        let (reply, _) = server.recv().await.unwrap();
 
-        for _ in 0..8 {
-            let s = server.clone();
-            let c = client.clone();
+        // Way past the socket buffer and the queue, never read
+        let large = Instruction::request_fetch("x".repeat(DEFAULT_MAX_FRAME_SIZE / 2));
+        for _ in 0..2 * OUTGOING_BUFFER {
+            server.send(stalled_reply, large.clone()).await.unwrap();
+        }
+        assert!(!server.end.connections.lock().await.contains_key(&0));
+
+        server
+            .send(reply, Instruction::respond_fetch(None))
+            .await
+            .unwrap();
+        let response = other.recv().await.unwrap();
+        assert_eq!(response.id, id);
+    }
 
-            tokio::spawn(async move {
-                loop {
-                    c.send(test_instruction_1()).await.unwrap();
-                    c.send(test_instruction_2()).await.unwrap();
+    #[tokio::test]
+    async fn ipc_socket_response_to_closed_client_is_dropped() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut server = server(&tmp).unwrap();
 
-                    s.send(test_instruction_1()).await.unwrap();
-                    s.send(test_instruction_2()).await.unwrap();
-                }
-            });
+        let mut gone = client(&tmp).unwrap();
+        gone.connect_blocking().unwrap();
+        gone.send(test_instruction_1()).await.unwrap();
+        let (reply, _) = server.recv().await.unwrap();
+        drop(gone);
 
-            assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-            assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-            assert_eq!(client.recv().await.unwrap(), test_instruction_2());
-            assert_eq!(server.recv().await.unwrap(), test_instruction_2());
-        }
+        server
+            .send(reply, Instruction::respond_fetch(None))
+            .await
+            .unwrap();
     }
 }
@@ -26,6 +26,8 @@ pub const ORGANIZATION: &str = "demfabris";
 
 pub const QUALIFIER: &str = "io";
 
+pub mod settings;
+
 pub mod path {
     use std::fs;
     use std::path::{Path, PathBuf};
@@ -113,6 +115,8 @@ pub mod env {
 
     pub const GISTIT_SERVER_URL: &str = "GISTIT_SERVER_URL";
 
+    pub const GISTIT_PASSWORD_VAR: &str = "GISTIT_PASSWORD";
+
     #[must_use]
     pub fn var_or_default(var: &str, default: PathBuf) -> PathBuf {
         env::var_os(var)
@@ -125,11 +129,31 @@ pub mod var {
     /// Max gistit size allowed in bytes
     pub const GISTIT_MAX_SIZE: usize = 50_000;
 
+    /// Max gistit size allowed in bytes between nodes over p2p, unless `daemon.max_size` says
+    /// otherwise
+    pub const GISTIT_NODE_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+    /// Size of the chunks large gistits are split into between nodes
+    pub const GISTIT_CHUNK_SIZE: usize = 256 * 1024;
+
+    /// Max encoded payload size in bytes for gistits of up to `max_size` bytes, room for the
+    /// base64 encoded ciphertext of encrypted files on top of their metadata
+    #[must_use]
+    pub const fn max_payload_size(max_size: usize) -> usize {
+        max_size / 3 * 4 + 16_384
+    }
+
     /// Gistit hash size (sha256)
     pub const GISTIT_HASH_LENGTH: usize = 64;
 
     /// Default server base url
     pub const GISTIT_SERVER_URL_BASE: &str = "https://us-central1-gistit-base.cloudfunctions.net/";
+
+    /// Where the web app of the default server shows a gistit
+    pub const GISTIT_WEB_URL_BASE: &str = "https://gistit.vercel.app/h/";
+
+    /// GitHub OAuth app of the default server
+    pub const GISTIT_GITHUB_CLIENT_ID: &str = "265cd618948a2e58042e";
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -141,4 +165,7 @@ pub enum Error {
 
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Settings(String),
 }
@@ -0,0 +1,612 @@
+//! The settings module
+//!
+//! Defaults shared by the cli and the daemon live in `config.toml` in the config directory. Each
+//! setting resolves with the precedence cli > env > file > default, the cli flags being applied
+//! by the callers. Every key has an env var, `GISTIT_` followed by the key in upper case with
+//! dots replaced by underscores, e.g. `GISTIT_DAEMON_PORT` for `daemon.port`.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Array, Document, Item, Table, Value};
+
+use super::{env, var, Error, Result};
+
+/// The settings file, relative to the config directory
+pub const SETTINGS_FILE: &str = "config.toml";
+
+/// Stands for any name in a key, e.g. the profile in `servers.*.url`
+const WILDCARD: &str = "*";
+
+/// The server profile of the hosted gistit server, used unless another one is picked
+pub const DEFAULT_SERVER: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Bool,
+    Port,
+    /// A number of bytes
+    Size,
+    /// Comma separated when given as a string
+    List,
+    /// One of these
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub default: Option<&'static str>,
+    pub about: &'static str,
+}
+
+/// Every setting
+pub const KEYS: [Key; 17] = [
+    Key {
+        name: "author",
+        kind: Kind::Text,
+        default: None,
+        about: "author of sent gistits",
+    },
+    Key {
+        name: "description",
+        kind: Kind::Choice(&["optional", "required"]),
+        default: Some("optional"),
+        about: "whether sending requires a description",
+    },
+    Key {
+        name: "colorscheme",
+        kind: Kind::Text,
+        default: Some("Monokai Extended Origin"),
+        about: "colorscheme of previews",
+    },
+    Key {
+        name: "clipboard",
+        kind: Kind::Bool,
+        default: Some("false"),
+        about: "copy the hash of sent gistits to the clipboard",
+    },
+    Key {
+        name: "server",
+        kind: Kind::Text,
+        default: None,
+        about: "server profile to use without `--server`, see `servers.*.url`",
+    },
+    Key {
+        name: "servers.*.url",
+        kind: Kind::Text,
+        default: None,
+        about: "base url of a server profile",
+    },
+    Key {
+        name: "servers.*.web_url",
+        kind: Kind::Text,
+        default: None,
+        about: "where the web app of a server profile shows a gistit, followed by its hash",
+    },
+    Key {
+        name: "servers.*.github_client_id",
+        kind: Kind::Text,
+        default: None,
+        about: "GitHub OAuth app a server profile exchanges tokens for",
+    },
+    Key {
+        name: "servers.*.max_size",
+        kind: Kind::Size,
+        default: None,
+        about: "largest gistit in bytes a server profile accepts",
+    },
+    Key {
+        name: "daemon.host",
+        kind: Kind::Text,
+        default: Some("0.0.0.0"),
+        about: "address the node listens on",
+    },
+    Key {
+        name: "daemon.port",
+        kind: Kind::Port,
+        default: Some("0"),
+        about: "port the node listens on, 0 picks any",
+    },
+    Key {
+        name: "daemon.listen",
+        kind: Kind::List,
+        default: None,
+        about: "more multiaddrs the node listens on",
+    },
+    Key {
+        name: "daemon.bootnodes",
+        kind: Kind::List,
+        default: None,
+        about: "multiaddrs ending in `/p2p/<peer id>` to bootstrap from, instead of the IPFS ones",
+    },
+    Key {
+        name: "daemon.max_size",
+        kind: Kind::Size,
+        default: None,
+        about: "largest gistit in bytes sent and fetched over p2p, 16 MiB unless set",
+    },
+    Key {
+        name: "history.keys",
+        kind: Kind::Bool,
+        default: Some("false"),
+        about:
+            "keep the keys of `hash#key` gistits in the history, so `gistit log --show` opens them",
+    },
+    Key {
+        name: "backends.send",
+        kind: Kind::List,
+        default: None,
+        about: "backends to send to without `--via`, instead of every available one",
+    },
+    Key {
+        name: "backends.fetch",
+        kind: Kind::List,
+        default: None,
+        about: "sources to fetch from without `--from`, in order",
+    },
+];
+
+/// A gistit server and the settings that go with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub name: String,
+    pub url: String,
+    pub web_url: Option<String>,
+    pub github_client_id: Option<String>,
+    pub max_size: u64,
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Env,
+    File,
+    Default,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env => f.write_str("env"),
+            Self::File => f.write_str("file"),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    path: PathBuf,
+    document: Document,
+    /// Looks up environment variables, swapped out in tests
+    env: fn(&str) -> Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings file in `config_path`, a missing file has no settings
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't valid TOML
+    pub fn load(config_path: &Path) -> Result<Self> {
+        let path = config_path.join(SETTINGS_FILE);
+        let document = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .parse()
+                .map_err(|err| Error::Settings(format!("invalid {}: {}", SETTINGS_FILE, err)))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Document::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            document,
+            env: |name| std::env::var(name).ok(),
+        })
+    }
+
+    /// The value of a setting, comma separating lists, along with where it came from
+    ///
+    /// # Errors
+    ///
+    /// Fails if the key is unknown
+    pub fn get(&self, name: &str) -> Result<Option<(String, Origin)>> {
+        let key = key(name)?;
+
+        if !key.name.contains(WILDCARD) {
+            if let Some(value) = (self.env)(&env_var(name)) {
+                return Ok(Some((value, Origin::Env)));
+            }
+        }
+        if let Some(value) = self.file_value(name) {
+            return Ok(Some((value, Origin::File)));
+        }
+        Ok(key.default.map(|value| (value.to_owned(), Origin::Default)))
+    }
+
+    /// Every setting with a value, profiles found in the file included
+    #[must_use]
+    pub fn all(&self) -> Vec<(String, String, Origin)> {
+        let mut names = Vec::new();
+        for key in KEYS {
+            if let Some((prefix, suffix)) = key.name.split_once(WILDCARD) {
+                let table = self
+                    .document
+                    .get(prefix.trim_end_matches('.'))
+                    .and_then(Item::as_table_like);
+                if let Some(table) = table {
+                    for (name, _) in table.iter() {
+                        names.push(format!("{}{}{}", prefix, name, suffix));
+                    }
+                }
+            } else {
+                names.push(key.name.to_owned());
+            }
+        }
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let (value, origin) = self.get(&name).ok()??;
+                Some((name, value, origin))
+            })
+            .collect()
+    }
+
+    /// Sets a setting in the file, checking the value against its kind
+    ///
+    /// # Errors
+    ///
+    /// Fails if the key is unknown or the value invalid
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let item = parse(key(name)?.kind, value)
+            .ok_or_else(|| Error::Settings(format!("invalid value for `{}`: '{}'", name, value)))?;
+
+        let (parents, last) = name.rsplit_once('.').unwrap_or(("", name));
+        let parts = parents.split('.').filter(|part| !part.is_empty());
+
+        let mut table: &mut Table = self.document.as_table_mut();
+        for part in parts {
+            let entry = table.entry(part).or_insert_with(toml_edit::table);
+            // Intermediate tables only show up as headers if they hold values
+            if let Some(inner) = entry.as_table_mut() {
+                inner.set_implicit(true);
+            }
+            table = entry
+                .as_table_mut()
+                .ok_or_else(|| Error::Settings(format!("`{}` is not a table", part)))?;
+        }
+        table.insert(last, item);
+        Ok(())
+    }
+
+    /// Writes the settings file back, keeping comments and formatting
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be written
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, self.document.to_string())?;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.get(name).ok().flatten().map(|(value, _)| value)
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the value isn't `true` or `false`
+    pub fn flag(&self, name: &str) -> Result<Option<bool>> {
+        self.typed(name, |value| value.parse().ok())
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the value isn't a port number
+    pub fn port(&self, name: &str) -> Result<Option<u16>> {
+        self.typed(name, |value| value.parse().ok())
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the value isn't a number of bytes
+    pub fn size(&self, name: &str) -> Result<Option<u64>> {
+        self.typed(name, |value| value.parse().ok())
+    }
+
+    #[must_use]
+    pub fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.text(name).map(|value| split_list(&value))
+    }
+
+    /// The server profile `name`, else the `server` setting, else the default one. The default
+    /// profile is the hosted server, each of its settings can be overridden in `servers.default`.
+    /// `GISTIT_SERVER_URL` overrides the url of the profile picked unless `name` is given
+    ///
+    /// # Errors
+    ///
+    /// Fails if the profile doesn't exist or has invalid settings
+    pub fn server(&self, name: Option<&str>) -> Result<Server> {
+        let url_override = match name {
+            Some(_) => None,
+            None => (self.env)(env::GISTIT_SERVER_URL),
+        };
+        let name = name
+            .map(ToOwned::to_owned)
+            .or_else(|| self.text("server"))
+            .unwrap_or_else(|| DEFAULT_SERVER.to_owned());
+        let setting = |field: &str| format!("servers.{}.{}", name, field);
+        let builtin = |value: &str| (name == DEFAULT_SERVER).then(|| value.to_owned());
+
+        let url = url_override
+            .or_else(|| self.text(&setting("url")))
+            .or_else(|| builtin(var::GISTIT_SERVER_URL_BASE))
+            .ok_or_else(|| Error::Settings(format!("unknown server profile '{}'", name)))?;
+
+        Ok(Server {
+            web_url: self
+                .text(&setting("web_url"))
+                .or_else(|| builtin(var::GISTIT_WEB_URL_BASE)),
+            github_client_id: self
+                .text(&setting("github_client_id"))
+                .or_else(|| builtin(var::GISTIT_GITHUB_CLIENT_ID)),
+            max_size: self
+                .size(&setting("max_size"))?
+                .unwrap_or(var::GISTIT_MAX_SIZE as u64),
+            url,
+            name,
+        })
+    }
+
+    /// The largest gistit the node sends and fetches, see `daemon.max_size`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the setting isn't a number of bytes
+    pub fn node_max_size(&self) -> Result<u64> {
+        Ok(self
+            .size("daemon.max_size")?
+            .unwrap_or(var::GISTIT_NODE_MAX_SIZE as u64))
+    }
+
+    fn typed<T>(&self, name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>> {
+        match self.get(name)? {
+            Some((value, origin)) => parse(&value).map(Some).ok_or_else(|| {
+                Error::Settings(format!(
+                    "invalid value for `{}` in {}: '{}'",
+                    name, origin, value
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn file_value(&self, name: &str) -> Option<String> {
+        let mut item = self.document.as_item();
+        for part in name.split('.') {
+            item = item.get(part)?;
+        }
+
+        match item.as_value()? {
+            Value::String(value) => Some(value.value().clone()),
+            Value::Array(array) => Some(
+                array
+                    .iter()
+                    .map(|value| {
+                        value
+                            .as_str()
+                            .map_or_else(|| value.to_string(), ToOwned::to_owned)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            value => Some(value.to_string().trim().to_owned()),
+        }
+    }
+}
+
+/// The known key matching `name`
+///
+/// # Errors
+///
+/// Fails if there's none
+pub fn key(name: &str) -> Result<Key> {
+    KEYS.into_iter()
+        .find(|key| {
+            let mut parts = name.split('.');
+            key.name.split('.').all(
+                |part| matches!(parts.next(), Some(other) if part == WILDCARD || part == other),
+            ) && parts.next().is_none()
+        })
+        .ok_or_else(|| Error::Settings(format!("unknown setting `{}`", name)))
+}
+
+/// The env var overriding a key
+#[must_use]
+pub fn env_var(name: &str) -> String {
+    format!("GISTIT_{}", name.to_uppercase().replace('.', "_"))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn parse(kind: Kind, value: &str) -> Option<Item> {
+    match kind {
+        Kind::Text if !value.is_empty() => Some(toml_edit::value(value)),
+        Kind::Bool => value.parse::<bool>().ok().map(toml_edit::value),
+        Kind::Port => value
+            .parse::<u16>()
+            .ok()
+            .map(|port| toml_edit::value(i64::from(port))),
+        // TOML integers are signed 64 bits
+        Kind::Size => value
+            .parse::<u64>()
+            .ok()
+            .and_then(|size| i64::try_from(size).ok())
+            .map(toml_edit::value),
+        Kind::List => Some(toml_edit::value(
+            split_list(value).into_iter().collect::<Array>(),
+        )),
+        Kind::Choice(choices) if choices.contains(&value) => Some(toml_edit::value(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(contents: &str) -> Settings {
+        Settings {
+            path: PathBuf::new(),
+            document: contents.parse().unwrap(),
+            env: |_| None,
+        }
+    }
+
+    #[test]
+    fn settings_get_layers() {
+        let settings = settings(
+            r#"
+author = "Matthew McConaughey"
+clipboard = true
+
+[daemon]
+port = 4001
+bootnodes = ["/ip4/127.0.0.1/tcp/4001/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN"]
+"#,
+        );
+
+        assert_eq!(
+            settings.get("author").unwrap(),
+            Some(("Matthew McConaughey".to_owned(), Origin::File))
+        );
+        assert_eq!(settings.flag("clipboard").unwrap(), Some(true));
+        assert_eq!(settings.port("daemon.port").unwrap(), Some(4001));
+        assert_eq!(settings.list("daemon.bootnodes").unwrap().len(), 1);
+        assert_eq!(
+            settings.get("daemon.host").unwrap(),
+            Some(("0.0.0.0".to_owned(), Origin::Default))
+        );
+        assert_eq!(settings.get("backends.fetch").unwrap(), None);
+        assert!(settings.get("nope").is_err());
+
+        let settings = Settings {
+            env: |name| (name == env_var("colorscheme")).then(|| "Dracula".to_owned()),
+            ..settings
+        };
+        assert_eq!(
+            settings.get("colorscheme").unwrap(),
+            Some(("Dracula".to_owned(), Origin::Env))
+        );
+    }
+
+    #[test]
+    fn settings_set_keeps_the_rest() {
+        let mut settings = settings("# my settings\nauthor = \"me\"\n");
+
+        settings.set("backends.fetch", "cache, server").unwrap();
+        settings
+            .set("servers.staging.url", "http://localhost:4000/")
+            .unwrap();
+        settings.set("daemon.port", "4001").unwrap();
+        assert!(settings.set("daemon.port", "99999").is_err());
+        settings.set("daemon.max_size", "8589934592").unwrap();
+        assert_eq!(settings.node_max_size().unwrap(), 8_589_934_592);
+        assert!(settings
+            .set("daemon.max_size", "9223372036854775808")
+            .is_err());
+        assert!(settings.set("description", "sometimes").is_err());
+        assert!(settings
+            .set("servers.url", "http://localhost:4000/")
+            .is_err());
+
+        let document = settings.document.to_string();
+        assert!(document.starts_with("# my settings\nauthor = \"me\"\n"));
+        assert_eq!(
+            settings.list("backends.fetch").unwrap(),
+            vec!["cache", "server"]
+        );
+
+        settings.set("server", "staging").unwrap();
+        assert_eq!(settings.server(None).unwrap().url, "http://localhost:4000/");
+        assert_eq!(
+            settings
+                .all()
+                .iter()
+                .filter(|(_, _, origin)| *origin == Origin::File)
+                .map(|(name, _, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "author",
+                "server",
+                "servers.staging.url",
+                "daemon.port",
+                "daemon.max_size",
+                "backends.fetch"
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_server_profiles() {
+        let settings = settings(
+            r#"
+[servers.default]
+max_size = 100000
+
+[servers.staging]
+url = "http://localhost:4000/"
+github_client_id = "staging-client"
+"#,
+        );
+
+        let default = settings.server(None).unwrap();
+        assert_eq!(default.name, DEFAULT_SERVER);
+        assert_eq!(default.url, var::GISTIT_SERVER_URL_BASE);
+        assert_eq!(default.web_url.as_deref(), Some(var::GISTIT_WEB_URL_BASE));
+        assert_eq!(default.max_size, 100_000);
+
+        let staging = settings.server(Some("staging")).unwrap();
+        assert_eq!(
+            staging,
+            Server {
+                name: "staging".to_owned(),
+                url: "http://localhost:4000/".to_owned(),
+                web_url: None,
+                github_client_id: Some("staging-client".to_owned()),
+                max_size: var::GISTIT_MAX_SIZE as u64,
+            }
+        );
+
+        assert!(settings.server(Some("production")).is_err());
+    }
+
+    #[test]
+    fn settings_node_max_size() {
+        assert_eq!(
+            settings("").node_max_size().unwrap(),
+            var::GISTIT_NODE_MAX_SIZE as u64
+        );
+        assert_eq!(
+            settings("[daemon]\nmax_size = 1048576\n")
+                .node_max_size()
+                .unwrap(),
+            1_048_576
+        );
+        assert!(settings("[daemon]\nmax_size = \"big\"\n")
+            .node_max_size()
+            .is_err());
+    }
+}
@@ -1,4 +1,12 @@
 fn main() -> std::io::Result<()> {
-    prost_build::compile_protos(&["src/payload.proto", "src/ipc.proto"], &["src"])?;
+    prost_build::compile_protos(
+        &[
+            "src/payload.proto",
+            "src/ipc.proto",
+            "src/plugin.proto",
+            "src/exchange.proto",
+        ],
+        &["src"],
+    )?;
     Ok(())
 }
@@ -26,24 +26,59 @@ pub use prost;
 pub use ipc::Instruction;
 pub use payload::{gistit::Inner, Gistit};
 
+// Generated by prost, lints on it are out of our hands
+#[allow(clippy::derive_partial_eq_without_eq, clippy::large_enum_variant)]
 pub mod payload {
+    use std::borrow::Cow;
+    use std::io::{Read, Write};
+
     use super::prost::Message;
-    use super::Result;
+    use super::{Error, Result};
+    use flate2::read::DeflateDecoder;
+    use flate2::write::DeflateEncoder;
     use sha2::{Digest, Sha256};
 
     include!(concat!(env!("OUT_DIR"), "/gistit.payload.rs"));
 
-    pub fn hash(author: &str, description: Option<&str>, data: impl AsRef<[u8]>) -> String {
+    /// Smaller files are sent as they are, too little to gain
+    const COMPRESSION_MIN_SIZE: usize = 1024;
+
+    /// Hashes every `(name, contents)` file along with `author` and `description`. Each field
+    /// is length prefixed, so bytes can't move across file boundaries or between fields
+    pub fn hash<'a>(
+        author: &str,
+        description: Option<&str>,
+        files: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.update(author);
-        hasher.update(description.unwrap_or(""));
+        for (name, contents) in files {
+            update_prefixed(&mut hasher, name.as_bytes());
+            update_prefixed(&mut hasher, contents);
+        }
+        update_prefixed(&mut hasher, author.as_bytes());
+        update_optional(&mut hasher, description.map(str::as_bytes));
 
         format!("{:x}", hasher.finalize())
     }
 
+    fn update_prefixed(hasher: &mut Sha256, bytes: &[u8]) {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+
+    fn update_optional(hasher: &mut Sha256, bytes: Option<&[u8]>) {
+        match bytes {
+            Some(bytes) => {
+                hasher.update([1]);
+                update_prefixed(hasher, bytes);
+            }
+            None => hasher.update([0]),
+        }
+    }
+
     impl Gistit {
         #[must_use]
+        #[allow(clippy::missing_const_for_fn)]
         pub fn new(
             hash: String,
             author: String,
@@ -57,7 +92,147 @@ pub mod payload {
                 description,
                 timestamp,
                 inner,
+                encryption: None,
+                expires_at: None,
+                signature: None,
+            }
+        }
+
+        /// Computes the hash of this gistit's contents, to be compared against [`Self::hash`]
+        ///
+        /// Compressed files are hashed inflated so the hash doesn't depend on the compressor,
+        /// encrypted ones as ciphertext. Empty if a compressed file doesn't inflate, which
+        /// matches no hash
+        #[must_use]
+        pub fn compute_hash(&self) -> String {
+            let is_encrypted = self.is_encrypted();
+            let mut files = Vec::with_capacity(self.inner.len());
+
+            for inner in &self.inner {
+                let contents = if inner.is_compressed() && !is_encrypted {
+                    match inner.inflate() {
+                        Ok(contents) => Cow::Owned(contents),
+                        Err(_) => return String::new(),
+                    }
+                } else {
+                    Cow::Borrowed(inner.contents())
+                };
+                files.push((inner.name.as_str(), contents));
+            }
+
+            hash(
+                &self.author,
+                self.description.as_deref(),
+                files
+                    .iter()
+                    .map(|(name, contents)| (*name, contents.as_ref())),
+            )
+        }
+
+        /// Digest of every field but the signature, what gets signed. File contents are covered
+        /// through [`Self::hash`]
+        #[must_use]
+        pub fn signed_digest(&self) -> Vec<u8> {
+            let mut hasher = Sha256::new();
+            update_prefixed(&mut hasher, self.hash.as_bytes());
+            update_prefixed(&mut hasher, self.author.as_bytes());
+            update_optional(&mut hasher, self.description.as_deref().map(str::as_bytes));
+            update_prefixed(&mut hasher, self.timestamp.as_bytes());
+            update_optional(&mut hasher, self.expires_at.as_deref().map(str::as_bytes));
+
+            hasher.update((self.inner.len() as u64).to_be_bytes());
+            for inner in &self.inner {
+                update_prefixed(&mut hasher, inner.name.as_bytes());
+                update_prefixed(&mut hasher, inner.lang.as_bytes());
+                hasher.update(inner.size.to_be_bytes());
+                hasher.update(inner.encoding.to_be_bytes());
+                hasher.update(inner.compression.to_be_bytes());
+            }
+
+            match self.encryption {
+                Some(ref encryption) => {
+                    hasher.update([1]);
+                    hasher.update(encryption.scheme.to_be_bytes());
+                    update_prefixed(&mut hasher, &encryption.salt);
+                    update_prefixed(&mut hasher, &encryption.ephemeral_key);
+                    hasher.update((encryption.recipients.len() as u64).to_be_bytes());
+                    for recipient in &encryption.recipients {
+                        update_prefixed(&mut hasher, recipient.peer_id.as_bytes());
+                        update_prefixed(&mut hasher, &recipient.wrapped_key);
+                    }
+                }
+                None => hasher.update([0]),
+            }
+
+            hasher.finalize().to_vec()
+        }
+
+        /// Checks that every file carries its contents where its encoding and compression say
+        /// it does
+        ///
+        /// # Errors
+        ///
+        /// Fails if a plain text file has binary contents, or a binary or compressed file has
+        /// text contents while not being encrypted
+        pub fn check_encoding(&self) -> Result<()> {
+            let is_encrypted = self.is_encrypted();
+            // Ciphertext is always in `data`
+            let is_well_formed = |inner: &gistit::Inner| {
+                if is_encrypted || !(inner.is_binary() || inner.is_compressed()) {
+                    inner.raw.is_empty()
+                } else {
+                    inner.data.is_empty()
+                }
+            };
+
+            if self.inner.iter().all(is_well_formed) {
+                Ok(())
+            } else {
+                Err(Error::Other("file contents don't match their encoding"))
+            }
+        }
+
+        #[must_use]
+        pub const fn is_encrypted(&self) -> bool {
+            self.encryption.is_some()
+        }
+
+        #[must_use]
+        pub fn is_compressed(&self) -> bool {
+            self.inner.iter().any(gistit::Inner::is_compressed)
+        }
+
+        /// Inflates every compressed file in place
+        ///
+        /// # Errors
+        ///
+        /// Fails if the gistit is still encrypted or a file doesn't inflate to its size
+        pub fn decompress(&mut self) -> Result<()> {
+            if self.is_encrypted() && self.is_compressed() {
+                return Err(Error::Other(
+                    "encrypted gistits are decompressed once decrypted",
+                ));
             }
+            self.inner
+                .iter_mut()
+                .try_for_each(gistit::Inner::decompress)
+        }
+
+        /// Milliseconds left at `now` before this gistit expires, `None` if it never does
+        ///
+        /// Malformed expiry timestamps are treated as already expired
+        #[must_use]
+        pub fn remaining_millis(&self, now: u128) -> Option<u128> {
+            self.expires_at.as_ref().map(|expires_at| {
+                expires_at
+                    .parse::<u128>()
+                    .map_or(0, |expires_at| expires_at.saturating_sub(now))
+            })
+        }
+
+        #[must_use]
+        pub fn is_expired(&self, now: u128) -> bool {
+            self.remaining_millis(now) == Some(0)
         }
 
         #[must_use]
@@ -72,6 +247,28 @@ pub mod payload {
                 lang,
                 size,
                 data,
+                encoding: gistit::inner::Encoding::Utf8 as i32,
+                raw: Vec::new(),
+                compression: gistit::inner::Compression::None as i32,
+            }
+        }
+
+        /// Like [`Self::new_inner`] for contents that aren't valid UTF-8 text
+        #[must_use]
+        pub const fn new_binary_inner(
+            name: String,
+            lang: String,
+            size: u32,
+            raw: Vec<u8>,
+        ) -> gistit::Inner {
+            gistit::Inner {
+                name,
+                lang,
+                size,
+                data: String::new(),
+                encoding: gistit::inner::Encoding::Binary as i32,
+                raw,
+                compression: gistit::inner::Compression::None as i32,
             }
         }
 
@@ -84,8 +281,90 @@ pub mod payload {
             Ok(Self::decode(bytes.as_ref())?)
         }
     }
+
+    impl gistit::Inner {
+        #[must_use]
+        pub fn is_binary(&self) -> bool {
+            self.encoding() == gistit::inner::Encoding::Binary
+        }
+
+        #[must_use]
+        pub fn is_compressed(&self) -> bool {
+            self.compression() != gistit::inner::Compression::None
+        }
+
+        /// The bytes this file carries, `raw` for unencrypted binary or compressed files and
+        /// `data` otherwise
+        #[must_use]
+        pub fn contents(&self) -> &[u8] {
+            if (self.is_binary() || self.is_compressed()) && self.data.is_empty() {
+                &self.raw
+            } else {
+                self.data.as_bytes()
+            }
+        }
+
+        /// Deflates the contents into `raw` if that saves at least a tenth of them. Must happen
+        /// before encrypting, ciphertext doesn't compress
+        pub fn compress(&mut self) {
+            let size = self.contents().len();
+            if self.is_compressed() || size < COMPRESSION_MIN_SIZE {
+                return;
+            }
+
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            let compressed = match encoder
+                .write_all(self.contents())
+                .and_then(|()| encoder.finish())
+            {
+                Ok(compressed) if compressed.len() <= size - size / 10 => compressed,
+                _ => return,
+            };
+
+            self.data.clear();
+            self.raw = compressed;
+            self.set_compression(gistit::inner::Compression::Deflate);
+        }
+
+        /// Inflates the contents back to where their encoding says, once decrypted
+        ///
+        /// # Errors
+        ///
+        /// Fails if they don't inflate, inflate past the file size or aren't valid UTF-8 in a
+        /// text file
+        pub fn decompress(&mut self) -> Result<()> {
+            if !self.is_compressed() {
+                return Ok(());
+            }
+
+            let contents = self.inflate()?;
+            if self.is_binary() {
+                self.raw = contents;
+            } else {
+                self.data = String::from_utf8(contents)
+                    .map_err(|_| Error::Other("compressed text file is not valid utf8"))?;
+                self.raw.clear();
+            }
+            self.set_compression(gistit::inner::Compression::None);
+            Ok(())
+        }
+
+        /// The inflated contents of a compressed file, never past its declared size
+        fn inflate(&self) -> Result<Vec<u8>> {
+            let mut contents = Vec::new();
+            DeflateDecoder::new(self.raw.as_slice())
+                .take(u64::from(self.size) + 1)
+                .read_to_end(&mut contents)
+                .map_err(|_| Error::Other("compressed file is corrupted"))?;
+            if contents.len() > self.size as usize {
+                return Err(Error::Other("compressed file is bigger than its size"));
+            }
+            Ok(contents)
+        }
+    }
 }
 
+#[allow(clippy::derive_partial_eq_without_eq, clippy::large_enum_variant)]
 pub mod ipc {
     use super::Gistit;
     use super::{Error, Result};
@@ -96,6 +375,7 @@ pub mod ipc {
         #[must_use]
         pub const fn request_status() -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::StatusRequest(
                     instruction::StatusRequest {},
                 )),
@@ -105,6 +385,7 @@ pub mod ipc {
         #[must_use]
         pub const fn request_fetch(hash: String) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::FetchRequest(instruction::FetchRequest {
                     hash,
                 })),
@@ -112,11 +393,13 @@ pub mod ipc {
         }
 
         #[must_use]
-        pub const fn request_provide(gistit: Gistit) -> Self {
+        pub const fn request_provide(gistit: Gistit, once: bool) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::ProvideRequest(
                     instruction::ProvideRequest {
                         gistit: Some(gistit),
+                        once,
                     },
                 )),
             }
@@ -125,6 +408,7 @@ pub mod ipc {
         #[must_use]
         pub const fn request_shutdown() -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::ShutdownRequest(
                     instruction::ShutdownRequest {},
                 )),
@@ -134,26 +418,70 @@ pub mod ipc {
         #[must_use]
         pub const fn request_dial(address: String) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::DialRequest(instruction::DialRequest {
                     address,
                 })),
             }
         }
 
+        #[must_use]
+        pub const fn request_hosting() -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::HostingRequest(
+                    instruction::HostingRequest {},
+                )),
+            }
+        }
+
+        #[must_use]
+        pub const fn request_unhost(hash: String) -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::UnhostRequest(
+                    instruction::UnhostRequest { hash },
+                )),
+            }
+        }
+
+        #[must_use]
+        pub const fn request_republish() -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::RepublishRequest(
+                    instruction::RepublishRequest {},
+                )),
+            }
+        }
+
+        #[must_use]
+        pub const fn request_cancel(id: u64) -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::CancelRequest(
+                    instruction::CancelRequest { id },
+                )),
+            }
+        }
+
         #[must_use]
         pub const fn respond_status(
             peer_id: String,
             peer_count: u32,
             pending_connections: u32,
             hosting: u32,
+            once: u32,
         ) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::StatusResponse(
                     instruction::StatusResponse {
                         peer_id,
                         peer_count,
                         pending_connections,
                         hosting,
+                        once,
                     },
                 )),
             }
@@ -162,6 +490,7 @@ pub mod ipc {
         #[must_use]
         pub const fn respond_fetch(gistit: Option<Gistit>) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::FetchResponse(
                     instruction::FetchResponse { gistit },
                 )),
@@ -171,12 +500,43 @@ pub mod ipc {
         #[must_use]
         pub const fn respond_provide(maybe_hash: Option<String>) -> Self {
             Self {
+                id: 0,
                 kind: Some(instruction::Kind::ProvideResponse(
                     instruction::ProvideResponse { hash: maybe_hash },
                 )),
             }
         }
 
+        #[must_use]
+        pub const fn respond_hosting(hosted: Vec<instruction::Hosted>) -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::HostingResponse(
+                    instruction::HostingResponse { hosted },
+                )),
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_unhost(unhosted: bool) -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::UnhostResponse(
+                    instruction::UnhostResponse { unhosted },
+                )),
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_republish(count: u32) -> Self {
+            Self {
+                id: 0,
+                kind: Some(instruction::Kind::RepublishResponse(
+                    instruction::RepublishResponse { count },
+                )),
+            }
+        }
+
         /// Unwraps [`Self`] expecting a request kind
         ///
         /// # Errors
@@ -190,12 +550,17 @@ pub mod ipc {
                         Some(
                             instruction::Kind::FetchResponse(_)
                             | instruction::Kind::ProvideResponse(_)
-                            | instruction::Kind::StatusResponse(_),
+                            | instruction::Kind::StatusResponse(_)
+                            | instruction::Kind::HostingResponse(_)
+                            | instruction::Kind::UnhostResponse(_)
+                            | instruction::Kind::RepublishResponse(_),
                         )
                         | None,
+                    ..
                 } => Err(Error::Other("instruction is not a request")),
                 Self {
                     kind: Some(request),
+                    ..
                 } => Ok(request),
             }
         }
@@ -214,18 +579,201 @@ pub mod ipc {
                             instruction::Kind::FetchRequest(_)
                             | instruction::Kind::StatusRequest(_)
                             | instruction::Kind::ShutdownRequest(_)
-                            | instruction::Kind::ProvideRequest(_),
+                            | instruction::Kind::ProvideRequest(_)
+                            | instruction::Kind::HostingRequest(_)
+                            | instruction::Kind::UnhostRequest(_)
+                            | instruction::Kind::RepublishRequest(_)
+                            | instruction::Kind::CancelRequest(_),
                         )
                         | None,
+                    ..
                 } => Err(Error::Other("instruction is not a response")),
                 Self {
                     kind: Some(response),
+                    ..
                 } => Ok(response),
             }
         }
     }
 }
 
+#[allow(clippy::derive_partial_eq_without_eq, clippy::large_enum_variant)]
+pub mod exchange {
+    use super::Gistit;
+    use sha2::{Digest, Sha256};
+
+    include!(concat!(env!("OUT_DIR"), "/gistit.exchange.rs"));
+
+    /// Sha256 of a chunk, chunks are addressed by it
+    #[must_use]
+    pub fn digest(data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+
+    impl Request {
+        #[must_use]
+        pub const fn manifest(hash: String) -> Self {
+            Self {
+                kind: Some(request::Kind::Manifest(request::Manifest { hash })),
+            }
+        }
+
+        #[must_use]
+        pub const fn chunk(hash: String, index: u32, digest: Vec<u8>) -> Self {
+            Self {
+                kind: Some(request::Kind::Chunk(request::Chunk {
+                    hash,
+                    index,
+                    digest,
+                })),
+            }
+        }
+    }
+
+    impl Response {
+        #[must_use]
+        pub const fn gistit(gistit: Gistit) -> Self {
+            Self {
+                kind: Some(response::Kind::Gistit(gistit)),
+            }
+        }
+
+        #[must_use]
+        pub const fn manifest(manifest: response::Manifest) -> Self {
+            Self {
+                kind: Some(response::Kind::Manifest(manifest)),
+            }
+        }
+
+        #[must_use]
+        pub const fn chunk(index: u32, data: Vec<u8>) -> Self {
+            Self {
+                kind: Some(response::Kind::Chunk(response::Chunk { index, data })),
+            }
+        }
+    }
+
+    impl response::Manifest {
+        /// Splits the protobuf encoding of the gistit `hash` every `chunk_size` bytes
+        ///
+        /// # Panics
+        ///
+        /// If `chunk_size` is zero or doesn't fit a `u32`
+        #[must_use]
+        pub fn new(hash: String, encoded: &[u8], chunk_size: usize) -> Self {
+            Self {
+                hash,
+                size: encoded.len() as u64,
+                chunk_size: u32::try_from(chunk_size).expect("chunk size to fit a u32"),
+                chunks: encoded.chunks(chunk_size).map(digest).collect(),
+            }
+        }
+
+        /// Whether the chunks add up to `size`, a manifest not adding up can't be fetched
+        #[must_use]
+        pub fn is_consistent(&self) -> bool {
+            self.chunk_size > 0
+                && self.chunks.len() as u64
+                    == (self.size + u64::from(self.chunk_size) - 1) / u64::from(self.chunk_size)
+        }
+
+        /// The chunk `index` of `encoded`, if it's the one with `digest`
+        #[must_use]
+        pub fn chunk<'a>(&self, encoded: &'a [u8], index: u32, digest: &[u8]) -> Option<&'a [u8]> {
+            let data = encoded
+                .chunks(self.chunk_size as usize)
+                .nth(index as usize)?;
+            (self.chunks.get(index as usize).map(Vec::as_slice) == Some(digest)).then(|| data)
+        }
+
+        /// Whether `data` is the chunk `index`
+        #[must_use]
+        pub fn verify(&self, index: u32, data: &[u8]) -> bool {
+            self.chunks.get(index as usize) == Some(&digest(data))
+        }
+    }
+}
+
+#[allow(
+    clippy::derive_partial_eq_without_eq,
+    clippy::large_enum_variant,
+    clippy::struct_excessive_bools
+)]
+pub mod plugin {
+    use super::Gistit;
+
+    include!(concat!(env!("OUT_DIR"), "/gistit.plugin.rs"));
+
+    impl Request {
+        #[must_use]
+        pub const fn capabilities() -> Self {
+            Self {
+                kind: Some(request::Kind::Capabilities(request::Capabilities {})),
+            }
+        }
+
+        #[must_use]
+        pub const fn publish(gistit: Gistit, once: bool) -> Self {
+            Self {
+                kind: Some(request::Kind::Publish(request::Publish {
+                    gistit: Some(gistit),
+                    once,
+                })),
+            }
+        }
+
+        #[must_use]
+        pub const fn retrieve(hash: String) -> Self {
+            Self {
+                kind: Some(request::Kind::Retrieve(request::Retrieve { hash })),
+            }
+        }
+    }
+
+    impl Response {
+        #[must_use]
+        #[allow(clippy::fn_params_excessive_bools)]
+        pub const fn capabilities(
+            available: bool,
+            encryption: bool,
+            once: bool,
+            max_size: u64,
+            binary: bool,
+        ) -> Self {
+            Self {
+                kind: Some(response::Kind::Capabilities(response::Capabilities {
+                    available,
+                    encryption,
+                    once,
+                    max_size,
+                    binary,
+                })),
+            }
+        }
+
+        #[must_use]
+        pub const fn published(hash: Option<String>, url: Option<String>) -> Self {
+            Self {
+                kind: Some(response::Kind::Published(response::Published { hash, url })),
+            }
+        }
+
+        #[must_use]
+        pub const fn retrieved(gistit: Option<Gistit>) -> Self {
+            Self {
+                kind: Some(response::Kind::Retrieved(response::Retrieved { gistit })),
+            }
+        }
+
+        #[must_use]
+        pub const fn failed(reason: String) -> Self {
+            Self {
+                kind: Some(response::Kind::Failed(response::Failed { reason })),
+            }
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
@@ -252,6 +800,162 @@ mod tests {
         assert_eq!(Gistit::decode(&*bytes).unwrap(), payload);
     }
 
+    #[test]
+    fn test_payload_compute_hash() {
+        let inner = vec![
+            Gistit::new_inner("a.rs".to_owned(), "rust".to_owned(), 3, "foo".to_owned()),
+            Gistit::new_inner("b.rs".to_owned(), "rust".to_owned(), 3, "bar".to_owned()),
+        ];
+        let hash = payload::hash(
+            "author",
+            Some("description"),
+            [("a.rs", &b"foo"[..]), ("b.rs", &b"bar"[..])],
+        );
+        let mut payload = Gistit::new(
+            hash.clone(),
+            "author".to_owned(),
+            Some("description".to_owned()),
+            String::new(),
+            inner,
+        );
+        assert_eq!(payload.compute_hash(), hash);
+
+        payload.inner[1].data = "baz".to_owned();
+        assert_ne!(payload.compute_hash(), hash);
+
+        // Moving bytes across files or renaming one changes the hash too
+        payload.inner[0].data = "fo".to_owned();
+        payload.inner[1].data = "obar".to_owned();
+        assert_ne!(payload.compute_hash(), hash);
+        payload.inner[0].data = "foo".to_owned();
+        payload.inner[1].data = "bar".to_owned();
+        assert_eq!(payload.compute_hash(), hash);
+        payload.inner[1].name = "c.rs".to_owned();
+        assert_ne!(payload.compute_hash(), hash);
+    }
+
+    #[test]
+    fn test_payload_binary_inner() {
+        let raw = vec![0x89, b'P', b'N', b'G', 0x00, 0xff];
+        let inner = vec![
+            Gistit::new_inner("a.txt".to_owned(), "text".to_owned(), 3, "foo".to_owned()),
+            Gistit::new_binary_inner("b.png".to_owned(), "binary".to_owned(), 6, raw.clone()),
+        ];
+        let hash = payload::hash("author", None, [("a.txt", &b"foo"[..]), ("b.png", &raw)]);
+        let mut payload = Gistit::new(
+            hash.clone(),
+            "author".to_owned(),
+            None,
+            String::new(),
+            inner,
+        );
+
+        assert!(payload.inner[1].is_binary());
+        assert_eq!(payload.inner[1].contents(), raw.as_slice());
+        assert_eq!(payload.compute_hash(), hash);
+        assert!(payload.check_encoding().is_ok());
+
+        let decoded = Gistit::decode(&*payload.encode_to_vec()).unwrap();
+        assert_eq!(decoded, payload);
+
+        payload.inner[0].raw = raw;
+        assert!(payload.check_encoding().is_err());
+    }
+
+    #[test]
+    fn test_payload_compression() {
+        let log = "GET /index.html 200\n".repeat(500);
+        let raw: Vec<u8> = [0_u8, 1, 2, 3].repeat(500);
+        let mut payload = Gistit::new(
+            String::new(),
+            "author".to_owned(),
+            None,
+            String::new(),
+            vec![
+                Gistit::new_inner("a.log".to_owned(), "text".to_owned(), 10_000, log.clone()),
+                Gistit::new_binary_inner(
+                    "b.bin".to_owned(),
+                    "binary".to_owned(),
+                    2000,
+                    raw.clone(),
+                ),
+                Gistit::new_inner("c.txt".to_owned(), "text".to_owned(), 3, "foo".to_owned()),
+            ],
+        );
+        let hash = payload.compute_hash();
+        payload.inner.iter_mut().for_each(Inner::compress);
+        // Compression leaves the hash alone
+        assert_eq!(payload.compute_hash(), hash);
+
+        // Too small to be worth it
+        assert!(!payload.inner[2].is_compressed());
+        assert!(payload.inner[0].is_compressed() && payload.inner[1].is_compressed());
+        assert!(payload.inner[0].data.is_empty());
+        assert!(payload.inner[0].contents().len() < log.len() / 5);
+        assert!(payload.check_encoding().is_ok());
+
+        let mut decoded = Gistit::decode(&*payload.encode_to_vec()).unwrap();
+        decoded.decompress().unwrap();
+        assert!(!decoded.is_compressed());
+        assert_eq!(decoded.inner[0].data, log);
+        assert!(decoded.inner[0].raw.is_empty());
+        assert_eq!(decoded.inner[1].raw, raw);
+        assert_eq!(decoded.inner[2].data, "foo");
+
+        // Inflating past the declared size is refused
+        let mut bomb = payload.clone();
+        bomb.inner[0].size = 100;
+        assert_eq!(bomb.compute_hash(), "");
+        assert!(bomb.decompress().is_err());
+
+        payload.encryption = Some(payload::gistit::Encryption::default());
+        assert!(payload.decompress().is_err());
+    }
+
+    #[test]
+    fn test_exchange_manifest() {
+        use exchange::response::Manifest;
+
+        let encoded: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let manifest = Manifest::new("hash".to_owned(), &encoded, 256);
+
+        assert_eq!(manifest.size, 1000);
+        assert_eq!(manifest.chunks.len(), 4);
+        assert!(manifest.is_consistent());
+
+        let digest = manifest.chunks[3].clone();
+        let last = manifest.chunk(&encoded, 3, &digest).unwrap();
+        assert_eq!(last, &encoded[768..]);
+        assert!(manifest.verify(3, last));
+        assert!(!manifest.verify(2, last));
+        assert!(manifest.chunk(&encoded, 2, &digest).is_none());
+        assert!(manifest.chunk(&encoded, 4, &digest).is_none());
+
+        let mut truncated = manifest.clone();
+        truncated.chunks.pop();
+        assert!(!truncated.is_consistent());
+
+        let empty = Manifest::new("hash".to_owned(), &[], 256);
+        assert!(empty.chunks.is_empty());
+        assert!(empty.is_consistent());
+    }
+
+    #[test]
+    fn test_payload_expiry() {
+        let mut payload = Gistit::default();
+        assert_eq!(payload.remaining_millis(1000), None);
+        assert!(!payload.is_expired(1000));
+
+        payload.expires_at = Some("1500".to_owned());
+        assert_eq!(payload.remaining_millis(1000), Some(500));
+        assert!(!payload.is_expired(1000));
+        assert!(payload.is_expired(1500));
+        assert!(payload.is_expired(2000));
+
+        payload.expires_at = Some("soon".to_owned());
+        assert!(payload.is_expired(0));
+    }
+
     #[test]
     fn test_ipc_encode_decode() {
         let instruction = Instruction::request_shutdown();
@@ -259,10 +963,27 @@ mod tests {
         assert_eq!(Instruction::decode(&*bytes).unwrap(), instruction);
     }
 
+    #[test]
+    fn test_plugin_encode_decode() {
+        let request = plugin::Request::publish(Gistit::default(), true);
+        let bytes = request.encode_length_delimited_to_vec();
+        assert_eq!(
+            plugin::Request::decode_length_delimited(&*bytes).unwrap(),
+            request
+        );
+
+        let response = plugin::Response::published(Some("foo".to_owned()), None);
+        let bytes = response.encode_length_delimited_to_vec();
+        assert_eq!(
+            plugin::Response::decode_length_delimited(&*bytes).unwrap(),
+            response
+        );
+    }
+
     #[test]
     fn test_ipc_unwrap_methods() {
         let req1 = Instruction::request_shutdown().expect_request().unwrap();
-        let req2 = Instruction::request_provide(Gistit::default())
+        let req2 = Instruction::request_provide(Gistit::default(), false)
             .expect_request()
             .unwrap();
         let req3 = Instruction::request_status().expect_request().unwrap();
@@ -276,10 +997,28 @@ mod tests {
         let res2 = Instruction::respond_provide(None)
             .expect_response()
             .unwrap();
-        let res3 = Instruction::respond_status(String::new(), 0, 0, 0)
+        let res3 = Instruction::respond_status(String::new(), 0, 0, 0, 0)
             .expect_response()
             .unwrap();
 
+        for request in [
+            Instruction::request_hosting(),
+            Instruction::request_unhost(String::new()),
+            Instruction::request_republish(),
+            Instruction::request_cancel(1),
+        ] {
+            assert!(request.clone().expect_response().is_err());
+            request.expect_request().unwrap();
+        }
+        for response in [
+            Instruction::respond_hosting(Vec::new()),
+            Instruction::respond_unhost(true),
+            Instruction::respond_republish(0),
+        ] {
+            assert!(response.clone().expect_request().is_err());
+            response.expect_response().unwrap();
+        }
+
         assert!(true);
     }
 }
@@ -0,0 +1,84 @@
+//! Payload validations, these mirror the ones performed by the cli before sending
+use std::ops::RangeInclusive;
+
+use gistit_project::var;
+use gistit_proto::Gistit;
+
+use crate::store::now;
+use crate::{Error, Result};
+
+const ALLOWED_AUTHOR_CHAR_LENGTH_RANGE: RangeInclusive<usize> = 3..=50;
+
+const ALLOWED_DESCRIPTION_CHAR_LENGTH_RANGE: RangeInclusive<usize> = 10..=100;
+
+const MIN_FILE_SIZE: usize = 20;
+
+const OAUTH_STATE_MAX_CHAR_LENGTH: usize = 64;
+
+pub fn hash(hash: &str) -> Result<&str> {
+    if hash.len() == var::GISTIT_HASH_LENGTH && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hash)
+    } else {
+        Err(Error::Invalid("invalid gistit hash format"))
+    }
+}
+
+pub fn state(state: &str) -> Result<&str> {
+    if !state.is_empty()
+        && state.len() <= OAUTH_STATE_MAX_CHAR_LENGTH
+        && state.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        Ok(state)
+    } else {
+        Err(Error::Invalid("invalid oauth state"))
+    }
+}
+
+/// Files and their total must fit `max_size` bytes, see `--max-size`
+pub fn gistit(gistit: &Gistit, max_size: usize) -> Result<()> {
+    hash(&gistit.hash)?;
+
+    if !gistit.author.is_empty() && !ALLOWED_AUTHOR_CHAR_LENGTH_RANGE.contains(&gistit.author.len())
+    {
+        return Err(Error::Invalid("invalid author length"));
+    }
+
+    if let Some(ref description) = gistit.description {
+        if !ALLOWED_DESCRIPTION_CHAR_LENGTH_RANGE.contains(&description.len()) {
+            return Err(Error::Invalid("invalid description length"));
+        }
+    }
+
+    if gistit.inner.is_empty() {
+        return Err(Error::Invalid("missing gistit files"));
+    }
+
+    if gistit.check_encoding().is_err() {
+        return Err(Error::Invalid("file contents don't match their encoding"));
+    }
+
+    let total_size: usize = gistit
+        .inner
+        .iter()
+        .map(|inner| inner.contents().len())
+        .sum();
+    // Limits go by what's carried, compressed files may end up smaller than the minimum
+    if total_size > max_size
+        || gistit
+            .inner
+            .iter()
+            .any(|inner| inner.contents().len() > max_size || (inner.size as usize) < MIN_FILE_SIZE)
+    {
+        return Err(Error::Invalid("file size is not allowed"));
+    }
+
+    if gistit.compute_hash() != gistit.hash {
+        return Err(Error::Invalid("gistit hash doesn't match its contents"));
+    }
+
+    if gistit.is_expired(now()) {
+        return Err(Error::Invalid("gistit has already expired"));
+    }
+
+    Ok(())
+}
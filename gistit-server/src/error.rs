@@ -0,0 +1,43 @@
+use hyper::StatusCode;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("i/o error, {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("http error, {0}")]
+    Hyper(#[from] hyper::Error),
+
+    #[error("request error, {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("serialize error, {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("decode error, {0}")]
+    Proto(#[from] gistit_proto::Error),
+
+    #[error("project path error, {0}")]
+    Project(#[from] gistit_project::Error),
+
+    #[error("invalid request, {0}")]
+    Invalid(&'static str),
+
+    #[error("github oauth error, {0}")]
+    OAuth(&'static str),
+
+    #[error("not found")]
+    NotFound,
+}
+
+impl Error {
+    /// The HTTP status this error is reported with
+    #[must_use]
+    pub const fn status(&self) -> StatusCode {
+        match self {
+            Self::Invalid(_) | Self::Proto(_) | Self::Json(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
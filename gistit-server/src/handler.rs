@@ -0,0 +1,210 @@
+//! The request handlers
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+
+use gistit_project::var;
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::{check, Error, Result, State};
+
+const GITHUB_OAUTH_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+
+/// Bodies of get, token and auth requests bigger than this are rejected, they only carry a hash
+/// or OAuth parameters. Gistits posted to load are bounded by the server's max size instead
+const BODY_MAX_SIZE: usize = 10_000;
+
+/// GitHub OAuth application credentials used to exchange authorization codes
+#[derive(Debug, Clone)]
+pub struct GithubApp {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPayload {
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthPayload {
+    code: String,
+    state: String,
+}
+
+/// Dispatches a request to its handler, errors are mapped to status codes
+pub async fn route(
+    state: Arc<State>,
+    req: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    log::debug!("{} {}", req.method(), req.uri().path());
+
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::OPTIONS, _) => Ok(empty(StatusCode::NO_CONTENT)),
+        (&Method::POST, "/load") => load(&state, req).await,
+        (&Method::POST, "/get") => get(&state, req).await,
+        (&Method::POST, "/token") => token(&state, req).await,
+        (&Method::POST, "/auth") => auth(&state, req).await,
+        _ => Err(Error::NotFound),
+    };
+
+    let mut response = result.unwrap_or_else(|err| {
+        log::error!("{}", err);
+        empty(err.status())
+    });
+    allow_cors(&mut response);
+
+    Ok(response)
+}
+
+async fn load(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+    let gistit = Gistit::from_bytes(read_body(req, var::max_payload_size(state.max_size)).await?)?;
+    check::gistit(&gistit, state.max_size)?;
+
+    state.store.put(&gistit).await?;
+    log::info!("Added gistit: {}", gistit.hash);
+
+    // Respond without the file contents
+    let mut response = gistit;
+    for inner in &mut response.inner {
+        inner.data.clear();
+        inner.raw.clear();
+    }
+
+    Ok(protobuf(&response))
+}
+
+async fn get(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+    let body = read_body(req, BODY_MAX_SIZE).await?;
+
+    // The web app can't send raw buffers, so it sends them json encoded
+    let from_web_app = body.first() == Some(&b'[');
+    let bytes = if from_web_app {
+        serde_json::from_slice::<Vec<u8>>(&body)?
+    } else {
+        body
+    };
+    let hash = Gistit::from_bytes(bytes)?.hash;
+
+    let mut gistit = state
+        .store
+        .get(check::hash(&hash)?)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    // Nor can it inflate, it gets the files as they were before being sent
+    if from_web_app && !gistit.is_encrypted() {
+        gistit.decompress()?;
+    }
+
+    Ok(protobuf(&gistit))
+}
+
+async fn token(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+    let TokenPayload { state: oauth_state } =
+        serde_json::from_slice(&read_body(req, BODY_MAX_SIZE).await?)?;
+
+    let token = state
+        .store
+        .take_token(&oauth_state)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(json(&token))
+}
+
+async fn auth(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+    let github = state
+        .github
+        .as_ref()
+        .ok_or(Error::OAuth("github oauth is not configured"))?;
+    let AuthPayload {
+        code,
+        state: oauth_state,
+    } = serde_json::from_slice(&read_body(req, BODY_MAX_SIZE).await?)?;
+    check::state(&oauth_state)?;
+
+    let data: serde_json::Value = reqwest::Client::new()
+        .post(GITHUB_OAUTH_TOKEN_URL)
+        .header("accept", "application/json")
+        .json(&serde_json::json!({
+            "client_id": github.client_id,
+            "client_secret": github.client_secret,
+            "code": code,
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if data.get("error").and_then(serde_json::Value::as_str) == Some("bad_verification_code") {
+        return Err(Error::OAuth("expired verification code"));
+    }
+
+    if data.get("access_token").is_some() {
+        state.store.put_token(&oauth_state, data).await?;
+        Ok(json(&serde_json::json!({ "success": "authenticated" })))
+    } else {
+        Err(Error::Invalid("github didn't return an access token"))
+    }
+}
+
+async fn read_body(req: Request<Body>, max_size: usize) -> Result<Vec<u8>> {
+    let mut body = req.into_body();
+    let mut buf = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        buf.extend_from_slice(&chunk?);
+
+        if buf.len() > max_size {
+            return Err(Error::Invalid("payload too large"));
+        }
+    }
+
+    Ok(buf)
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn protobuf(gistit: &Gistit) -> Response<Body> {
+    let mut response = Response::new(Body::from(gistit.encode_to_vec()));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-protobuf"),
+    );
+    response
+}
+
+fn json(value: &serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn allow_cors(response: &mut Response<Body>) {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET,HEAD,OPTIONS,POST,PUT"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("Origin, Accept, X-Requested-With, Content-Type"),
+    );
+}
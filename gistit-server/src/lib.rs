@@ -0,0 +1,323 @@
+//
+//   ________.__          __  .__  __
+//  /  _____/|__| _______/  |_|__|/  |_
+// /   \  ___|  |/  ___/\   __\  \   __\
+// \    \_\  \  |\___ \  |  | |  ||  |
+//  \______  /__/____  > |__| |__||__|
+//         \/        \/
+//
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(clippy::multiple_crate_versions, clippy::module_name_repetitions)]
+#![cfg_attr(
+    test,
+    allow(
+        unused,
+        clippy::all,
+        clippy::pedantic,
+        clippy::nursery,
+        clippy::dbg_macro,
+        clippy::unwrap_used,
+        clippy::missing_docs_in_private_items,
+    )
+)]
+//! Self-hostable gistit server
+//!
+//! Implements the same protobuf over HTTP contract the cli expects from `GISTIT_SERVER_URL`:
+//! `load`, `get`, `token` and the GitHub OAuth `auth` callback.
+
+mod check;
+mod error;
+mod handler;
+mod store;
+
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+
+pub use error::Error;
+pub use handler::GithubApp;
+pub use store::Store;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How often expired gistits are removed from the store
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Shared server state
+#[derive(Debug)]
+pub struct State {
+    pub store: Store,
+    pub github: Option<GithubApp>,
+    /// Largest gistit taken, in bytes of file contents
+    pub max_size: usize,
+}
+
+/// Serves requests on `listener` until the process exits
+///
+/// # Errors
+///
+/// Fails if the listener can't be used by [`hyper`]
+pub async fn serve(listener: TcpListener, state: State) -> Result<()> {
+    let state = Arc::new(state);
+    tokio::spawn(remove_expired(state.clone()));
+
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handler::route(state.clone(), req))) }
+    });
+
+    log::info!("Listening on {:?}", listener.local_addr()?);
+    hyper::Server::from_tcp(listener)?
+        .serve(make_service)
+        .await?;
+
+    Ok(())
+}
+
+async fn remove_expired(state: Arc<State>) {
+    let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        match state.store.remove_expired().await {
+            Ok(0) => (),
+            Ok(removed) => log::info!("Removed {} expired gistits", removed),
+            Err(err) => log::error!("Failed to remove expired gistits: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gistit_project::var;
+    use gistit_proto::payload::hash;
+    use gistit_proto::prost::Message;
+    use gistit_proto::Gistit;
+    use reqwest::StatusCode;
+
+    async fn spawn_server(tmp: &assert_fs::TempDir) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = State {
+            store: Store::new(tmp.path()).unwrap(),
+            github: None,
+            max_size: var::GISTIT_MAX_SIZE,
+        };
+        tokio::spawn(serve(listener, state));
+
+        format!("http://{}/", addr)
+    }
+
+    fn test_gistit() -> Gistit {
+        let data = "fn main() { println!(\"hello\"); }".to_owned();
+        Gistit::new(
+            hash("Matthew McConaughey", None, [("main.rs", data.as_bytes())]),
+            "Matthew McConaughey".to_owned(),
+            None,
+            "0".to_owned(),
+            vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                data.len() as u32,
+                data,
+            )],
+        )
+    }
+
+    async fn post(url: String, body: Vec<u8>) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(url)
+            .header("content-type", "application/x-protobuf")
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn server_load_and_get() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+        let gistit = test_gistit();
+
+        let response = post(format!("{}load", base), gistit.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let loaded = Gistit::from_bytes(response.bytes().await.unwrap()).unwrap();
+        assert_eq!(loaded.hash, gistit.hash);
+
+        let request = Gistit {
+            hash: gistit.hash.clone(),
+            ..Gistit::default()
+        };
+        let response = post(format!("{}get", base), request.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let fetched = Gistit::from_bytes(response.bytes().await.unwrap()).unwrap();
+        assert_eq!(fetched, gistit);
+    }
+
+    #[tokio::test]
+    async fn server_get_compressed() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+        let data = "tick\n".repeat(20_000);
+        let mut inner = Gistit::new_inner(
+            "clock.log".to_owned(),
+            "text".to_owned(),
+            data.len() as u32,
+            data.clone(),
+        );
+        inner.compress();
+        let gistit = Gistit::new(
+            hash(
+                "Matthew McConaughey",
+                None,
+                [("clock.log", data.as_bytes())],
+            ),
+            "Matthew McConaughey".to_owned(),
+            None,
+            "0".to_owned(),
+            vec![inner],
+        );
+
+        // Over the limit as text, well under once compressed
+        let response = post(format!("{}load", base), gistit.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Gistit {
+            hash: gistit.hash.clone(),
+            ..Gistit::default()
+        }
+        .encode_to_vec();
+        let response = post(format!("{}get", base), request.clone()).await;
+        let fetched = Gistit::from_bytes(response.bytes().await.unwrap()).unwrap();
+        assert_eq!(fetched, gistit);
+
+        // The web app gets the text
+        let response = post(
+            format!("{}get", base),
+            serde_json::to_vec(&request).unwrap(),
+        )
+        .await;
+        let fetched = Gistit::from_bytes(response.bytes().await.unwrap()).unwrap();
+        assert!(!fetched.is_compressed());
+        assert_eq!(fetched.inner[0].data, data);
+    }
+
+    #[tokio::test]
+    async fn server_get_not_found() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+
+        let request = Gistit {
+            hash: "a".repeat(64),
+            ..Gistit::default()
+        };
+        let response = post(format!("{}get", base), request.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn server_load_rejects_invalid_payloads() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+
+        let mut bad_author = test_gistit();
+        bad_author.author = "me".to_owned();
+
+        let mut bad_description = test_gistit();
+        bad_description.description = Some("short".to_owned());
+
+        let mut bad_size = test_gistit();
+        bad_size.inner[0].data = "x".repeat(60_000);
+
+        let mut bad_hash = test_gistit();
+        bad_hash.hash = "../../etc/passwd".to_owned();
+
+        let mut bad_encoding = test_gistit();
+        bad_encoding.inner[0].raw = vec![0xff; 32];
+
+        let mut mismatched_hash = test_gistit();
+        mismatched_hash.inner[0].data = "fn main() { std::process::exit(1); }".to_owned();
+
+        for gistit in [
+            bad_author,
+            bad_description,
+            bad_size,
+            bad_hash,
+            bad_encoding,
+            mismatched_hash,
+        ] {
+            let response = post(format!("{}load", base), gistit.encode_to_vec()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = post(format!("{}load", base), b"garbage".to_vec()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn server_keeps_stored_gistits() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let store = Store::new(tmp.path()).unwrap();
+        let gistit = test_gistit();
+        store.put(&gistit).await.unwrap();
+
+        let mut replacement = test_gistit();
+        replacement.expires_at = Some(u64::MAX.to_string());
+        store.put(&replacement).await.unwrap();
+        assert_eq!(store.get(&gistit.hash).await.unwrap(), Some(gistit));
+
+        // Only the gistit is left behind
+        let entries = std::fs::read_dir(tmp.path().join("gistits")).unwrap();
+        assert_eq!(entries.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn server_expired_gistits() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+
+        let mut expired = test_gistit();
+        expired.expires_at = Some("0".to_owned());
+        let response = post(format!("{}load", base), expired.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Expires while stored
+        let store = Store::new(tmp.path()).unwrap();
+        store.put(&expired).await.unwrap();
+        let request = Gistit {
+            hash: expired.hash.clone(),
+            ..Gistit::default()
+        };
+        let response = post(format!("{}get", base), request.encode_to_vec()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!tmp.path().join("gistits").join(&expired.hash).exists());
+
+        store.put(&expired).await.unwrap();
+        let mut alive = test_gistit();
+        alive.hash = "b".repeat(64);
+        alive.expires_at = Some(u64::MAX.to_string());
+        store.put(&alive).await.unwrap();
+
+        assert_eq!(store.remove_expired().await.unwrap(), 1);
+        assert!(store.get(&alive.hash).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn server_token_not_found() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let base = spawn_server(&tmp).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}token", base))
+            .json(&serde_json::json!({ "state": "abcdefgh" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,95 @@
+//
+//   ________.__          __  .__  __
+//  /  _____/|__| _______/  |_|__|/  |_
+// /   \  ___|  |/  ___/\   __\  \   __\
+// \    \_\  \  |\___ \  |  | |  ||  |
+//  \______  /__/____  > |__| |__||__|
+//         \/        \/
+//
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(clippy::multiple_crate_versions)]
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use gistit_project::var;
+use gistit_server::{serve, GithubApp, Result, State, Store};
+
+const GITHUB_CLIENT_ID_VAR: &str = "GISTIT_GITHUB_CLIENT_ID";
+
+const GITHUB_CLIENT_SECRET_VAR: &str = "GISTIT_GITHUB_CLIENT_SECRET";
+
+/// Self-hostable gistit server
+#[derive(Parser, PartialEq, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(long)]
+    /// Override data directory
+    data_path: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Address to listen for connections
+    host: Option<Ipv4Addr>,
+
+    #[clap(long)]
+    /// Port to listen for connections
+    port: Option<u16>,
+
+    #[clap(long, default_value_t = var::GISTIT_MAX_SIZE)]
+    /// Largest gistit taken, in bytes of file contents
+    max_size: usize,
+}
+
+async fn run() -> Result<()> {
+    let Args {
+        data_path,
+        host,
+        port,
+        max_size,
+    } = Args::parse();
+
+    let data_path = match data_path {
+        Some(path) => path,
+        None => gistit_project::path::data()?.join("server"),
+    };
+    let addr = SocketAddr::from((
+        host.unwrap_or(Ipv4Addr::LOCALHOST),
+        port.unwrap_or(4000_u16),
+    ));
+
+    let github = if let (Ok(client_id), Ok(client_secret)) = (
+        std::env::var(GITHUB_CLIENT_ID_VAR),
+        std::env::var(GITHUB_CLIENT_SECRET_VAR),
+    ) {
+        Some(GithubApp {
+            client_id,
+            client_secret,
+        })
+    } else {
+        log::warn!("GitHub OAuth credentials not set, `--github` won't be available");
+        None
+    };
+
+    log::debug!("Storing gistits at {:?}", data_path);
+    let state = State {
+        store: Store::new(&data_path)?,
+        github,
+        max_size,
+    };
+
+    serve(TcpListener::bind(addr)?, state).await
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .write_style(env_logger::WriteStyle::Always)
+        .init();
+
+    if let Err(err) = run().await {
+        log::error!("{:?}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,184 @@
+//! The storage module
+//!
+//! Gistits are kept as protobuf encoded files named after their hash. GitHub OAuth tokens are kept
+//! as json files named after the OAuth state until the cli claims them.
+//!
+//! Expired gistits are dropped when requested and by a periodic [`Store::remove_expired`] sweep.
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::{check, Result};
+
+/// Unclaimed tokens are discarded after 10 minutes
+const TOKEN_LIFETIME_MILLIS: u128 = 10 * 60 * 1000;
+
+/// Tells apart the partial files of concurrent [`Store::put`]s
+static PARTIAL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub struct Store {
+    gistits: PathBuf,
+    tokens: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredToken {
+    token: serde_json::Value,
+    expire_at: u128,
+}
+
+impl Store {
+    /// Opens a store at `base`, creating the needed directories
+    ///
+    /// # Errors
+    ///
+    /// Fails if can't create the directories
+    pub fn new(base: &Path) -> Result<Self> {
+        let gistits = base.join("gistits");
+        let tokens = base.join("tokens");
+        std::fs::create_dir_all(&gistits)?;
+        std::fs::create_dir_all(&tokens)?;
+
+        Ok(Self { gistits, tokens })
+    }
+
+    /// Stores a gistit, keeping the one already stored under the same hash unless expired
+    ///
+    /// The payload is written aside and then linked in place, so a gistit is never seen half
+    /// written nor replaced.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`std::io::Error`] or if the hash format is invalid
+    pub async fn put(&self, gistit: &Gistit) -> Result<()> {
+        let hash = check::hash(&gistit.hash)?;
+        let path = self.gistits.join(hash);
+        let partial = self.gistits.join(format!(
+            "{}.{}-{}.partial",
+            hash,
+            std::process::id(),
+            PARTIAL_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&partial, gistit.encode_to_vec()).await?;
+
+        let mut linked = fs::hard_link(&partial, &path).await;
+        if matches!(linked, Err(ref err) if err.kind() == ErrorKind::AlreadyExists)
+            && self.get(hash).await?.is_none()
+        {
+            // The stored one had expired and is gone now
+            linked = fs::hard_link(&partial, &path).await;
+        }
+        fs::remove_file(&partial).await?;
+
+        match linked {
+            Err(err) if err.kind() != ErrorKind::AlreadyExists => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Retrieves a gistit by hash, removing it if expired
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`std::io::Error`] or if the stored payload is corrupted
+    pub async fn get(&self, hash: &str) -> Result<Option<Gistit>> {
+        let path = self.gistits.join(check::hash(hash)?);
+
+        let gistit = match fs::read(&path).await {
+            Ok(bytes) => Gistit::from_bytes(bytes)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        if gistit.is_expired(now()) {
+            // Another request or the sweep may have removed it first
+            match fs::remove_file(&path).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+            Ok(None)
+        } else {
+            Ok(Some(gistit))
+        }
+    }
+
+    /// Removes every expired gistit, returning how many were removed
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`std::io::Error`]
+    pub async fn remove_expired(&self) -> Result<usize> {
+        let mut entries = fs::read_dir(&self.gistits).await?;
+        let mut removed = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let hash = entry.file_name().to_string_lossy().to_string();
+
+            if check::hash(&hash).is_err() {
+                continue;
+            }
+
+            // A corrupted gistit shouldn't stop the sweep
+            match self.get(&hash).await {
+                Ok(None) => removed += 1,
+                Ok(Some(_)) => (),
+                Err(err) => log::warn!("Failed to check expiry of {}: {}", hash, err),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Stores a token waiting to be claimed with `state`
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`std::io::Error`]
+    pub async fn put_token(&self, state: &str, token: serde_json::Value) -> Result<()> {
+        let stored = StoredToken {
+            token,
+            expire_at: now() + TOKEN_LIFETIME_MILLIS,
+        };
+        let path = self.tokens.join(check::state(state)?);
+        fs::write(path, serde_json::to_vec(&stored)?).await?;
+        Ok(())
+    }
+
+    /// Removes and returns the token stored under `state`, if not expired
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`std::io::Error`]
+    pub async fn take_token(&self, state: &str) -> Result<Option<serde_json::Value>> {
+        let path = self.tokens.join(check::state(state)?);
+
+        let bytes = match fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        fs::remove_file(&path).await?;
+
+        let stored: StoredToken = serde_json::from_slice(&bytes)?;
+        if stored.expire_at < now() {
+            Ok(None)
+        } else {
+            Ok(Some(stored.token))
+        }
+    }
+}
+
+pub fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Check your system time")
+        .as_millis()
+}
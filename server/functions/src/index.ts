@@ -35,8 +35,22 @@ export type GistitPayload = {
     data: string;
     size: number;
   }[];
+  encryption?: {
+    scheme: number;
+    salt?: Uint8Array;
+    ephemeralKey?: Uint8Array;
+    recipients?: { peerId: string; wrappedKey: Uint8Array }[];
+  };
+  expiresAt?: string;
+  signature?: {
+    publicKey: Uint8Array;
+    signature: Uint8Array;
+  };
 };
 
+export const isExpired = (expiresAt?: string) =>
+  expiresAt !== undefined && !(Number(expiresAt) > Date.now());
+
 export const load = functions.https.onRequest(async (req, res) => {
   const proto = await protobuf.load("payload.proto");
   const Gistit = proto.lookupType("gistit.payload.Gistit");
@@ -48,7 +62,10 @@ export const load = functions.https.onRequest(async (req, res) => {
       author,
       description,
       timestamp,
-      inner: [{ name, lang, size, data }],
+      inner,
+      encryption,
+      expiresAt,
+      signature,
     } = payload as unknown as GistitPayload;
     functions.logger.log(payload);
 
@@ -71,9 +88,14 @@ export const load = functions.https.onRequest(async (req, res) => {
       throw Error("Invalid description length");
     }
 
+    if (!inner?.length) throw Error("Missing gistit files");
+
+    if (isExpired(expiresAt)) throw Error("Gistit has already expired");
+
+    const totalSize = inner.reduce((acc, { data }) => acc + data.length, 0);
     if (
-      data.length > GISTIT_FILE_MAX_SIZE ||
-      data.length < GISTIT_FILE_MIN_SIZE
+      totalSize > GISTIT_FILE_MAX_SIZE ||
+      inner.some(({ data }) => data.length < GISTIT_FILE_MIN_SIZE)
     ) {
       throw Error("File size is not allowed");
     }
@@ -85,7 +107,36 @@ export const load = functions.https.onRequest(async (req, res) => {
         author,
         description,
         timestamp: timestamp.toString(),
-        inner: [{ name, lang, data, size }],
+        inner: inner.map(({ name, lang, data, size }) => ({
+          name,
+          lang,
+          data,
+          size,
+        })),
+        ...(encryption
+          ? {
+              encryption: {
+                scheme: encryption.scheme ?? 0,
+                salt: Buffer.from(encryption.salt ?? []),
+                ephemeralKey: Buffer.from(encryption.ephemeralKey ?? []),
+                recipients: (encryption.recipients ?? []).map(
+                  ({ peerId, wrappedKey }) => ({
+                    peerId,
+                    wrappedKey: Buffer.from(wrappedKey ?? []),
+                  })
+                ),
+              },
+            }
+          : {}),
+        ...(expiresAt ? { expiresAt } : {}),
+        ...(signature
+          ? {
+              signature: {
+                publicKey: Buffer.from(signature.publicKey ?? []),
+                signature: Buffer.from(signature.signature ?? []),
+              },
+            }
+          : {}),
       });
 
     functions.logger.info("added gistit: ", hash);
@@ -94,7 +145,10 @@ export const load = functions.https.onRequest(async (req, res) => {
       author,
       description,
       timestamp,
-      inner: [{ name, lang, data: "", size }],
+      inner: inner.map(({ name, lang, size }) => ({ name, lang, data: "", size })),
+      encryption,
+      expiresAt,
+      signature,
     }).finish();
 
     res.send(response);
@@ -144,6 +198,13 @@ export const get = functions.https.onRequest(async (req, res) => {
     }
 
     const gistit = gistitRef.data();
+
+    if (isExpired(gistit?.expiresAt)) {
+      await db.doc(`gistits/${hash}`).delete();
+      await db.doc(`reserved/${hash}`).delete();
+      res.status(404).end();
+      return;
+    }
     console.log(gistit);
     const response = Gistit.encode({ ...gistit, hash }).finish();
     res.status(200).send(response);
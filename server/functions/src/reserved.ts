@@ -9,30 +9,34 @@ interface onChangeContext extends functions.EventContext {
   };
 }
 
+// Gistits with an earlier `expiresAt` are removed sooner
+const removeAt = (expiresAt?: string) =>
+  Math.min(Date.now() + 300 * 60 * 100, Number(expiresAt ?? Infinity));
+
 export const createReservedData = functions.firestore
   .document("gistits/{hash}")
-  .onCreate(async (_, context) => {
+  .onCreate(async (snapshot, context) => {
     const hash = (context as onChangeContext).params.hash;
 
     return db
       .collection("reserved")
       .doc(hash)
       .set({
-        removeAt: Date.now() + 300 * 60 * 100,
+        removeAt: removeAt(snapshot.data().expiresAt),
         reuploaded: 0,
       });
   });
 
 export const updateReservedData = functions.firestore
   .document("gistits/{hash}")
-  .onUpdate(async (_, context) => {
+  .onUpdate(async (change, context) => {
     const hash = (context as onChangeContext).params.hash;
 
     return db
       .collection("reserved")
       .doc(hash)
       .update({
-        removeAt: Date.now() + 300 * 60 * 100,
+        removeAt: removeAt(change.after.data().expiresAt),
         reuploaded: admin.firestore.FieldValue.increment(1),
       });
   });